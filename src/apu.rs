@@ -0,0 +1,466 @@
+use std::io::Write;
+
+use byteorder::{ByteOrder, WriteBytesExt, LE};
+use serde::Serialize;
+
+use crate::music::*;
+
+const CPU_CLOCK: f64 = 1_789_773.0;
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const NOISE_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
+pub enum ApuChannel {
+    Square1,
+    Square2,
+    Triangle,
+    Noise,
+}
+
+impl ApuChannel {
+    pub const ALL: [Self; 4] = [Self::Square1, Self::Square2, Self::Triangle, Self::Noise];
+
+    fn index(self) -> usize {
+        match self {
+            Self::Square1 => 0,
+            Self::Square2 => 1,
+            Self::Triangle => 2,
+            Self::Noise => 3,
+        }
+    }
+
+    pub(crate) fn reg_base(self) -> u16 {
+        0x4000 + 4 * self.index() as u16
+    }
+}
+
+/// フレーム番号付きの APU レジスタ書き込み。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
+pub struct ApuWrite {
+    pub frame: u32,
+    pub addr: u16,
+    pub value: u8,
+}
+
+/// レジスタ値から読み取れるチャンネルの状態。
+///
+/// volume は固定音量の場合は音量、エンベロープの場合はその周期。
+/// period は矩形波/三角波ではタイマー周期、ノイズでは周期インデックス。
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, Serialize)]
+pub struct ChannelState {
+    pub enabled: bool,
+    pub volume: u8,
+    pub constant_volume: bool,
+    pub duty: u8,
+    pub period: u16,
+    pub noise_short: bool,
+}
+
+/// APU のレジスタをミラーし、チャンネルごとの状態を取り出す。
+#[derive(Clone, Debug, Default)]
+pub struct ApuRegisters {
+    regs: [u8; 0x18],
+}
+
+impl ApuRegisters {
+    pub fn write(&mut self, addr: u16, value: u8) {
+        if (0x4000..=0x4017).contains(&addr) {
+            self.regs[usize::from(addr - 0x4000)] = value;
+        }
+    }
+
+    pub fn channel_state(&self, ch: ApuChannel) -> ChannelState {
+        let base = usize::from(ch.reg_base() - 0x4000);
+        let r = &self.regs[base..base + 4];
+        let enabled = (self.regs[0x15] & (1 << ch.index())) != 0;
+
+        match ch {
+            ApuChannel::Square1 | ApuChannel::Square2 => ChannelState {
+                enabled,
+                volume: r[0] & 0x0F,
+                constant_volume: (r[0] & 0x10) != 0,
+                duty: r[0] >> 6,
+                period: u16::from(r[2]) | (u16::from(r[3] & 7) << 8),
+                noise_short: false,
+            },
+            ApuChannel::Triangle => ChannelState {
+                enabled,
+                volume: if (r[0] & 0x7F) != 0 { 15 } else { 0 },
+                constant_volume: true,
+                duty: 0,
+                period: u16::from(r[2]) | (u16::from(r[3] & 7) << 8),
+                noise_short: false,
+            },
+            ApuChannel::Noise => ChannelState {
+                enabled,
+                volume: r[0] & 0x0F,
+                constant_volume: (r[0] & 0x10) != 0,
+                duty: 0,
+                period: u16::from(r[2] & 0x0F),
+                noise_short: (r[2] & 0x80) != 0,
+            },
+        }
+    }
+}
+
+/// 音色確認用の簡易 APU シンセサイザ。
+///
+/// エンベロープ、長さカウンタ、線形カウンタは再現するが、スイープと DMC は無視する。
+/// レジスタ書き込みはフレーム先頭でまとめて反映する。
+#[derive(Clone, Debug)]
+pub struct ApuSynth {
+    sample_rate: u32,
+    regs: ApuRegisters,
+    pulses: [PulseVoice; 2],
+    tri: TriangleVoice,
+    noise: NoiseVoice,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Envelope {
+    start: bool,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn clock(&mut self, reg: u8) {
+        let period = reg & 0x0F;
+        let looping = (reg & 0x20) != 0;
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = period;
+        } else if self.divider == 0 {
+            self.divider = period;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn volume(&self, reg: u8) -> u8 {
+        if (reg & 0x10) != 0 {
+            reg & 0x0F
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct PulseVoice {
+    phase: f64,
+    length: u8,
+    envelope: Envelope,
+}
+
+#[derive(Clone, Debug, Default)]
+struct TriangleVoice {
+    phase: f64,
+    length: u8,
+    linear: u8,
+    linear_reload: bool,
+}
+
+#[derive(Clone, Debug)]
+struct NoiseVoice {
+    phase: f64,
+    lfsr: u16,
+    length: u8,
+    envelope: Envelope,
+}
+
+impl Default for NoiseVoice {
+    fn default() -> Self {
+        Self {
+            phase: 0.0,
+            lfsr: 1,
+            length: 0,
+            envelope: Envelope::default(),
+        }
+    }
+}
+
+impl ApuSynth {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            regs: ApuRegisters::default(),
+            pulses: Default::default(),
+            tri: TriangleVoice::default(),
+            noise: NoiseVoice::default(),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        self.regs.write(addr, value);
+
+        let length = LENGTH_TABLE[usize::from(value >> 3)];
+        match addr {
+            0x4003 | 0x4007 => {
+                let pulse = &mut self.pulses[usize::from((addr - 0x4003) / 4)];
+                pulse.length = length;
+                pulse.envelope.start = true;
+                pulse.phase = 0.0;
+            }
+            0x400B => {
+                self.tri.length = length;
+                self.tri.linear_reload = true;
+            }
+            0x400F => {
+                self.noise.length = length;
+                self.noise.envelope.start = true;
+            }
+            0x4015 => {
+                if (value & 1) == 0 {
+                    self.pulses[0].length = 0;
+                }
+                if (value & 2) == 0 {
+                    self.pulses[1].length = 0;
+                }
+                if (value & 4) == 0 {
+                    self.tri.length = 0;
+                }
+                if (value & 8) == 0 {
+                    self.noise.length = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 1 フレーム (1/60 秒) 分のサンプルを生成する。
+    pub fn render_frame(&mut self, out: &mut Vec<f32>) {
+        let n = self.sample_rate / 60;
+        for i in 0..n {
+            // 4 ステップのフレームシーケンサ
+            let step = i / (n / 4);
+            if i % (n / 4) == 0 && step < 4 {
+                self.clock_quarter_frame();
+                if step % 2 == 1 {
+                    self.clock_half_frame();
+                }
+            }
+            out.push(self.sample());
+        }
+    }
+
+    fn reg(&self, addr: u16) -> u8 {
+        self.regs.regs[usize::from(addr - 0x4000)]
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        let r0 = self.reg(0x4000);
+        let r4 = self.reg(0x4004);
+        let rc = self.reg(0x400C);
+        self.pulses[0].envelope.clock(r0);
+        self.pulses[1].envelope.clock(r4);
+        self.noise.envelope.clock(rc);
+
+        let r8 = self.reg(0x4008);
+        if self.tri.linear_reload {
+            self.tri.linear = r8 & 0x7F;
+        } else if self.tri.linear > 0 {
+            self.tri.linear -= 1;
+        }
+        if (r8 & 0x80) == 0 {
+            self.tri.linear_reload = false;
+        }
+    }
+
+    fn clock_half_frame(&mut self) {
+        let halts = [
+            self.reg(0x4000) & 0x20,
+            self.reg(0x4004) & 0x20,
+            self.reg(0x4008) & 0x80,
+            self.reg(0x400C) & 0x20,
+        ];
+        let [pulse1, pulse2] = &mut self.pulses;
+        let lengths = [
+            &mut pulse1.length,
+            &mut pulse2.length,
+            &mut self.tri.length,
+            &mut self.noise.length,
+        ];
+        for (length, halt) in itertools::zip(lengths, &halts) {
+            if *halt == 0 && *length > 0 {
+                *length -= 1;
+            }
+        }
+    }
+
+    fn sample(&mut self) -> f32 {
+        let dt = 1.0 / f64::from(self.sample_rate);
+
+        let mut pulse_out = 0.0;
+        for i in 0..2 {
+            let base = 0x4000 + 4 * i as u16;
+            let r0 = self.reg(base);
+            let period = u16::from(self.reg(base + 2)) | (u16::from(self.reg(base + 3) & 7) << 8);
+            let pulse = &mut self.pulses[i];
+            if pulse.length == 0 || period < 8 {
+                continue;
+            }
+            let freq = CPU_CLOCK / (16.0 * f64::from(period + 1));
+            pulse.phase = (pulse.phase + freq * dt).fract();
+            let step = (pulse.phase * 8.0) as usize;
+            let duty = usize::from(r0 >> 6);
+            pulse_out += f64::from(DUTY_TABLE[duty][step] * pulse.envelope.volume(r0));
+        }
+
+        let tri_period = u16::from(self.reg(0x400A)) | (u16::from(self.reg(0x400B) & 7) << 8);
+        if self.tri.length > 0 && self.tri.linear > 0 && tri_period >= 2 {
+            let freq = CPU_CLOCK / (32.0 * f64::from(tri_period + 1));
+            self.tri.phase = (self.tri.phase + freq * dt).fract();
+        }
+        let tri_step = (self.tri.phase * 32.0) as u8;
+        let tri_out = if tri_step < 16 {
+            15 - tri_step
+        } else {
+            tri_step - 16
+        };
+
+        let rc = self.reg(0x400C);
+        let re = self.reg(0x400E);
+        let freq = CPU_CLOCK / f64::from(NOISE_PERIOD_TABLE[usize::from(re & 0x0F)]);
+        self.noise.phase += freq * dt;
+        while self.noise.phase >= 1.0 {
+            self.noise.phase -= 1.0;
+            let tap = if (re & 0x80) != 0 { 6 } else { 1 };
+            let feedback = (self.noise.lfsr ^ (self.noise.lfsr >> tap)) & 1;
+            self.noise.lfsr = (self.noise.lfsr >> 1) | (feedback << 14);
+        }
+        let noise_out = if self.noise.length > 0 && (self.noise.lfsr & 1) == 0 {
+            self.noise.envelope.volume(rc)
+        } else {
+            0
+        };
+
+        // 線形近似のミキサー
+        let out =
+            0.00752 * pulse_out + 0.00851 * f64::from(tri_out) + 0.00494 * f64::from(noise_out);
+        out as f32
+    }
+}
+
+/// フレームごとのレジスタ書き込みを合成して 16bit モノラル WAV を出力する。
+pub fn write_apu_wav<W: Write>(
+    mut wtr: W,
+    writes: &[ApuWrite],
+    n_frame: u32,
+    sample_rate: u32,
+) -> eyre::Result<()> {
+    let mut synth = ApuSynth::new(sample_rate);
+    let mut samples = Vec::new();
+
+    let mut it = writes.iter().peekable();
+    for frame in 0..n_frame {
+        while let Some(w) = it.peek().filter(|w| w.frame == frame) {
+            synth.write(w.addr, w.value);
+            it.next();
+        }
+        synth.render_frame(&mut samples);
+    }
+
+    let data_len = 2 * samples.len() as u32;
+    wtr.write_all(b"RIFF")?;
+    wtr.write_u32::<LE>(36 + data_len)?;
+    wtr.write_all(b"WAVEfmt ")?;
+    wtr.write_u32::<LE>(16)?;
+    wtr.write_u16::<LE>(1)?; // PCM
+    wtr.write_u16::<LE>(1)?; // モノラル
+    wtr.write_u32::<LE>(sample_rate)?;
+    wtr.write_u32::<LE>(2 * sample_rate)?;
+    wtr.write_u16::<LE>(2)?;
+    wtr.write_u16::<LE>(16)?;
+    wtr.write_all(b"data")?;
+    wtr.write_u32::<LE>(data_len)?;
+    for sample in samples {
+        let v = (sample.clamp(-1.0, 1.0) * 32767.0) as i16;
+        wtr.write_i16::<LE>(v)?;
+    }
+
+    Ok(())
+}
+
+/// フレームごとのレジスタ書き込みを VGM 1.61 形式 (NES APU) で出力する。
+///
+/// loop_frame を指定すると、そのフレームの先頭から末尾までをループ区間とする。
+pub fn write_apu_vgm<W: Write>(
+    mut wtr: W,
+    writes: &[ApuWrite],
+    n_frame: u32,
+    loop_frame: Option<u32>,
+) -> eyre::Result<()> {
+    const HEADER_LEN: usize = 0xC0;
+    const SAMPLES_PER_FRAME: u32 = 735; // 44100 / 60
+
+    let mut data = Vec::new();
+    let mut loop_offset = None;
+
+    let mut it = writes.iter().peekable();
+    for frame in 0..n_frame {
+        if loop_frame == Some(frame) {
+            loop_offset = Some(HEADER_LEN + data.len());
+        }
+        while let Some(w) = it.peek().filter(|w| w.frame == frame) {
+            data.extend_from_slice(&[0xB4, (w.addr - 0x4000) as u8, w.value]);
+            it.next();
+        }
+        data.push(0x62); // 1/60 秒待つ
+    }
+    data.push(0x66);
+
+    let total_samples = SAMPLES_PER_FRAME * n_frame;
+
+    let mut header = vec![0; HEADER_LEN];
+    header[0..4].copy_from_slice(b"Vgm ");
+    LE::write_u32(&mut header[0x04..], (HEADER_LEN + data.len() - 4) as u32);
+    LE::write_u32(&mut header[0x08..], 0x161);
+    LE::write_u32(&mut header[0x18..], total_samples);
+    if let (Some(offset), Some(loop_frame)) = (loop_offset, loop_frame) {
+        LE::write_u32(&mut header[0x1C..], (offset - 0x1C) as u32);
+        LE::write_u32(
+            &mut header[0x20..],
+            SAMPLES_PER_FRAME * (n_frame - loop_frame),
+        );
+    }
+    LE::write_u32(&mut header[0x24..], 60);
+    LE::write_u32(&mut header[0x34..], (HEADER_LEN - 0x34) as u32);
+    LE::write_u32(&mut header[0x84..], CPU_CLOCK as u32);
+
+    wtr.write_all(&header)?;
+    wtr.write_all(&data)?;
+
+    Ok(())
+}
+
+/// タイマー周期から最も近い (octave, note) を返す。
+pub fn period_to_tone(period: u16, tri: bool) -> (u8, u8) {
+    let div = if tri { 32.0 } else { 16.0 };
+    let freq = CPU_CLOCK / (div * f64::from(period + 1));
+    // O0C を基準とした半音数
+    let semitone = (12.0 * (freq / tone_frequency(0, 0)).log2())
+        .round()
+        .max(0.0) as u32;
+    ((semitone / 12) as u8, (semitone % 12) as u8)
+}
@@ -0,0 +1,237 @@
+// メタスプライトの Aseprite (.aseprite) ファイル出力。
+//
+// インデックスカラーモードで、パレットはスプライトパレットセット 4x4 色 (インデックス 0 が透明色)。
+// 1 フレームが 1 メタスプライトで、8x8 の部品ごとに 1 レイヤーとする (反転は適用済み)。
+// アニメーションは、フレーム (メタスプライト ID) が連続しているものだけをタグにする。
+//
+// フォーマット: https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md
+
+use std::io::Write;
+
+use byteorder::{WriteBytesExt, LE};
+
+use crate::game::*;
+use crate::meta_sprite_animation::*;
+use crate::ppu::*;
+
+const MAGIC_FILE: u16 = 0xA5E0;
+const MAGIC_FRAME: u16 = 0xF1FA;
+
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_TAGS: u16 = 0x2018;
+const CHUNK_PALETTE: u16 = 0x2019;
+
+/// タグのないフレームの表示時間 (ms)。
+const FRAME_DURATION_DEFAULT: u16 = 100;
+
+const LAYER_NAMES: [&str; 4] = ["top-left", "bottom-left", "top-right", "bottom-right"];
+
+/// Aseprite のタグ。from, to はフレーム番号 (両端含む)。
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct AsepriteTag {
+    pub name: String,
+    pub from: u16,
+    pub to: u16,
+}
+
+/// 全メタスプライトを 1 ファイルにする。
+/// フレーム 0..=META_SPRITE_MAX はメタスプライト ID 順に 1 枚ずつ並べる。
+/// その後ろに animations ごとに専用のフレームを表示時間付きで追加し、タグを付ける。
+/// tag_name はアニメーションからタグ名を作る。
+pub fn write_meta_sprite_aseprite<W: Write>(
+    mut wtr: W,
+    game: &Game,
+    second_round: bool,
+    animations: &[MetaSpriteAnimation],
+    tag_name: impl Fn(&MetaSpriteAnimation) -> String,
+) -> eyre::Result<()> {
+    // (メタスプライト ID, 表示時間 [ms])
+    let mut sequence: Vec<(u8, u16)> = (0..=META_SPRITE_MAX)
+        .map(|id| (id, FRAME_DURATION_DEFAULT))
+        .collect();
+    let mut tags = Vec::new();
+    for anim in animations.iter().filter(|anim| !anim.frames.is_empty()) {
+        let from = sequence.len() as u16;
+        sequence.extend(anim.frames.iter().map(|frame| {
+            (
+                frame.sprite_id,
+                (1000 * u32::from(frame.duration) / 60) as u16,
+            )
+        }));
+        tags.push(AsepriteTag {
+            name: tag_name(anim),
+            from,
+            to: sequence.len() as u16 - 1,
+        });
+    }
+
+    let mut frames = Vec::with_capacity(sequence.len());
+    for (i, &(id, duration)) in sequence.iter().enumerate() {
+        let mut chunks = Vec::new();
+
+        if i == 0 {
+            chunks.push(palette_chunk(game.sprite_palette_set()));
+            for name in &LAYER_NAMES {
+                chunks.push(layer_chunk(name));
+            }
+            chunks.push(tags_chunk(&tags));
+        }
+
+        for (layer, &((x, y), tile, attr)) in
+            itertools::enumerate(&game.meta_sprite_parts(id, second_round))
+        {
+            chunks.push(cel_chunk(layer as u16, x as i16, y as i16, tile, attr));
+        }
+
+        frames.push(frame_bytes(&chunks, duration));
+    }
+
+    let body: Vec<u8> = frames.concat();
+    write_header(&mut wtr, 128 + body.len() as u32, sequence.len() as u16)?;
+    wtr.write_all(&body)?;
+
+    Ok(())
+}
+
+fn write_header<W: Write>(mut wtr: W, file_size: u32, n_frame: u16) -> eyre::Result<()> {
+    wtr.write_u32::<LE>(file_size)?;
+    wtr.write_u16::<LE>(MAGIC_FILE)?;
+    wtr.write_u16::<LE>(n_frame)?;
+    wtr.write_u16::<LE>(16)?; // 幅
+    wtr.write_u16::<LE>(16)?; // 高さ
+    wtr.write_u16::<LE>(8)?; // インデックスカラー
+    wtr.write_u32::<LE>(1)?; // レイヤーの不透明度が有効
+    wtr.write_u16::<LE>(FRAME_DURATION_DEFAULT)?;
+    wtr.write_u32::<LE>(0)?;
+    wtr.write_u32::<LE>(0)?;
+    wtr.write_u8(0)?; // 透明色のインデックス
+    wtr.write_all(&[0; 3])?;
+    wtr.write_u16::<LE>(16)?; // 色数
+    wtr.write_u8(1)?; // ピクセル幅
+    wtr.write_u8(1)?; // ピクセル高さ
+    wtr.write_i16::<LE>(0)?; // グリッド X
+    wtr.write_i16::<LE>(0)?; // グリッド Y
+    wtr.write_u16::<LE>(8)?; // グリッド幅
+    wtr.write_u16::<LE>(8)?; // グリッド高さ
+    wtr.write_all(&[0; 84])?;
+
+    Ok(())
+}
+
+fn frame_bytes(chunks: &[Vec<u8>], duration: u16) -> Vec<u8> {
+    let body: Vec<u8> = chunks.concat();
+
+    let mut res = Vec::with_capacity(16 + body.len());
+    res.write_u32::<LE>(16 + body.len() as u32).unwrap();
+    res.write_u16::<LE>(MAGIC_FRAME).unwrap();
+    res.write_u16::<LE>(chunks.len().min(0xFFFF) as u16)
+        .unwrap();
+    res.write_u16::<LE>(duration).unwrap();
+    res.extend(&[0; 2]);
+    res.write_u32::<LE>(chunks.len() as u32).unwrap();
+    res.extend(body);
+
+    res
+}
+
+fn chunk(ty: u16, data: Vec<u8>) -> Vec<u8> {
+    let mut res = Vec::with_capacity(6 + data.len());
+    res.write_u32::<LE>(6 + data.len() as u32).unwrap();
+    res.write_u16::<LE>(ty).unwrap();
+    res.extend(data);
+
+    res
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &str) {
+    buf.write_u16::<LE>(s.len() as u16).unwrap();
+    buf.extend(s.as_bytes());
+}
+
+fn palette_chunk(palette_set: &[Palette]) -> Vec<u8> {
+    let n = 4 * palette_set.len() as u32;
+
+    let mut data = Vec::new();
+    data.write_u32::<LE>(n).unwrap();
+    data.write_u32::<LE>(0).unwrap();
+    data.write_u32::<LE>(n - 1).unwrap();
+    data.extend(&[0; 8]);
+    for (i, plt) in itertools::enumerate(palette_set) {
+        for (j, &color_id) in itertools::enumerate(&plt.color_ids()) {
+            let rgba = nes_color(color_id);
+            let alpha = if i == 0 && j == 0 { 0 } else { 0xFF };
+            data.write_u16::<LE>(0).unwrap();
+            data.extend(&[rgba[0], rgba[1], rgba[2], alpha]);
+        }
+    }
+
+    chunk(CHUNK_PALETTE, data)
+}
+
+fn layer_chunk(name: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.write_u16::<LE>(1 | 2).unwrap(); // 表示, 編集可
+    data.write_u16::<LE>(0).unwrap(); // 通常レイヤー
+    data.write_u16::<LE>(0).unwrap(); // 階層
+    data.write_u16::<LE>(0).unwrap();
+    data.write_u16::<LE>(0).unwrap();
+    data.write_u16::<LE>(0).unwrap(); // 通常合成
+    data.write_u8(0xFF).unwrap(); // 不透明度
+    data.extend(&[0; 3]);
+    write_string(&mut data, name);
+
+    chunk(CHUNK_LAYER, data)
+}
+
+/// 8x8 部品の生データ cel。ピクセル値は 4 * パレット番号 + インデックス (インデックス 0 は透明色 0)。
+fn cel_chunk(layer: u16, x: i16, y: i16, tile: &Tile, attr: SpriteAttribute) -> Vec<u8> {
+    let base = 4 * attr.palette_index();
+    let indices = tile.pixel_indices();
+
+    let mut data = Vec::new();
+    data.write_u16::<LE>(layer).unwrap();
+    data.write_i16::<LE>(x).unwrap();
+    data.write_i16::<LE>(y).unwrap();
+    data.write_u8(0xFF).unwrap(); // 不透明度
+    data.write_u16::<LE>(0).unwrap(); // 生データ
+    data.extend(&[0; 7]);
+    data.write_u16::<LE>(8).unwrap();
+    data.write_u16::<LE>(8).unwrap();
+    for py in 0..8 {
+        for px in 0..8 {
+            let sx = if attr.is_flipped_horizontal() {
+                7 - px
+            } else {
+                px
+            };
+            let sy = if attr.is_flipped_vertical() {
+                7 - py
+            } else {
+                py
+            };
+            let idx = indices[sy][sx];
+            data.push(if idx == 0 { 0 } else { base + idx });
+        }
+    }
+
+    chunk(CHUNK_CEL, data)
+}
+
+fn tags_chunk(tags: &[AsepriteTag]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.write_u16::<LE>(tags.len() as u16).unwrap();
+    data.extend(&[0; 8]);
+    for tag in tags {
+        data.write_u16::<LE>(tag.from).unwrap();
+        data.write_u16::<LE>(tag.to).unwrap();
+        data.write_u8(0).unwrap(); // 順方向
+        data.write_u16::<LE>(0).unwrap(); // 無限に繰り返す
+        data.extend(&[0; 6]);
+        data.extend(&[0, 0, 0]); // タグの色 (非推奨)
+        data.write_u8(0).unwrap();
+        write_string(&mut data, &tag.name);
+    }
+
+    chunk(CHUNK_TAGS, data)
+}
@@ -0,0 +1,225 @@
+// メタスプライト/セルのテクスチャアトラス。
+//
+// JSON は TexturePacker の JSON (Hash) 形式で、Aseprite のエクスポートとも互換。
+// 各フレームには独自に id, round, paletteIndices を追加し、meta.palettes に色 ID を入れる。
+// meta.scale は縦の拡大倍率。--aspect で横に引き伸ばした場合は横の倍率を meta.scaleX に入れる。
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use image::imageops;
+use image::RgbaImage;
+use serde::Serialize;
+
+use crate::game::*;
+use crate::ppu::*;
+use crate::scaling::*;
+
+/// アトラスの 1 行に並べるフレーム数。
+const ATLAS_COLUMNS: u32 = 16;
+
+/// アトラスに詰める画像 1 枚。
+#[derive(Clone, Debug)]
+pub struct AtlasEntry {
+    pub name: String,
+    pub id: u8,
+    pub round: u8,
+    /// 使うパレット番号 (セルは 1 個、メタスプライトはタイルごとに 4 個)。
+    pub palette_indices: Vec<u8>,
+    pub image: RgbaImage,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+struct JsonRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+struct JsonSize {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonFrame {
+    frame: JsonRect,
+    rotated: bool,
+    trimmed: bool,
+    sprite_source_size: JsonRect,
+    source_size: JsonSize,
+    duration: u32,
+    id: u8,
+    round: u8,
+    palette_indices: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonMeta {
+    app: &'static str,
+    version: &'static str,
+    image: String,
+    format: &'static str,
+    size: JsonSize,
+    scale: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scale_x: Option<String>,
+    /// パレット番号ごとの NES 色 ID。
+    palettes: Vec<[u8; 4]>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct JsonAtlas {
+    frames: BTreeMap<String, JsonFrame>,
+    meta: JsonMeta,
+}
+
+#[derive(Clone, Debug)]
+pub struct Atlas {
+    pub image: RgbaImage,
+    frames: BTreeMap<String, JsonFrame>,
+    palettes: Vec<[u8; 4]>,
+    scaling: ImageScaling,
+}
+
+impl Atlas {
+    /// entries を左上から順に格子状に詰める。全画像は同じ大きさで、scaling で拡大済みとする。
+    pub fn pack(entries: Vec<AtlasEntry>, palettes: &[Palette], scaling: &ImageScaling) -> Self {
+        let (w, h) = entries
+            .first()
+            .map_or((0, 0), |entry| entry.image.dimensions());
+        let n_row = (entries.len() as u32).div_ceil(ATLAS_COLUMNS);
+
+        let mut image = RgbaImage::new(w * ATLAS_COLUMNS, h * n_row);
+        let mut frames = BTreeMap::new();
+
+        for (i, entry) in entries.into_iter().enumerate() {
+            assert_eq!(entry.image.dimensions(), (w, h), "image size mismatch");
+
+            let x = w * (i as u32 % ATLAS_COLUMNS);
+            let y = h * (i as u32 / ATLAS_COLUMNS);
+            imageops::overlay(&mut image, &entry.image, x, y);
+
+            frames.insert(
+                entry.name,
+                JsonFrame {
+                    frame: JsonRect { x, y, w, h },
+                    rotated: false,
+                    trimmed: false,
+                    sprite_source_size: JsonRect { x: 0, y: 0, w, h },
+                    source_size: JsonSize { w, h },
+                    duration: 100,
+                    id: entry.id,
+                    round: entry.round,
+                    palette_indices: entry.palette_indices,
+                },
+            );
+        }
+
+        Self {
+            image,
+            frames,
+            palettes: palettes.iter().map(|plt| plt.color_ids()).collect(),
+            scaling: *scaling,
+        }
+    }
+
+    /// image_name はアトラス画像のファイル名 (JSON からの相対パス)。
+    pub fn write_json<W: Write>(&self, wtr: W, image_name: &str) -> eyre::Result<()> {
+        let json = JsonAtlas {
+            frames: self.frames.clone(),
+            meta: JsonMeta {
+                app: "star_soldier_extract",
+                version: env!("CARGO_PKG_VERSION"),
+                image: image_name.to_owned(),
+                format: "RGBA8888",
+                size: JsonSize {
+                    w: self.image.width(),
+                    h: self.image.height(),
+                },
+                scale: self.scaling.scale.to_string(),
+                scale_x: self
+                    .scaling
+                    .aspect
+                    .then(|| format!("{:.6}", self.scaling.scale_x())),
+                palettes: self.palettes.clone(),
+            },
+        };
+        serde_json::to_writer_pretty(wtr, &json)?;
+
+        Ok(())
+    }
+}
+
+/// 両周回の全メタスプライトのアトラス。各画像は scaling で拡大する。
+pub fn meta_sprite_atlas(game: &Game, scaling: &ImageScaling) -> Atlas {
+    let mut entries = Vec::new();
+    for &second_round in &[false, true] {
+        let round = if second_round { 2 } else { 1 };
+        let imgs = game.meta_sprite_images(second_round);
+        for (id, image) in (0..=META_SPRITE_MAX).zip(imgs) {
+            entries.push(AtlasEntry {
+                name: format!("meta_sprite-{}-{:02X}", round, id),
+                id,
+                round,
+                palette_indices: game.meta_sprite_palette_indices(id).to_vec(),
+                image: scaling.apply(&image),
+            });
+        }
+    }
+
+    Atlas::pack(entries, game.sprite_palette_set(), scaling)
+}
+
+/// 両周回の全セルを palette_set で描いたアトラス。各画像は scaling で拡大する。
+pub fn cell_atlas(game: &Game, palette_set: &[Palette], scaling: &ImageScaling) -> Atlas {
+    let mut entries = Vec::new();
+    for &second_round in &[false, true] {
+        let round = if second_round { 2 } else { 1 };
+        let imgs = game.cell_images(second_round, palette_set);
+        for (id, image) in (0..=CELL_MAX).zip(imgs) {
+            entries.push(AtlasEntry {
+                name: format!("cell-{}-{:02X}", round, id),
+                id,
+                round,
+                palette_indices: vec![game.cell_palette_index(id)],
+                image: scaling.apply(&image),
+            });
+        }
+    }
+
+    Atlas::pack(entries, palette_set, scaling)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_meta_has_actual_scale() {
+        let scaling = ImageScaling {
+            scale: 2,
+            filter: ScaleFilter::Nearest,
+            aspect: true,
+        };
+        let entry = AtlasEntry {
+            name: "cell-1-00".to_owned(),
+            id: 0,
+            round: 1,
+            palette_indices: vec![0],
+            image: scaling.apply(&RgbaImage::new(16, 16)),
+        };
+        let atlas = Atlas::pack(vec![entry], &[], &scaling);
+
+        let mut buf = Vec::new();
+        atlas.write_json(&mut buf, "cells.png").unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+
+        assert_eq!(json["meta"]["scale"], "2");
+        assert_eq!(json["meta"]["scaleX"], "2.285714");
+    }
+}
@@ -0,0 +1,44 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+/// 全メタスプライトを周回ごとに .aseprite で出力する。
+/// アニメーションは meta_sprite_animation と同じくコードから探す。
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    #[structopt(parse(try_from_os_str = parse_directory))]
+    dir_out: PathBuf,
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = Rom::from_ines_bytes(std::fs::read(&opt.path_rom)?)?;
+    let game = Game::from_rom(&rom);
+
+    let anims = locate_meta_sprite_animations(&rom)?.animations;
+    let tag_name = |anim: &MetaSpriteAnimation| format!("anim-{:02X}", anim.id);
+
+    for &second_round in &[false, true] {
+        let path_out = opt.dir_out.join(format!(
+            "meta_sprites-{}.aseprite",
+            if second_round { 2 } else { 1 }
+        ));
+        write_meta_sprite_aseprite(
+            BufWriter::new(File::create(path_out)?),
+            &game,
+            second_round,
+            &anims,
+            tag_name,
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+/// メタスプライトとセルのアトラスを PNG + JSON で出力する。
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(flatten)]
+    scaling: ImageScaling,
+
+    /// セルのパレットセットを使うステージ
+    #[structopt(long, default_value = "1", parse(try_from_str = parse_stage))]
+    stage: u8,
+
+    /// セルのパレットセットを使う地形の前半 (0) / 後半 (1)
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_half))]
+    half: usize,
+
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    #[structopt(parse(try_from_os_str = parse_directory))]
+    dir_out: PathBuf,
+}
+
+fn save_atlas(opt: &Opt, atlas: &Atlas, name: &str) -> eyre::Result<()> {
+    let name_png = format!("{}.png", name);
+    atlas.image.save(opt.dir_out.join(&name_png))?;
+    atlas.write_json(
+        BufWriter::new(File::create(opt.dir_out.join(format!("{}.json", name)))?),
+        &name_png,
+    )?;
+
+    Ok(())
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+    opt.scaling.validate()?;
+
+    let rom = Rom::from_ines_bytes(std::fs::read(&opt.path_rom)?)?;
+    let game = Game::from_rom(&rom);
+
+    save_atlas(
+        &opt,
+        &meta_sprite_atlas(&game, &opt.scaling),
+        "meta_sprites",
+    )?;
+
+    let ground = game.ground(opt.stage);
+    let atlas = cell_atlas(&game, ground.palette_set_half(opt.half), &opt.scaling);
+    save_atlas(
+        &opt,
+        &atlas,
+        &format!("cells-{:02}-{}", opt.stage, opt.half),
+    )?;
+
+    Ok(())
+}
@@ -13,12 +13,6 @@ struct Opt {
     dir_out: PathBuf,
 }
 
-fn parse_directory(s: &std::ffi::OsStr) -> Result<PathBuf, std::ffi::OsString> {
-    let dir = PathBuf::from(s);
-
-    dir.is_dir().then(|| dir).ok_or_else(|| s.to_owned())
-}
-
 fn main() -> eyre::Result<()> {
     let opt = Opt::from_args();
 
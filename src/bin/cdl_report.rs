@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    /// FCEUX の .cdl ファイル (複数指定した場合は統合する)
+    #[structopt(parse(from_os_str), required = true)]
+    paths_cdl: Vec<PathBuf>,
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = Rom::from_ines_bytes(std::fs::read(opt.path_rom)?)?;
+    let game = Game::from_rom(&rom);
+    let tables = load_known_tables(&rom);
+
+    let mut cdl = Cdl::from_bytes(std::fs::read(&opt.paths_cdl[0])?)?;
+    for path in &opt.paths_cdl[1..] {
+        cdl.merge(&Cdl::from_bytes(std::fs::read(path)?)?);
+    }
+
+    let (cov_code, cov_data, cov_chr) = cdl.coverage();
+    println!("# coverage");
+    println!();
+    println!("PRG code: {:6.2}%", 100.0 * cov_code);
+    println!("PRG data: {:6.2}%", 100.0 * cov_data);
+    println!("CHR:      {:6.2}%", 100.0 * cov_chr);
+    println!();
+
+    println!("# unmapped PRG data");
+    println!();
+    for range in cdl.unmapped_prg_data(&tables) {
+        let indirect = range.clone().any(|i| cdl.is_prg_indirect_data(i));
+        println!(
+            "${:04X}-${:04X} ({:5} bytes){}",
+            0x8000 + range.start,
+            0x8000 + range.end - 1,
+            range.len(),
+            if indirect { " indirect" } else { "" }
+        );
+    }
+    println!();
+
+    println!("# unmapped CHR tiles");
+    println!();
+    for range in cdl.unmapped_chr_tiles(&game.referenced_tile_ids()) {
+        println!(
+            "tile ${:03X}-${:03X} ({:3} tiles)",
+            range.start,
+            range.end - 1,
+            range.len()
+        );
+    }
+    println!();
+
+    println!("# known tables never accessed");
+    println!();
+    for table in cdl.untouched_tables(&tables) {
+        println!("${:04X} {}", table.addr, table.name);
+    }
+
+    Ok(())
+}
@@ -1,19 +1,49 @@
+use std::collections::BTreeSet;
 use std::path::PathBuf;
 
 use image::imageops;
 use image::{Rgba, RgbaImage};
+use imageproc::pixelops::interpolate;
 use structopt::StructOpt;
 
 use star_soldier_extract::*;
 
+const MATRIX_WIDTH: u32 = 256 + 16;
+const MATRIX_HEIGHT: u32 = 160 + 16;
+
+/// --all で各マトリクスの上に置くタイトルの高さ。
+const TITLE_HEIGHT: u32 = 20;
+
+/// --all で横に並べるマトリクスの数 (2 ステージ分)。
+const GRID_COLUMNS: u32 = 4;
+
 const COLOR_BG: Rgba<u8> = Rgba([0, 0, 0, 0xFF]);
 const COLOR_TEXT: Rgba<u8> = Rgba([0xFF, 0xFF, 0xFF, 0xFF]);
 
 #[derive(Debug, StructOpt)]
 struct Opt {
+    #[structopt(flatten)]
+    scaling: ImageScaling,
+
     #[structopt(long)]
     second_round: bool,
 
+    /// パレットセットを使うステージ
+    #[structopt(long, default_value = "1", parse(try_from_str = parse_stage))]
+    stage: u8,
+
+    /// パレットセットを使う地形の前半 (0) / 後半 (1)
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_half))]
+    half: usize,
+
+    /// 全ステージの前半/後半のパレットセットで描いたものを並べる
+    #[structopt(long)]
+    all: bool,
+
+    /// そのステージの前半/後半で使われないセルを暗くする
+    #[structopt(long)]
+    highlight: bool,
+
     #[structopt(parse(from_os_str))]
     path_rom: PathBuf,
 
@@ -21,36 +51,99 @@ struct Opt {
     path_out: PathBuf,
 }
 
-fn main() -> eyre::Result<()> {
-    let opt = Opt::from_args();
-
-    let rom = Rom::from_ines_bytes(std::fs::read(opt.path_rom)?)?;
-    let game = Game::from_rom(&rom);
-
-    // とりあえず 1 面前半のパレットセットを使う
-    let plt_set = game.ground(1).palette_set_half(0).to_vec();
-
-    let mut img = RgbaImage::from_pixel(256 + 16, 160 + 16, COLOR_BG);
-
+/// (x0, y0) にセルのマトリクスを描く。used を与えた場合、含まれないセルを暗くする。
+fn draw_matrix(
+    img: &mut RgbaImage,
+    x0: u32,
+    y0: u32,
+    game: &Game,
+    plt_set: &[Palette],
+    second_round: bool,
+    used: Option<&BTreeSet<u8>>,
+) {
     let font = Font::new(16.0);
 
     for c in 0..16 {
-        let x = 16 + 16 * c;
-        font.draw(&mut img, x + 2, 0, COLOR_TEXT, format!("x{:X}", c));
+        let x = x0 + 16 + 16 * c;
+        font.draw(img, x + 2, y0, COLOR_TEXT, format!("x{:X}", c));
     }
-    for r in 0..16 {
-        let y = 16 + 16 * r;
-        font.draw(&mut img, 2, y, COLOR_TEXT, format!("{:X}x", r));
+    for r in 0..10 {
+        let y = y0 + 16 + 16 * r;
+        font.draw(img, x0 + 2, y, COLOR_TEXT, format!("{:X}x", r));
     }
 
-    let imgs_cell = game.cell_images(opt.second_round, &plt_set);
+    let imgs_cell = game.cell_images(second_round, plt_set);
     for i in 0..=CELL_MAX {
-        let c = i as u32 % 16;
-        let r = i as u32 / 16;
-        imageops::overlay(&mut img, &imgs_cell[i as usize], 16 + 16 * c, 16 + 16 * r);
+        let x = x0 + 16 + 16 * (i as u32 % 16);
+        let y = y0 + 16 + 16 * (i as u32 / 16);
+        imageops::overlay(img, &imgs_cell[i as usize], x, y);
+
+        if used.is_some_and(|used| !used.contains(&i)) {
+            for (y, x) in itertools::iproduct!(y..y + 16, x..x + 16) {
+                let pixel = interpolate(COLOR_BG, *img.get_pixel(x, y), 0.75);
+                img.put_pixel(x, y, pixel);
+            }
+        }
     }
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+    opt.scaling.validate()?;
+
+    let rom = Rom::from_ines_bytes(std::fs::read(&opt.path_rom)?)?;
+    let game = Game::from_rom(&rom);
+
+    let img = if opt.all {
+        let targets: Vec<(u8, usize)> = itertools::iproduct!(1..=16, 0..2).collect();
+        let n_row = (targets.len() as u32).div_ceil(GRID_COLUMNS);
+        let mut img = RgbaImage::from_pixel(
+            MATRIX_WIDTH * GRID_COLUMNS,
+            (TITLE_HEIGHT + MATRIX_HEIGHT) * n_row,
+            COLOR_BG,
+        );
+        let font = Font::new(16.0);
+        for (i, &(stage, half)) in targets.iter().enumerate() {
+            let x = MATRIX_WIDTH * (i as u32 % GRID_COLUMNS);
+            let y = (TITLE_HEIGHT + MATRIX_HEIGHT) * (i as u32 / GRID_COLUMNS);
+            font.draw(
+                &mut img,
+                x + 2,
+                y,
+                COLOR_TEXT,
+                format!("Stage {} half {}", stage, half),
+            );
+
+            let ground = game.ground(stage);
+            let used = opt.highlight.then(|| ground.used_cells(half));
+            draw_matrix(
+                &mut img,
+                x,
+                y + TITLE_HEIGHT,
+                &game,
+                ground.palette_set_half(half),
+                opt.second_round,
+                used.as_ref(),
+            );
+        }
+        img
+    } else {
+        let mut img = RgbaImage::from_pixel(MATRIX_WIDTH, MATRIX_HEIGHT, COLOR_BG);
+        let ground = game.ground(opt.stage);
+        let used = opt.highlight.then(|| ground.used_cells(opt.half));
+        draw_matrix(
+            &mut img,
+            0,
+            0,
+            &game,
+            ground.palette_set_half(opt.half),
+            opt.second_round,
+            used.as_ref(),
+        );
+        img
+    };
 
-    img.save(opt.path_out)?;
+    opt.scaling.save(&img, &opt.path_out)?;
 
     Ok(())
 }
@@ -0,0 +1,33 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// 割り込みベクタ以外のコード開始アドレス (16 進)
+    #[structopt(long = "entry", parse(try_from_str = parse_prg_addr))]
+    entrypoints: Vec<u16>,
+
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    #[structopt(parse(from_os_str))]
+    path_out: PathBuf,
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = Rom::from_ines_bytes(std::fs::read(opt.path_rom)?)?;
+
+    let tables = load_known_tables(&rom);
+    let disasm = Disassembly::new(&rom, &tables, &opt.entrypoints);
+
+    disasm.write_ca65(std::io::BufWriter::new(std::fs::File::create(
+        opt.path_out,
+    )?))?;
+
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(flatten)]
+    scaling: ImageScaling,
+
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    #[structopt(parse(try_from_os_str = parse_directory))]
+    dir_out: PathBuf,
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+    opt.scaling.validate()?;
+
+    let rom = Rom::from_ines_bytes(std::fs::read(opt.path_rom)?)?;
+    let game = Game::from_rom(&rom);
+
+    let groups = load_enemy_groups(&rom);
+    let animations = locate_meta_sprite_animations(&rom).ok();
+    let sprite_idss = enemy_group_sprite_ids(&groups, animations.as_ref());
+
+    for (group, sprite_ids) in itertools::zip(&groups, &sprite_idss) {
+        let img = enemy_card_image(&game, group, sprite_ids);
+        let path_out = opt.dir_out.join(format!("enemy-{:02X}.png", group.id));
+        opt.scaling.save(&img, path_out)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// 出力形式 (md, csv, html)
+    #[structopt(long, default_value = "md")]
+    format: TableFormat,
+
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = Rom::from_ines_bytes(std::fs::read(opt.path_rom)?)?;
+
+    let groups = load_enemy_groups(&rom);
+    write_enemy_group_table(std::io::stdout().lock(), &groups, opt.format)?;
+
+    Ok(())
+}
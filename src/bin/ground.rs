@@ -13,9 +13,20 @@ const COLOR_TEXT: Rgba<u8> = Rgba([0xFF, 0xFF, 0xFF, 0xFF]);
 
 #[derive(Debug, StructOpt)]
 struct Opt {
+    #[structopt(flatten)]
+    scaling: ImageScaling,
+
     #[structopt(long)]
     second_round: bool,
 
+    /// セルの種類を色分けして重ねる
+    #[structopt(long)]
+    overlay: bool,
+
+    /// 横スクロールで画面に映る範囲の境界を描く
+    #[structopt(long)]
+    viewport: bool,
+
     #[structopt(parse(from_os_str))]
     path_rom: PathBuf,
 
@@ -26,15 +37,6 @@ struct Opt {
     path_out: PathBuf,
 }
 
-fn parse_stage(s_stage: &str) -> eyre::Result<u8> {
-    const RANGE: std::ops::RangeInclusive<u8> = 1..=16;
-
-    let stage: u8 = s_stage.parse()?;
-    eyre::ensure!(RANGE.contains(&stage), "stage must be within {:?}", RANGE);
-
-    Ok(stage)
-}
-
 fn range_len(range: &std::ops::Range<i32>) -> i32 {
     range.end - range.start
 }
@@ -63,7 +65,15 @@ fn draw_space(img: &mut RgbaImage, idx: usize) {
     }
 }
 
-fn draw_ground(img: &mut RgbaImage, game: &Game, ground: &Ground, idx: usize, second_round: bool) {
+fn draw_ground(
+    img: &mut RgbaImage,
+    game: &Game,
+    ground: &Ground,
+    idx: usize,
+    second_round: bool,
+    overlay: Option<&CellAttributes>,
+    viewport: Option<&ScrollModel>,
+) {
     let y_bias = match idx {
         0 => range_len(&SPACE_ROW_RANGES[0]),
         1 => 128 + range_len(&SPACE_ROW_RANGES[0]) + range_len(&SPACE_ROW_RANGES[1]),
@@ -92,6 +102,14 @@ fn draw_ground(img: &mut RgbaImage, game: &Game, ground: &Ground, idx: usize, se
             if visual_id == CELL_TRAP {
                 draw_trap(img, x, y);
             }
+
+            if let Some(attrs) = overlay {
+                draw_overlay(img, x, y, attrs.kind(ground.cell(r, c)));
+            }
+        }
+
+        if let Some(model) = viewport {
+            draw_viewport(img, y, model);
         }
 
         font.draw(img, 2, y, COLOR_TEXT, format!("{:3}", r));
@@ -115,13 +133,62 @@ fn draw_trap(img: &mut RgbaImage, x0: u32, y0: u32) {
     }
 }
 
+/// セルの種類に応じた色を半透明で重ねる。Plain には何もしない。
+fn draw_overlay(img: &mut RgbaImage, x0: u32, y0: u32, kind: CellKind) {
+    use imageproc::pixelops::interpolate;
+
+    let color = match kind {
+        CellKind::Plain => return,
+        CellKind::Item => Rgba([0x40, 0xFF, 0x40, 0xFF]),
+        CellKind::Zeg { .. } => Rgba([0xFF, 0xFF, 0x40, 0xFF]),
+        CellKind::Trap => Rgba([0xFF, 0x40, 0x40, 0xFF]),
+        CellKind::GroundTurret => Rgba([0xFF, 0x40, 0xFF, 0xFF]),
+        CellKind::Destructible => Rgba([0x40, 0xC0, 0xFF, 0xFF]),
+    };
+
+    for (y, x) in itertools::iproduct!(y0..y0 + 16, x0..x0 + 16) {
+        let pixel = interpolate(color, *img.get_pixel(x, y), 0.5);
+        img.put_pixel(x, y, pixel);
+    }
+}
+
+/// 1 行分について、画面に映りうる範囲 (赤) と常に映る範囲 (黄) の境界線を描く。
+fn draw_viewport(img: &mut RgbaImage, y0: u32, model: &ScrollModel) {
+    const COLOR_REACHABLE: Rgba<u8> = Rgba([0xFF, 0x40, 0x40, 0xFF]);
+    const COLOR_ALWAYS: Rgba<u8> = Rgba([0xFF, 0xFF, 0x40, 0xFF]);
+
+    let reachable = model.reachable_x_range();
+    let always = model.always_visible_x_range();
+    let lines = [
+        (always.start, COLOR_ALWAYS),
+        (always.end - 1, COLOR_ALWAYS),
+        (reachable.start, COLOR_REACHABLE),
+        (reachable.end - 1, COLOR_REACHABLE),
+    ];
+
+    for &(x, color) in &lines {
+        for y in y0..y0 + 16 {
+            img.put_pixel(32 + x, y, color);
+        }
+    }
+}
+
 fn main() -> eyre::Result<()> {
     let opt = Opt::from_args();
+    opt.scaling.validate()?;
 
-    let rom = Rom::from_ines_bytes(std::fs::read(opt.path_rom)?)?;
+    let rom = Rom::from_ines_bytes(std::fs::read(&opt.path_rom)?)?;
     let game = Game::from_rom(&rom);
 
     let ground = game.ground(opt.stage);
+    let overlay = opt
+        .overlay
+        .then(|| CellAttributes::from_rom(&rom))
+        .transpose()?;
+    let viewport = opt
+        .viewport
+        .then(|| ScrollModel::from_rom(&rom))
+        .transpose()?;
 
     let mut img = canvas();
 
@@ -129,10 +196,18 @@ fn main() -> eyre::Result<()> {
         draw_space(&mut img, i);
     }
     for i in 0..2 {
-        draw_ground(&mut img, &game, &ground, i, opt.second_round);
+        draw_ground(
+            &mut img,
+            &game,
+            &ground,
+            i,
+            opt.second_round,
+            overlay.as_ref(),
+            viewport.as_ref(),
+        );
     }
 
-    img.save(opt.path_out)?;
+    opt.scaling.save(&img, &opt.path_out)?;
 
     Ok(())
 }
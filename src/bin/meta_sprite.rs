@@ -6,6 +6,9 @@ use star_soldier_extract::*;
 
 #[derive(Debug, StructOpt)]
 struct Opt {
+    #[structopt(flatten)]
+    scaling: ImageScaling,
+
     #[structopt(parse(from_os_str))]
     path_rom: PathBuf,
 
@@ -13,14 +16,9 @@ struct Opt {
     dir_out: PathBuf,
 }
 
-fn parse_directory(s: &std::ffi::OsStr) -> Result<PathBuf, std::ffi::OsString> {
-    let dir = PathBuf::from(s);
-
-    dir.is_dir().then(|| dir).ok_or_else(|| s.to_owned())
-}
-
 fn main() -> eyre::Result<()> {
     let opt = Opt::from_args();
+    opt.scaling.validate()?;
 
     let rom = Rom::from_ines_bytes(std::fs::read(opt.path_rom)?)?;
     let game = Game::from_rom(&rom);
@@ -33,7 +31,7 @@ fn main() -> eyre::Result<()> {
                 if second_round { 2 } else { 1 },
                 i
             ));
-            img.save(path_out)?;
+            opt.scaling.save(img, path_out)?;
         }
     }
 
@@ -0,0 +1,76 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+/// アニメーションはコードから探す (meta_sprite_animation.rs を参照)。
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(flatten)]
+    scaling: ImageScaling,
+
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    #[structopt(parse(try_from_os_str = parse_directory))]
+    dir_out: PathBuf,
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+    opt.scaling.validate()?;
+
+    let rom = Rom::from_ines_bytes(std::fs::read(&opt.path_rom)?)?;
+    let game = Game::from_rom(&rom);
+
+    let found = locate_meta_sprite_animations(&rom)?;
+    println!("meta-sprite ID array: ${:04X}", found.sprite_id_array);
+    for anim in &found.animations {
+        let writers: Vec<_> = anim
+            .writers
+            .iter()
+            .map(|pc| format!("${:04X}", pc))
+            .collect();
+        println!(
+            "anim-{:02X}: table ${:04X}, counter ${:04X}, {} frames, written at {}",
+            anim.id,
+            anim.table,
+            anim.counter,
+            anim.frames.len(),
+            writers.join(" ")
+        );
+    }
+    for pc in &found.other_writers {
+        println!("not an animation: write at ${:04X}", pc);
+    }
+
+    for anim in &found.animations {
+        for &second_round in &[false, true] {
+            let path = |ext: &str| {
+                opt.dir_out.join(format!(
+                    "anim-{}-{:02X}.{}",
+                    if second_round { 2 } else { 1 },
+                    anim.id,
+                    ext
+                ))
+            };
+            anim.write_gif(
+                BufWriter::new(File::create(path("gif"))?),
+                &game,
+                second_round,
+                &opt.scaling,
+            )?;
+            anim.write_apng(
+                BufWriter::new(File::create(path("png"))?),
+                &game,
+                second_round,
+                &opt.scaling,
+            )?;
+        }
+    }
+
+    Ok(())
+}
@@ -11,6 +11,9 @@ const COLOR_TEXT: Rgba<u8> = Rgba([0xFF, 0xFF, 0xFF, 0xFF]);
 
 #[derive(Debug, StructOpt)]
 struct Opt {
+    #[structopt(flatten)]
+    scaling: ImageScaling,
+
     #[structopt(long)]
     second_round: bool,
 
@@ -23,6 +26,7 @@ struct Opt {
 
 fn main() -> eyre::Result<()> {
     let opt = Opt::from_args();
+    opt.scaling.validate()?;
 
     let rom = Rom::from_ines_bytes(std::fs::read(opt.path_rom)?)?;
     let game = Game::from_rom(&rom);
@@ -52,7 +56,7 @@ fn main() -> eyre::Result<()> {
         );
     }
 
-    img.save(opt.path_out)?;
+    opt.scaling.save(&img, &opt.path_out)?;
 
     Ok(())
 }
@@ -6,6 +6,14 @@ use star_soldier_extract::*;
 
 #[derive(Debug, StructOpt)]
 struct Opt {
+    /// MML の方言
+    #[structopt(long, default_value = "flmml", possible_values = &["flmml", "ppmck", "nsd"])]
+    dialect: String,
+
+    /// MML に加えて JSON (music_patch の入力形式) も出力する
+    #[structopt(long)]
+    json: bool,
+
     #[structopt(parse(from_os_str))]
     path_rom: PathBuf,
 
@@ -13,20 +21,26 @@ struct Opt {
     dir_out: PathBuf,
 }
 
-fn parse_directory(s: &std::ffi::OsStr) -> Result<PathBuf, std::ffi::OsString> {
-    let dir = PathBuf::from(s);
-
-    dir.is_dir().then(|| dir).ok_or_else(|| s.to_owned())
-}
-
 fn main() -> eyre::Result<()> {
     let opt = Opt::from_args();
 
     let rom = Rom::from_ines_bytes(std::fs::read(opt.path_rom)?)?;
 
+    let dialect: &dyn MmlDialect = match opt.dialect.as_str() {
+        "flmml" => &FlMml,
+        "ppmck" => &Ppmck,
+        "nsd" => &NsdLib,
+        _ => unreachable!(),
+    };
+
     for music in load_musics(&rom) {
         let path_out = opt.dir_out.join(format!("music-{:02}.mml", music.id));
-        music.write_mml(std::fs::File::create(path_out)?)?;
+        music.write_mml_dialect(std::fs::File::create(path_out)?, dialect)?;
+
+        if opt.json {
+            let path_out = opt.dir_out.join(format!("music-{:02}.json", music.id));
+            serde_json::to_writer_pretty(std::fs::File::create(path_out)?, &music)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use eyre::eyre;
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// 差し替える曲 (ID:パス)。拡張子が .json なら JSON、それ以外は MML (PPMCK 方言) として読む。
+    #[structopt(long = "music", parse(try_from_str = parse_music_arg), required = true)]
+    musics: Vec<(u8, PathBuf)>,
+
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    #[structopt(parse(from_os_str))]
+    path_out: PathBuf,
+}
+
+fn parse_music_arg(s: &str) -> eyre::Result<(u8, PathBuf)> {
+    let (id, path) = s
+        .split_once(':')
+        .ok_or_else(|| eyre!("expected ID:PATH: {}", s))?;
+
+    Ok((id.parse()?, PathBuf::from(path)))
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let buf = std::fs::read(&opt.path_rom)?;
+    let mut rom = Rom::from_ines_bytes(&buf)?;
+
+    let mut musics = Vec::new();
+    for (id, path) in opt.musics {
+        let src = std::fs::read_to_string(&path)?;
+        let music = if path.extension().is_some_and(|ext| ext == "json") {
+            let mut music: Music = serde_json::from_str(&src)?;
+            music.id = id;
+            music
+        } else {
+            parse_music_mml(&src, id)?
+        };
+        musics.push(music);
+    }
+
+    patch_musics(&mut rom, musics)?;
+
+    let mut buf_out = buf;
+    buf_out[16..][..rom.prg.len()].copy_from_slice(&rom.prg);
+    std::fs::write(opt.path_out, buf_out)?;
+
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// 音名ごとの音符数も出力する
+    #[structopt(long)]
+    histogram: bool,
+
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = Rom::from_ines_bytes(std::fs::read(opt.path_rom)?)?;
+
+    let analyses: Vec<_> = load_musics(&rom).iter().map(analyze_music).collect();
+
+    // bpm は推定値 (MusicAnalysis::estimated_bpm() を参照)
+    println!(
+        "{:>2}  {:>6}  {:>6}  {:<7}  {:<8}  {:>5}  {:<9}  {:<9}  {:<9}",
+        "ID", "frames", "sec", "end", "key", "~bpm", "sq1", "sq2", "tri"
+    );
+    for analysis in &analyses {
+        println!(
+            "{:>2}  {:>6}  {:>6.2}  {:<7}  {:<8}  {:>5}  {:<9}  {:<9}  {:<9}",
+            analysis.id,
+            analysis.frame_count,
+            analysis.seconds(),
+            if analysis.repeat { "restart" } else { "end" },
+            analysis.key_name().unwrap_or_else(|| "-".to_owned()),
+            analysis
+                .estimated_bpm()
+                .map_or_else(|| "-".to_owned(), |bpm| format!("{:.1}", bpm)),
+            range_str(&analysis.channels[0]),
+            range_str(&analysis.channels[1]),
+            range_str(&analysis.channels[2]),
+        );
+    }
+
+    // ループ構造: 回数 x 本体のフレーム数 @ 開始フレーム
+    println!();
+    println!(
+        "{:>2}  {:<3}  loops (count x body frames @ start frame)",
+        "ID", "ch"
+    );
+    for analysis in &analyses {
+        for (name, ch) in itertools::zip(&["sq1", "sq2", "tri"], &analysis.channels) {
+            let loops: Vec<_> = ch
+                .loops
+                .iter()
+                .map(|info| format!("{}x{}@{}", info.count, info.body_frames, info.start_frame))
+                .collect();
+            println!(
+                "{:>2}  {:<3}  {}",
+                analysis.id,
+                name,
+                if loops.is_empty() {
+                    "-".to_owned()
+                } else {
+                    loops.join(" ")
+                }
+            );
+        }
+    }
+
+    if opt.histogram {
+        println!();
+        print!("{:>2}  {:<3}", "ID", "ch");
+        for note in 0..12 {
+            print!("  {:>3}", note_to_mml(note));
+        }
+        println!();
+        for analysis in &analyses {
+            for (name, ch) in itertools::zip(&["sq1", "sq2", "tri"], &analysis.channels) {
+                print!("{:>2}  {:<3}", analysis.id, name);
+                for count in &ch.histogram {
+                    print!("  {:>3}", count);
+                }
+                println!();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn range_str(ch: &ChannelAnalysis) -> String {
+    match (ch.lowest, ch.highest) {
+        (Some((o1, n1)), Some((o2, n2))) => {
+            format!("O{}{}-O{}{}", o1, note_to_mml(n1), o2, note_to_mml(n2))
+        }
+        _ => "-".to_owned(),
+    }
+}
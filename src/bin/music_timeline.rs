@@ -0,0 +1,37 @@
+use std::fs::File;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    #[structopt(parse(try_from_os_str = parse_directory))]
+    dir_out: PathBuf,
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = Rom::from_ines_bytes(std::fs::read(&opt.path_rom)?)?;
+
+    let periods = locate_period_table(&rom)?;
+
+    for music in load_musics(&rom) {
+        let path = |ext: &str| opt.dir_out.join(format!("music-{:02}.{}", music.id, ext));
+
+        let frames: Vec<_> = MusicTimeline::new(&music, &periods)?
+            .filter(|frame| !frame.events.is_empty())
+            .collect();
+        serde_json::to_writer_pretty(File::create(path("events.json"))?, &frames)?;
+        let writes = music_apu_writes(&music, &periods)?;
+        serde_json::to_writer_pretty(File::create(path("apu.json"))?, &writes)?;
+        music.write_vgm(File::create(path("vgm"))?, &periods)?;
+    }
+
+    Ok(())
+}
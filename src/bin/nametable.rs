@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+/// 地形を画面ごとに .nam (属性込み 1KB), .atr (属性のみ), .pal (BG パレット 16 バイト) で出力する。
+/// BG パターンテーブルは .chr (4KB) で出力する。
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(long)]
+    second_round: bool,
+
+    /// 切り出す 16 列の左端の列 (0..=4)
+    #[structopt(long, default_value = "2", parse(try_from_str = parse_column))]
+    column: u8,
+
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    #[structopt(parse(try_from_str = parse_stage))]
+    stage: u8,
+
+    #[structopt(parse(try_from_os_str = parse_directory))]
+    dir_out: PathBuf,
+}
+
+fn parse_column(s_column: &str) -> eyre::Result<u8> {
+    let column: u8 = s_column.parse()?;
+    eyre::ensure!(
+        column + SCREEN_CELL_COLUMNS <= 20,
+        "column must be within 0..={}",
+        20 - SCREEN_CELL_COLUMNS
+    );
+
+    Ok(column)
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = Rom::from_ines_bytes(std::fs::read(&opt.path_rom)?)?;
+    let game = Game::from_rom(&rom);
+    let ground = game.ground(opt.stage);
+
+    let round = if opt.second_round { 2 } else { 1 };
+
+    std::fs::write(
+        opt.dir_out.join(format!("ground-{}.chr", round)),
+        game.bg_pattern_table(opt.second_round),
+    )?;
+
+    for (i, screen) in ground_screens().iter().enumerate() {
+        let path = |ext: &str| {
+            opt.dir_out.join(format!(
+                "ground-{}-{:02}-{:02}.{}",
+                round, opt.stage, i, ext
+            ))
+        };
+
+        let nametable = ground_nametable(&game, &ground, screen, opt.column, opt.second_round)?;
+        std::fs::write(path("nam"), nametable.to_bytes())?;
+        std::fs::write(path("atr"), &nametable.attrs)?;
+
+        let pal: Vec<u8> = ground
+            .palette_set_half(screen.half)
+            .iter()
+            .flat_map(|plt| plt.color_ids())
+            .collect();
+        std::fs::write(path("pal"), pal)?;
+    }
+
+    Ok(())
+}
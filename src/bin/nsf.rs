@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+/// サウンドドライバのアドレスは ROM から自動で探す。
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// 曲名リスト (1 行 1 曲)。省略時は "BGM 01" などとする。
+    #[structopt(long, parse(from_os_str))]
+    titles: Option<PathBuf>,
+
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    /// 出力する .nsf ファイル。同じ場所に .m3u も出力する。
+    #[structopt(parse(from_os_str))]
+    path_out: PathBuf,
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = Rom::from_ines_bytes(std::fs::read(opt.path_rom)?)?;
+    let musics = load_musics(&rom);
+
+    let titles: Vec<String> = match &opt.titles {
+        Some(path) => std::fs::read_to_string(path)?
+            .lines()
+            .map(str::to_owned)
+            .collect(),
+        None => musics
+            .iter()
+            .map(|music| format!("BGM {:02}", music.id))
+            .collect(),
+    };
+    eyre::ensure!(
+        titles.len() >= musics.len(),
+        "title list must have {} entries",
+        musics.len()
+    );
+
+    let driver = locate_music_driver(&rom)?;
+    let nsf = build_nsf(&rom, driver, "Star Soldier")?;
+    std::fs::write(&opt.path_out, nsf)?;
+
+    let nsf_file_name = opt.path_out.file_name().unwrap().to_string_lossy();
+    let path_m3u = opt.path_out.with_extension("m3u");
+    write_nsf_m3u(
+        std::fs::File::create(path_m3u)?,
+        &nsf_file_name,
+        driver,
+        &musics,
+        &titles,
+    )?;
+
+    Ok(())
+}
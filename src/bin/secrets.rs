@@ -0,0 +1,100 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use image::imageops;
+use image::{Rgba, RgbaImage};
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+const ROW_HEIGHT: u32 = 20;
+
+const COLOR_BG: Rgba<u8> = Rgba([0, 0, 0, 0xFF]);
+const COLOR_TEXT: Rgba<u8> = Rgba([0xFF, 0xFF, 0xFF, 0xFF]);
+
+/// 全ステージの隠しアイテムを secrets.md (一覧表) と secrets.png (アイコン付き一覧) に出力する。
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(flatten)]
+    scaling: ImageScaling,
+
+    #[structopt(long)]
+    second_round: bool,
+
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    #[structopt(parse(try_from_os_str = parse_directory))]
+    dir_out: PathBuf,
+}
+
+fn write_table<W: Write>(mut wtr: W, entries: &[SecretEntry]) -> eyre::Result<()> {
+    writeln!(wtr, "| Stage | Row | Col | Visual | Contents |")?;
+    writeln!(wtr, "|---|---|---|---|---|")?;
+    for entry in entries {
+        writeln!(
+            wtr,
+            "| {} | {} | {} | {:02X} | {} |",
+            entry.stage,
+            entry.r,
+            entry.c,
+            entry.item.visual_id(),
+            entry.item.name()
+        )?;
+    }
+
+    Ok(())
+}
+
+fn catalogue_image(game: &Game, entries: &[SecretEntry], second_round: bool) -> RgbaImage {
+    let font = Font::new(16.0);
+
+    let mut img = RgbaImage::from_pixel(400, ROW_HEIGHT * entries.len() as u32, COLOR_BG);
+
+    for (i, entry) in entries.iter().enumerate() {
+        let y = ROW_HEIGHT * i as u32;
+
+        let ground = game.ground(entry.stage);
+        let plt_set = ground.palette_set_half(usize::from(entry.r / 128));
+        let icon = game.cell_image(entry.item.visual_id(), second_round, plt_set);
+        imageops::overlay(&mut img, &icon, 2, y + 2);
+
+        font.draw(
+            &mut img,
+            24,
+            y + 2,
+            COLOR_TEXT,
+            format!(
+                "{:2}  r{:3}  c{:2}  {}",
+                entry.stage,
+                entry.r,
+                entry.c,
+                entry.item.name()
+            ),
+        );
+    }
+
+    img
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+    opt.scaling.validate()?;
+
+    let rom = Rom::from_ines_bytes(std::fs::read(&opt.path_rom)?)?;
+    let game = Game::from_rom(&rom);
+
+    let entries = secret_catalogue(&game);
+
+    write_table(
+        BufWriter::new(File::create(opt.dir_out.join("secrets.md"))?),
+        &entries,
+    )?;
+    opt.scaling.save(
+        &catalogue_image(&game, &entries, opt.second_round),
+        opt.dir_out.join("secrets.png"),
+    )?;
+
+    Ok(())
+}
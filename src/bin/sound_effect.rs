@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+/// 効果音ドライバと効果音 ID は ROM から自動で探す。
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// 効果音名リスト。各行は "ID 名前" (ID は 16 進)。
+    #[structopt(long, parse(from_os_str))]
+    names: Option<PathBuf>,
+
+    /// 1 つの効果音の最大フレーム数
+    #[structopt(long, default_value = "600")]
+    max_frames: u32,
+
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    #[structopt(parse(try_from_os_str = parse_directory))]
+    dir_out: PathBuf,
+}
+
+fn load_names(path: &std::path::Path) -> eyre::Result<std::collections::BTreeMap<u8, String>> {
+    let mut names = std::collections::BTreeMap::new();
+    for line in std::fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (id, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let id = parse_hex_u8(id)?;
+        names.insert(id, name.trim().to_owned());
+    }
+    Ok(names)
+}
+
+/// 効果音名をファイル名に使える形にする。英数字と '-', '_' 以外は '_' に置き換える。
+fn file_name_part(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = Rom::from_ines_bytes(std::fs::read(&opt.path_rom)?)?;
+
+    let names = match &opt.names {
+        Some(path) => load_names(path)?,
+        None => Default::default(),
+    };
+
+    let (driver, callers) = locate_sound_effect_driver(&rom)?;
+    let disasm = Disassembly::new(&rom, &load_known_tables(&rom), &[]);
+    println!(
+        "sound effect driver: trigger ${:04X}, play ${:04X}, table {}",
+        driver.trigger,
+        driver.play,
+        driver
+            .table
+            .map_or_else(|| "not found".to_owned(), |addr| format!("${:04X}", addr))
+    );
+
+    for (&id, pcs) in &callers {
+        let mut sfx = record_sound_effect(&rom, &disasm, driver, id, opt.max_frames)?;
+        sfx.name = names.get(&id).cloned();
+        sfx.callers = pcs.clone();
+        if sfx.channels.is_empty() {
+            continue;
+        }
+
+        let stem = match &sfx.name {
+            Some(name) => format!("sfx-{:02X}-{}", id, file_name_part(name)),
+            None => format!("sfx-{:02X}", id),
+        };
+        let path = |ext: &str| opt.dir_out.join(format!("{}.{}", stem, ext));
+        sfx.write_mml(File::create(path("mml"))?)?;
+        sfx.write_wav(File::create(path("wav"))?)?;
+        sfx.write_json(File::create(path("json"))?)?;
+    }
+
+    Ok(())
+}
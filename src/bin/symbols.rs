@@ -0,0 +1,37 @@
+use std::fs::File;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+
+    #[structopt(parse(try_from_os_str = parse_directory))]
+    dir_out: PathBuf,
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = Rom::from_ines_bytes(std::fs::read(&opt.path_rom)?)?;
+
+    let symbols = table_symbols(&load_known_tables(&rom));
+
+    // エミュレータは ROM ファイル名からシンボルファイル名を決める。
+    let rom_name = opt.path_rom.file_name().unwrap().to_string_lossy();
+    let rom_stem = opt.path_rom.file_stem().unwrap().to_string_lossy();
+
+    for bank in 0..2 {
+        let path_out = opt.dir_out.join(format!("{}.{}.nl", rom_name, bank));
+        write_fceux_nl(File::create(path_out)?, &symbols, bank)?;
+    }
+
+    let path_out = opt.dir_out.join(format!("{}.mlb", rom_stem));
+    write_mesen_mlb(File::create(path_out)?, &symbols)?;
+
+    Ok(())
+}
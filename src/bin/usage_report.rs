@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// 各 ID の使用数も出力する
+    #[structopt(long)]
+    counts: bool,
+
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+}
+
+fn format_ids(ids: &[u8]) -> String {
+    if ids.is_empty() {
+        return "-".to_owned();
+    }
+
+    ids.iter()
+        .map(|id| format!("{:02X}", id))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn print_counts(counts: &[u32]) {
+    for (i, count) in counts.iter().enumerate() {
+        println!("{:02X}: {}", i, count);
+    }
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = Rom::from_ines_bytes(std::fs::read(&opt.path_rom)?)?;
+    let game = Game::from_rom(&rom);
+    let report = analyze_usage(&rom, &game);
+
+    println!("# unused cells");
+    println!();
+    println!("{}", format_ids(&report.unused_cells()));
+    println!();
+
+    println!("# CHR tiles not referenced by used cells or meta-sprites");
+    println!();
+    for range in report.unused_tile_ranges() {
+        println!(
+            "tile ${:03X}-${:03X} ({:3} tiles)",
+            range.start,
+            range.end - 1,
+            range.len()
+        );
+    }
+    println!();
+
+    // 自機、弾、アイテム、爆発などのうちアニメーションとして見つからないものは含まれない。
+    println!("# meta-sprites not in enemy group frames or located animations (heuristic)");
+    println!();
+    match report.meta_sprite_animation_count {
+        Some(n) => println!("{} animations located in the code", n),
+        None => println!("animations could not be located; enemy group frames only"),
+    }
+    println!("{}", format_ids(&report.unused_meta_sprites()));
+    println!();
+
+    println!("# unused ground palettes");
+    println!();
+    println!("{}", format_ids(&report.unused_ground_palettes()));
+    println!();
+
+    println!("# palette usage");
+    println!();
+    println!("cell palette index:   {:?}", report.cell_palette_counts);
+    println!("sprite palette index: {:?}", report.sprite_palette_counts);
+    println!();
+
+    println!("# silent music (ID 10)");
+    println!();
+    let [sq1, sq2, tri] = report.silent_music_ptrs;
+    println!("sq1 ${:04X}, sq2 ${:04X}, tri ${:04X}", sq1, sq2, tri);
+
+    if opt.counts {
+        println!();
+        println!("# cell counts");
+        println!();
+        print_counts(&report.cell_counts);
+        println!();
+        println!("# meta-sprite counts");
+        println!();
+        print_counts(&report.meta_sprite_counts);
+        println!();
+        println!("# ground palette counts");
+        println!();
+        print_counts(&report.ground_palette_counts);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use star_soldier_extract::*;
+
+/// ゲーム自身のルーチンを実行してデコーダの結果を検証する。
+/// 各ルーチンのアドレスは ROM から自動で探す。
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    path_rom: PathBuf,
+}
+
+fn print_report(name: &str, report: &ValidationReport) {
+    println!(
+        "{}: {} checked, {} mismatches",
+        name,
+        report.checked,
+        report.mismatches.len()
+    );
+    for msg in &report.mismatches {
+        println!("  {}", msg);
+    }
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = Rom::from_ines_bytes(std::fs::read(opt.path_rom)?)?;
+
+    let routine = locate_ground_row_routine(&rom)?;
+    println!(
+        "ground row routine: ${:04X} (src ptr ${:02X}, dst ${:04X})",
+        routine.entry, routine.src_ptr_zp, routine.dst
+    );
+    let report_ground = validate_ground_rows(&rom, routine)?;
+    print_report("ground rows", &report_ground);
+
+    let driver = locate_music_driver(&rom)?;
+    let periods = locate_period_table(&rom)?;
+    println!(
+        "music driver: init ${:04X}, play ${:04X}, id base {}",
+        driver.init, driver.play, driver.id_base
+    );
+    println!(
+        "period table: lo ${:04X}, hi ${:04X}, {} entries",
+        periods.addr_lo,
+        periods.addr_hi,
+        periods.periods.len()
+    );
+    let report_music = validate_musics(&rom, driver, &periods)?;
+    print_report("musics", &report_music);
+
+    eyre::ensure!(
+        report_ground.is_ok() && report_music.is_ok(),
+        "validation failed"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use eyre::ensure;
+
+use crate::known_table::*;
+
+const PRG_CODE: u8 = 1 << 0;
+const PRG_DATA: u8 = 1 << 1;
+const PRG_INDIRECT_CODE: u8 = 1 << 4;
+const PRG_INDIRECT_DATA: u8 = 1 << 5;
+const PRG_PCM: u8 = 1 << 6;
+
+const CHR_RENDERED: u8 = 1 << 0;
+const CHR_READ: u8 = 1 << 1;
+
+/// FCEUX の Code/Data Logger ファイル。
+///
+/// PRG の各バイトのフラグに続いて CHR の各バイトのフラグが格納されている。
+#[derive(Clone, Debug)]
+pub struct Cdl {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+}
+
+impl Cdl {
+    pub fn from_bytes(buf: impl AsRef<[u8]>) -> eyre::Result<Self> {
+        let buf = buf.as_ref();
+        ensure!(buf.len() == 0x8000 + 0x8000, "size mismatch");
+
+        let prg = buf[..0x8000].to_vec();
+        let chr = buf[0x8000..].to_vec();
+
+        Ok(Self { prg, chr })
+    }
+
+    /// 複数回のログを統合する。
+    pub fn merge(&mut self, other: &Self) {
+        for (dst, src) in itertools::zip(&mut self.prg, &other.prg) {
+            *dst |= src;
+        }
+        for (dst, src) in itertools::zip(&mut self.chr, &other.chr) {
+            *dst |= src;
+        }
+    }
+
+    pub fn is_prg_code(&self, offset: usize) -> bool {
+        (self.prg[offset] & (PRG_CODE | PRG_INDIRECT_CODE)) != 0
+    }
+
+    pub fn is_prg_data(&self, offset: usize) -> bool {
+        (self.prg[offset] & (PRG_DATA | PRG_INDIRECT_DATA | PRG_PCM)) != 0
+    }
+
+    pub fn is_prg_indirect_data(&self, offset: usize) -> bool {
+        (self.prg[offset] & PRG_INDIRECT_DATA) != 0
+    }
+
+    pub fn is_chr_accessed(&self, offset: usize) -> bool {
+        (self.chr[offset] & (CHR_RENDERED | CHR_READ)) != 0
+    }
+
+    /// 実行時にデータとしてアクセスされたが、既知テーブルに含まれない PRG 領域を返す。
+    /// コードとしても実行された (オペランドなど) バイトは除く。
+    pub fn unmapped_prg_data(&self, tables: &[KnownTable]) -> Vec<Range<usize>> {
+        let mut known = vec![false; self.prg.len()];
+        for table in tables {
+            for i in table.range() {
+                known[i] = true;
+            }
+        }
+
+        ranges_where(self.prg.len(), |i| {
+            self.is_prg_data(i) && !self.is_prg_code(i) && !known[i]
+        })
+    }
+
+    /// 実行時に一度もアクセスされなかった既知テーブルを返す。
+    pub fn untouched_tables<'a>(&self, tables: &'a [KnownTable]) -> Vec<&'a KnownTable> {
+        tables
+            .iter()
+            .filter(|table| table.range().all(|i| !self.is_prg_data(i)))
+            .collect()
+    }
+
+    /// 実行時に描画/読み出しされたが、どのセル/メタスプライトからも参照されないタイル範囲を返す。
+    pub fn unmapped_chr_tiles(&self, referenced_tile_ids: &BTreeSet<usize>) -> Vec<Range<usize>> {
+        ranges_where(self.chr.len() / 16, |tile_id| {
+            let accessed = (16 * tile_id..16 * (tile_id + 1)).any(|i| self.is_chr_accessed(i));
+            accessed && !referenced_tile_ids.contains(&tile_id)
+        })
+    }
+
+    /// (PRG コードの割合, PRG データの割合, CHR アクセス済みの割合) を返す。
+    pub fn coverage(&self) -> (f64, f64, f64) {
+        let ratio = |n: usize, total: usize| n as f64 / total as f64;
+
+        let n_code = (0..self.prg.len()).filter(|&i| self.is_prg_code(i)).count();
+        let n_data = (0..self.prg.len()).filter(|&i| self.is_prg_data(i)).count();
+        let n_chr = (0..self.chr.len())
+            .filter(|&i| self.is_chr_accessed(i))
+            .count();
+
+        (
+            ratio(n_code, self.prg.len()),
+            ratio(n_data, self.prg.len()),
+            ratio(n_chr, self.chr.len()),
+        )
+    }
+}
+
+/// 0..len のうち pred を満たす連続区間を列挙する。
+pub(crate) fn ranges_where(len: usize, pred: impl Fn(usize) -> bool) -> Vec<Range<usize>> {
+    let mut res = Vec::new();
+
+    let mut start = None;
+    for i in 0..len {
+        match (pred(i), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                res.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        res.push(s..len);
+    }
+
+    res
+}
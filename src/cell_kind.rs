@@ -0,0 +1,334 @@
+// 地形セルの種類。
+//
+// アイテム、ゼグ、トラップはセル ID の範囲から分かる。
+// 砲台と破壊可能セルは、セル ID ごとの属性テーブルを読むゲームのルーチンを実行して分類する。
+// ルーチンとテーブルは次の手順で探す。
+//
+// 1. 地形展開ルーチン (validate.rs) の出力先を、地形のセル ID を持つ RAM とする。
+// 2. `LDA 地形,X/Y; TAX/TAY; LDA テーブル,X/Y` の形でセル ID を添字に PRG のテーブルを読む箇所を探す。
+//    描画用の既知テーブル (CellTile, CellPaletteIndex) は除く。
+// 3. その箇所を含むサブルーチンを、地形 RAM を各セル ID で埋めて実行する。
+//    - 地形 RAM のセルを書き換えるルーチン (当たり判定) が書き換えたセルを破壊可能とする。
+//    - 地形 RAM を書き換えないルーチンで、大半のセルと異なる RAM 状態になったセルを砲台とする。
+
+use std::collections::HashMap;
+
+use eyre::{bail, ensure};
+
+use crate::cpu::*;
+use crate::disasm::*;
+use crate::game::*;
+use crate::known_table::*;
+use crate::opcode::*;
+use crate::rom::*;
+use crate::validate::*;
+
+/// 属性テーブルを読むルーチン 1 回分の実行命令数の上限。
+const MAX_STEPS: usize = 100_000;
+
+/// ゼグのセル ID の末尾。
+const CELL_ZEG_LAST: u8 = CELL_ZEG_INI + 5;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CellKind {
+    /// 以下のどれでもないセル。
+    Plain,
+    /// 隠しアイテムが出現したセル。
+    Item,
+    /// stage 0 が出現済みのゼグ、1..=5 が埋まっているゼグ。
+    Zeg {
+        stage: u8,
+    },
+    Trap,
+    /// 当たり判定以外のルーチンが動作を変えるセル (地上の砲台)。
+    GroundTurret,
+    /// 当たり判定のルーチンが別のセルに書き換えるセル。
+    Destructible,
+}
+
+impl CellKind {
+    /// セル ID の範囲だけから分かる種類を返す。砲台と破壊可能セルは Plain になる。
+    pub fn from_cell_id(id: u8) -> Self {
+        match id {
+            0..=7 => Self::Item,
+            CELL_ZEG_INI..=CELL_ZEG_LAST => Self::Zeg {
+                stage: id - CELL_ZEG_INI,
+            },
+            CELL_TRAP => Self::Trap,
+            _ => Self::Plain,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Plain => "plain",
+            Self::Item => "item",
+            Self::Zeg { .. } => "zeg",
+            Self::Trap => "trap",
+            Self::GroundTurret => "turret",
+            Self::Destructible => "destructible",
+        }
+    }
+}
+
+/// セル属性テーブルと、それから求めた全セル ID の種類。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CellAttributes {
+    /// 属性テーブルのアドレス。
+    pub table: u16,
+    /// 地形のセル ID を持つ RAM のアドレス。
+    pub ground_row: u16,
+    /// 地形 RAM のセルを書き換えるルーチン。
+    pub hit_routines: Vec<u16>,
+    /// 属性テーブルを読み、地形 RAM を書き換えないルーチン。
+    pub other_routines: Vec<u16>,
+    kinds: Vec<CellKind>,
+}
+
+impl CellAttributes {
+    /// ROM から属性テーブルとそれを読むルーチンを探し、全セル ID を分類する。
+    pub fn from_rom(rom: &Rom) -> eyre::Result<Self> {
+        let tables = load_known_tables(rom);
+        let disasm = Disassembly::new(rom, &tables, &[]);
+
+        Self::from_disassembly(rom, &disasm)
+    }
+
+    fn from_disassembly(rom: &Rom, disasm: &Disassembly) -> eyre::Result<Self> {
+        let ground_row = locate_ground_row_routine_in(disasm)?.dst;
+
+        let mut readers = Vec::new();
+        for pc in disasm.code_addrs() {
+            if let Some(table) = cell_table_read(disasm, pc, ground_row) {
+                readers.push((pc, table));
+            }
+        }
+
+        let mut tables: Vec<u16> = readers.iter().map(|&(_, table)| table).collect();
+        tables.sort_unstable();
+        tables.dedup();
+        let table = match tables.as_slice() {
+            [table] => *table,
+            [] => bail!("cell attribute table not found"),
+            _ => bail!("cell attribute table is ambiguous: {:04X?}", tables),
+        };
+
+        let mut entries: Vec<u16> = readers
+            .iter()
+            .flat_map(|&(pc, _)| disasm.routines_containing(pc))
+            .collect();
+        entries.sort_unstable();
+        entries.dedup();
+
+        let mut hit_routines = Vec::new();
+        let mut other_routines = Vec::new();
+        let mut destructible = vec![false; usize::from(CELL_MAX) + 1];
+        let mut turret = vec![false; usize::from(CELL_MAX) + 1];
+        for entry in entries {
+            let rams = match run_with_cells(rom, entry, ground_row) {
+                Ok(rams) => rams,
+                Err(_) => continue,
+            };
+
+            let rewritten: Vec<bool> = rams
+                .iter()
+                .zip(0..=CELL_MAX)
+                .map(|(ram, id)| ground_row_cells(ram, ground_row).iter().any(|&c| c != id))
+                .collect();
+            if rewritten.contains(&true) {
+                hit_routines.push(entry);
+                for (dst, src) in destructible.iter_mut().zip(rewritten) {
+                    *dst |= src;
+                }
+            } else {
+                other_routines.push(entry);
+                let rams: Vec<Vec<u8>> = rams
+                    .into_iter()
+                    .map(|mut ram| {
+                        let start = usize::from(ground_row);
+                        ram[start..start + 20].fill(0);
+                        ram
+                    })
+                    .collect();
+                let common = most_common(&rams);
+                for (dst, ram) in turret.iter_mut().zip(&rams) {
+                    *dst |= ram != common;
+                }
+            }
+        }
+        ensure!(
+            !hit_routines.is_empty(),
+            "no routine reading cell attribute table ${:04X} rewrites ground cells",
+            table
+        );
+
+        let kinds = (0..=CELL_MAX)
+            .map(|id| {
+                let kind = CellKind::from_cell_id(id);
+                let i = usize::from(id);
+                match kind {
+                    CellKind::Plain if turret[i] => CellKind::GroundTurret,
+                    CellKind::Plain if destructible[i] => CellKind::Destructible,
+                    _ => kind,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            table,
+            ground_row,
+            hit_routines,
+            other_routines,
+            kinds,
+        })
+    }
+
+    /// セル ID の種類。
+    pub fn kind(&self, id: u8) -> CellKind {
+        self.kinds[usize::from(id)]
+    }
+}
+
+/// pc が `LDA テーブル,X/Y` で、添字が地形 RAM から読んだセル ID なら、テーブルのアドレスを返す。
+fn cell_table_read(disasm: &Disassembly, pc: u16, ground_row: u16) -> Option<u16> {
+    let (opcode, operand) = disasm.instruction(pc)?;
+    let index = indexed_by(opcode)?;
+    let table = u16::from_le_bytes([operand[0], operand[1]]);
+    if opcode.mnemonic != "LDA" || table < 0x8000 || disasm.table_at(table).is_some() {
+        return None;
+    }
+
+    let pc_transfer = disasm.previous_instruction(pc)?;
+    let (opcode, _) = disasm.instruction(pc_transfer)?;
+    let transfer = match index {
+        'X' => "TAX",
+        _ => "TAY",
+    };
+    if opcode.mnemonic != transfer {
+        return None;
+    }
+
+    let (opcode, operand) = disasm.instruction(disasm.previous_instruction(pc_transfer)?)?;
+    indexed_by(opcode)?;
+    let src = u16::from_le_bytes([operand[0], operand[1]]);
+    let is_ground_read = opcode.mnemonic == "LDA" && (ground_row..ground_row + 20).contains(&src);
+
+    is_ground_read.then_some(table)
+}
+
+/// 絶対インデックスアドレッシングなら添字レジスタを返す。
+fn indexed_by(opcode: Opcode) -> Option<char> {
+    match opcode.mode {
+        AddrMode::AbsoluteX => Some('X'),
+        AddrMode::AbsoluteY => Some('Y'),
+        _ => None,
+    }
+}
+
+/// 地形 RAM を各セル ID で埋めて entry を実行し、実行後の RAM をセル ID 順に返す。
+fn run_with_cells(rom: &Rom, entry: u16, ground_row: u16) -> eyre::Result<Vec<Vec<u8>>> {
+    (0..=CELL_MAX)
+        .map(|id| {
+            let mut bus = StubBus::new(&rom.prg);
+            let start = usize::from(ground_row);
+            bus.ram[start..start + 20].fill(id);
+            Cpu::new().call(&mut bus, entry, MAX_STEPS)?;
+            Ok(bus.ram)
+        })
+        .collect()
+}
+
+fn ground_row_cells(ram: &[u8], ground_row: u16) -> &[u8] {
+    let start = usize::from(ground_row);
+    &ram[start..start + 20]
+}
+
+/// 最も多く現れる RAM 状態を返す。
+fn most_common(rams: &[Vec<u8>]) -> &Vec<u8> {
+    let mut counts = HashMap::<&Vec<u8>, usize>::new();
+    for ram in rams {
+        *counts.entry(ram).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(ram, count)| (count, std::cmp::Reverse(ram)))
+        .unwrap()
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_cells_from_code() {
+        let mut prg = [0xEA; 0x8000]; // NOP で埋める
+        let code: &[(u16, &[u8])] = &[
+            // RESET: JSR $9000; JSR $9100; JSR $9200; JMP *
+            (
+                0x8000,
+                &[
+                    0x20, 0x00, 0x90, 0x20, 0x00, 0x91, 0x20, 0x00, 0x92, 0x4C, 0x09, 0x80,
+                ],
+            ),
+            // NMI: RTI
+            (0x8100, &[0x40]),
+            // 地形展開:
+            // LDY #0; loop: LDA ($10),Y; CMP #$DC; STA $0300,Y; INY; CPY #20; BNE loop; RTS
+            (
+                0x9000,
+                &[
+                    0xA0, 0x00, 0xB1, 0x10, 0xC9, 0xDC, 0x99, 0x00, 0x03, 0xC8, 0xC0, 0x14, 0xD0,
+                    0xF4, 0x60,
+                ],
+            ),
+            // 当たり判定:
+            // LDX #5; LDA $0300,X; TAY; LDA $A000,Y; AND #1; BEQ done; LDA #$90; STA $0300,X
+            // done: RTS
+            (
+                0x9100,
+                &[
+                    0xA2, 0x05, 0xBD, 0x00, 0x03, 0xA8, 0xB9, 0x00, 0xA0, 0x29, 0x01, 0xF0, 0x05,
+                    0xA9, 0x90, 0x9D, 0x00, 0x03, 0x60,
+                ],
+            ),
+            // 砲台の発射:
+            // LDX #5; LDA $0300,X; TAY; LDA $A000,Y; AND #2; BEQ done; INC $50
+            // done: RTS
+            (
+                0x9200,
+                &[
+                    0xA2, 0x05, 0xBD, 0x00, 0x03, 0xA8, 0xB9, 0x00, 0xA0, 0x29, 0x02, 0xF0, 0x02,
+                    0xE6, 0x50, 0x60,
+                ],
+            ),
+            (0xFFFA, &[0x00, 0x81, 0x00, 0x80]),
+        ];
+        for &(addr, bytes) in code {
+            prg[prg_offset(addr)..][..bytes.len()].copy_from_slice(bytes);
+        }
+        // 属性テーブル: bit0 が破壊可能、bit1 が砲台。
+        let table = prg_offset(0xA000);
+        prg[table..=table + usize::from(CELL_MAX)].fill(0);
+        prg[table + 0x20] = 1;
+        prg[table + 0x30] = 3;
+        prg[table + 0x40] = 2;
+        let rom = Rom {
+            prg,
+            chr: [0; 0x8000],
+        };
+        let disasm = Disassembly::new(&rom, &[], &[]);
+
+        let attrs = CellAttributes::from_disassembly(&rom, &disasm).unwrap();
+        assert_eq!(attrs.table, 0xA000);
+        assert_eq!(attrs.ground_row, 0x0300);
+        assert_eq!(attrs.hit_routines, [0x9100]);
+        assert_eq!(attrs.other_routines, [0x9200]);
+        assert_eq!(attrs.kind(0x00), CellKind::Item);
+        assert_eq!(attrs.kind(0x20), CellKind::Destructible);
+        assert_eq!(attrs.kind(0x30), CellKind::GroundTurret);
+        assert_eq!(attrs.kind(0x40), CellKind::GroundTurret);
+        assert_eq!(attrs.kind(0x50), CellKind::Plain);
+        assert_eq!(attrs.kind(CELL_TRAP), CellKind::Trap);
+    }
+}
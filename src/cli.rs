@@ -0,0 +1,51 @@
+// コマンドライン引数の解析。各 bin の structopt から使う。
+
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+
+/// 16 進数 ("0x" または "$" の接頭辞は省略可)。
+pub fn parse_hex_u16(s: &str) -> eyre::Result<u16> {
+    Ok(u16::from_str_radix(
+        s.trim_start_matches("0x").trim_start_matches('$'),
+        16,
+    )?)
+}
+
+/// 8 bit の 16 進数 ("0x" または "$" の接頭辞は省略可)。
+pub fn parse_hex_u8(s: &str) -> eyre::Result<u8> {
+    Ok(u8::try_from(parse_hex_u16(s)?)?)
+}
+
+/// PRG 内のアドレス ($8000 以上) を 16 進数で。
+pub fn parse_prg_addr(s: &str) -> eyre::Result<u16> {
+    let addr = parse_hex_u16(s)?;
+    eyre::ensure!(addr >= 0x8000, "not PRG address: {:#06X}", addr);
+
+    Ok(addr)
+}
+
+/// 既存のディレクトリ。
+pub fn parse_directory(s: &OsStr) -> Result<PathBuf, OsString> {
+    let dir = PathBuf::from(s);
+
+    dir.is_dir().then_some(dir).ok_or_else(|| s.to_owned())
+}
+
+/// ステージ番号 1..=16。
+pub fn parse_stage(s_stage: &str) -> eyre::Result<u8> {
+    const RANGE: std::ops::RangeInclusive<u8> = 1..=16;
+
+    let stage: u8 = s_stage.parse()?;
+    eyre::ensure!(RANGE.contains(&stage), "stage must be within {:?}", RANGE);
+
+    Ok(stage)
+}
+
+/// 地形の前半 (0) /後半 (1)。
+pub fn parse_half(s_half: &str) -> eyre::Result<usize> {
+    let half: usize = s_half.parse()?;
+    eyre::ensure!(half < 2, "half must be 0 or 1");
+
+    Ok(half)
+}
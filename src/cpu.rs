@@ -0,0 +1,405 @@
+use eyre::{bail, eyre};
+
+use crate::opcode::*;
+
+const FLAG_C: u8 = 1 << 0;
+const FLAG_Z: u8 = 1 << 1;
+const FLAG_I: u8 = 1 << 2;
+const FLAG_D: u8 = 1 << 3;
+const FLAG_B: u8 = 1 << 4;
+const FLAG_U: u8 = 1 << 5;
+const FLAG_V: u8 = 1 << 6;
+const FLAG_N: u8 = 1 << 7;
+
+/// Cpu::call() の戻り先として積むアドレス。RAM 上の何もない場所なら何でもよい。
+const ADDR_RETURN_SENTINEL: u16 = 0x07FF;
+
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+}
+
+/// 命令のオペランド。
+#[derive(Clone, Copy, Debug)]
+enum Operand {
+    None,
+    Accumulator,
+    Immediate(u8),
+    Address(u16),
+}
+
+/// 6502 インタプリタ。公式命令のみサポートし、サイクル数は数えない。
+/// (NES の 2A03 なので BCD モードは無視する)
+#[derive(Clone, Debug, Default)]
+pub struct Cpu {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+    pub pc: u16,
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        Self {
+            s: 0xFD,
+            p: FLAG_I | FLAG_U,
+            ..Default::default()
+        }
+    }
+
+    /// サブルーチン addr を呼び出し、RTS で戻ってくるまで実行する。
+    /// max_steps 命令以内に戻らなければエラーとする。
+    pub fn call<B: Bus>(&mut self, bus: &mut B, addr: u16, max_steps: usize) -> eyre::Result<()> {
+        let s_orig = self.s;
+        self.push_word(bus, ADDR_RETURN_SENTINEL - 1);
+        self.pc = addr;
+
+        for _ in 0..max_steps {
+            self.step(bus)?;
+            if self.pc == ADDR_RETURN_SENTINEL && self.s == s_orig {
+                return Ok(());
+            }
+        }
+
+        bail!(
+            "routine {:#06X} did not return in {} steps",
+            addr,
+            max_steps
+        )
+    }
+
+    /// 1 命令実行する。
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> eyre::Result<()> {
+        let pc = self.pc;
+        let op = bus.read(pc);
+        let opcode = decode_opcode(op)
+            .ok_or_else(|| eyre!("unofficial opcode {:#04X} at {:#06X}", op, pc))?;
+
+        let operand = self.fetch_operand(bus, opcode.mode);
+        self.pc = pc.wrapping_add(opcode.size() as u16);
+
+        match opcode.mnemonic {
+            "ADC" => {
+                let m = self.load(bus, operand);
+                self.add_with_carry(m);
+            }
+            "SBC" => {
+                let m = self.load(bus, operand);
+                self.add_with_carry(!m);
+            }
+            "AND" => {
+                self.a &= self.load(bus, operand);
+                self.set_nz(self.a);
+            }
+            "ORA" => {
+                self.a |= self.load(bus, operand);
+                self.set_nz(self.a);
+            }
+            "EOR" => {
+                self.a ^= self.load(bus, operand);
+                self.set_nz(self.a);
+            }
+            "ASL" => self.modify(bus, operand, |cpu, m| {
+                cpu.set_flag(FLAG_C, (m & 0x80) != 0);
+                m << 1
+            }),
+            "LSR" => self.modify(bus, operand, |cpu, m| {
+                cpu.set_flag(FLAG_C, (m & 1) != 0);
+                m >> 1
+            }),
+            "ROL" => self.modify(bus, operand, |cpu, m| {
+                let c = cpu.p & FLAG_C;
+                cpu.set_flag(FLAG_C, (m & 0x80) != 0);
+                (m << 1) | c
+            }),
+            "ROR" => self.modify(bus, operand, |cpu, m| {
+                let c = cpu.p & FLAG_C;
+                cpu.set_flag(FLAG_C, (m & 1) != 0);
+                (m >> 1) | (c << 7)
+            }),
+            "INC" => self.modify(bus, operand, |_, m| m.wrapping_add(1)),
+            "DEC" => self.modify(bus, operand, |_, m| m.wrapping_sub(1)),
+            "BIT" => {
+                let m = self.load(bus, operand);
+                self.set_flag(FLAG_Z, (self.a & m) == 0);
+                self.set_flag(FLAG_N, (m & 0x80) != 0);
+                self.set_flag(FLAG_V, (m & 0x40) != 0);
+            }
+            "CMP" => {
+                let m = self.load(bus, operand);
+                self.compare(self.a, m);
+            }
+            "CPX" => {
+                let m = self.load(bus, operand);
+                self.compare(self.x, m);
+            }
+            "CPY" => {
+                let m = self.load(bus, operand);
+                self.compare(self.y, m);
+            }
+            "BPL" => self.branch(operand, (self.p & FLAG_N) == 0),
+            "BMI" => self.branch(operand, (self.p & FLAG_N) != 0),
+            "BVC" => self.branch(operand, (self.p & FLAG_V) == 0),
+            "BVS" => self.branch(operand, (self.p & FLAG_V) != 0),
+            "BCC" => self.branch(operand, (self.p & FLAG_C) == 0),
+            "BCS" => self.branch(operand, (self.p & FLAG_C) != 0),
+            "BNE" => self.branch(operand, (self.p & FLAG_Z) == 0),
+            "BEQ" => self.branch(operand, (self.p & FLAG_Z) != 0),
+            "JMP" => self.pc = operand_addr(operand),
+            "JSR" => {
+                self.push_word(bus, self.pc.wrapping_sub(1));
+                self.pc = operand_addr(operand);
+            }
+            "RTS" => self.pc = self.pull_word(bus).wrapping_add(1),
+            "BRK" => {
+                self.push_word(bus, pc.wrapping_add(2));
+                self.push(bus, self.p | FLAG_B | FLAG_U);
+                self.p |= FLAG_I;
+                self.pc = u16::from(bus.read(0xFFFE)) | (u16::from(bus.read(0xFFFF)) << 8);
+            }
+            "RTI" => {
+                self.p = (self.pull(bus) & !FLAG_B) | FLAG_U;
+                self.pc = self.pull_word(bus);
+            }
+            "CLC" => self.set_flag(FLAG_C, false),
+            "SEC" => self.set_flag(FLAG_C, true),
+            "CLI" => self.set_flag(FLAG_I, false),
+            "SEI" => self.set_flag(FLAG_I, true),
+            "CLV" => self.set_flag(FLAG_V, false),
+            "CLD" => self.set_flag(FLAG_D, false),
+            "SED" => self.set_flag(FLAG_D, true),
+            "LDA" => {
+                self.a = self.load(bus, operand);
+                self.set_nz(self.a);
+            }
+            "LDX" => {
+                self.x = self.load(bus, operand);
+                self.set_nz(self.x);
+            }
+            "LDY" => {
+                self.y = self.load(bus, operand);
+                self.set_nz(self.y);
+            }
+            "STA" => bus.write(operand_addr(operand), self.a),
+            "STX" => bus.write(operand_addr(operand), self.x),
+            "STY" => bus.write(operand_addr(operand), self.y),
+            "INX" => {
+                self.x = self.x.wrapping_add(1);
+                self.set_nz(self.x);
+            }
+            "INY" => {
+                self.y = self.y.wrapping_add(1);
+                self.set_nz(self.y);
+            }
+            "DEX" => {
+                self.x = self.x.wrapping_sub(1);
+                self.set_nz(self.x);
+            }
+            "DEY" => {
+                self.y = self.y.wrapping_sub(1);
+                self.set_nz(self.y);
+            }
+            "TAX" => {
+                self.x = self.a;
+                self.set_nz(self.x);
+            }
+            "TAY" => {
+                self.y = self.a;
+                self.set_nz(self.y);
+            }
+            "TXA" => {
+                self.a = self.x;
+                self.set_nz(self.a);
+            }
+            "TYA" => {
+                self.a = self.y;
+                self.set_nz(self.a);
+            }
+            "TSX" => {
+                self.x = self.s;
+                self.set_nz(self.x);
+            }
+            "TXS" => self.s = self.x,
+            "PHA" => self.push(bus, self.a),
+            "PHP" => self.push(bus, self.p | FLAG_B | FLAG_U),
+            "PLA" => {
+                self.a = self.pull(bus);
+                self.set_nz(self.a);
+            }
+            "PLP" => self.p = (self.pull(bus) & !FLAG_B) | FLAG_U,
+            "NOP" => {}
+            _ => unreachable!("unhandled mnemonic: {}", opcode.mnemonic),
+        }
+
+        Ok(())
+    }
+
+    fn fetch_operand<B: Bus>(&self, bus: &mut B, mode: AddrMode) -> Operand {
+        let pc = self.pc;
+        let byte = |bus: &mut B| bus.read(pc.wrapping_add(1));
+        let word = |bus: &mut B| {
+            u16::from(bus.read(pc.wrapping_add(1))) | (u16::from(bus.read(pc.wrapping_add(2))) << 8)
+        };
+        let zp_word = |bus: &mut B, zp: u8| {
+            u16::from(bus.read(u16::from(zp)))
+                | (u16::from(bus.read(u16::from(zp.wrapping_add(1)))) << 8)
+        };
+
+        match mode {
+            AddrMode::Implied => Operand::None,
+            AddrMode::Accumulator => Operand::Accumulator,
+            AddrMode::Immediate => Operand::Immediate(byte(bus)),
+            AddrMode::ZeroPage => Operand::Address(u16::from(byte(bus))),
+            AddrMode::ZeroPageX => Operand::Address(u16::from(byte(bus).wrapping_add(self.x))),
+            AddrMode::ZeroPageY => Operand::Address(u16::from(byte(bus).wrapping_add(self.y))),
+            AddrMode::Absolute => Operand::Address(word(bus)),
+            AddrMode::AbsoluteX => Operand::Address(word(bus).wrapping_add(u16::from(self.x))),
+            AddrMode::AbsoluteY => Operand::Address(word(bus).wrapping_add(u16::from(self.y))),
+            AddrMode::Indirect => {
+                // ページ境界をまたがないバグも再現する。
+                let ptr = word(bus);
+                let ptr_hi = (ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF);
+                Operand::Address(u16::from(bus.read(ptr)) | (u16::from(bus.read(ptr_hi)) << 8))
+            }
+            AddrMode::IndirectX => {
+                let zp = byte(bus).wrapping_add(self.x);
+                Operand::Address(zp_word(bus, zp))
+            }
+            AddrMode::IndirectY => {
+                let zp = byte(bus);
+                Operand::Address(zp_word(bus, zp).wrapping_add(u16::from(self.y)))
+            }
+            AddrMode::Relative => {
+                let rel = byte(bus) as i8;
+                Operand::Address(pc.wrapping_add(2).wrapping_add(rel as u16))
+            }
+        }
+    }
+
+    fn load<B: Bus>(&self, bus: &mut B, operand: Operand) -> u8 {
+        match operand {
+            Operand::Accumulator => self.a,
+            Operand::Immediate(value) => value,
+            Operand::Address(addr) => bus.read(addr),
+            Operand::None => unreachable!(),
+        }
+    }
+
+    fn modify<B: Bus>(
+        &mut self,
+        bus: &mut B,
+        operand: Operand,
+        f: impl FnOnce(&mut Self, u8) -> u8,
+    ) {
+        let m = self.load(bus, operand);
+        let res = f(self, m);
+        self.set_nz(res);
+        match operand {
+            Operand::Accumulator => self.a = res,
+            Operand::Address(addr) => bus.write(addr, res),
+            _ => unreachable!(),
+        }
+    }
+
+    fn add_with_carry(&mut self, m: u8) {
+        let t = u16::from(self.a) + u16::from(m) + u16::from(self.p & FLAG_C);
+        let res = t as u8;
+        self.set_flag(FLAG_C, t > 0xFF);
+        self.set_flag(FLAG_V, ((self.a ^ res) & (m ^ res) & 0x80) != 0);
+        self.a = res;
+        self.set_nz(res);
+    }
+
+    fn compare(&mut self, r: u8, m: u8) {
+        self.set_flag(FLAG_C, r >= m);
+        self.set_nz(r.wrapping_sub(m));
+    }
+
+    fn branch(&mut self, operand: Operand, cond: bool) {
+        if cond {
+            self.pc = operand_addr(operand);
+        }
+    }
+
+    fn push<B: Bus>(&mut self, bus: &mut B, value: u8) {
+        bus.write(0x100 | u16::from(self.s), value);
+        self.s = self.s.wrapping_sub(1);
+    }
+
+    fn pull<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        self.s = self.s.wrapping_add(1);
+        bus.read(0x100 | u16::from(self.s))
+    }
+
+    fn push_word<B: Bus>(&mut self, bus: &mut B, value: u16) {
+        self.push(bus, (value >> 8) as u8);
+        self.push(bus, value as u8);
+    }
+
+    fn pull_word<B: Bus>(&mut self, bus: &mut B) -> u16 {
+        let lo = self.pull(bus);
+        let hi = self.pull(bus);
+        u16::from(lo) | (u16::from(hi) << 8)
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.p |= flag;
+        } else {
+            self.p &= !flag;
+        }
+    }
+
+    fn set_nz(&mut self, value: u8) {
+        self.set_flag(FLAG_Z, value == 0);
+        self.set_flag(FLAG_N, (value & 0x80) != 0);
+    }
+}
+
+fn operand_addr(operand: Operand) -> u16 {
+    match operand {
+        Operand::Address(addr) => addr,
+        _ => unreachable!(),
+    }
+}
+
+/// PRG と内蔵 RAM だけを持つ最小限のメモリマップ。
+///
+/// PPU は常に VBlank 中に見せかける。APU への書き込みは記録する。
+#[derive(Clone, Debug)]
+pub struct StubBus {
+    prg: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub apu_writes: Vec<(u16, u8)>,
+}
+
+impl StubBus {
+    pub fn new(prg: &[u8]) -> Self {
+        Self {
+            prg: prg.to_vec(),
+            ram: vec![0; 0x800],
+            apu_writes: Vec::new(),
+        }
+    }
+}
+
+impl Bus for StubBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.ram[usize::from(addr & 0x7FF)],
+            0x2002 => 0x80,
+            0x8000..=0xFFFF => self.prg[usize::from(addr - 0x8000)],
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram[usize::from(addr & 0x7FF)] = value,
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu_writes.push((addr, value)),
+            _ => {}
+        }
+    }
+}
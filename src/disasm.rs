@@ -0,0 +1,462 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use byteorder::{ByteOrder, LE};
+
+use crate::known_table::*;
+use crate::opcode::*;
+use crate::rom::*;
+
+const ADDR_VECTORS: u16 = 0xFFFA;
+
+const HW_REGISTERS: &[(u16, &str)] = &[
+    (0x2000, "PPUCTRL"),
+    (0x2001, "PPUMASK"),
+    (0x2002, "PPUSTATUS"),
+    (0x2003, "OAMADDR"),
+    (0x2004, "OAMDATA"),
+    (0x2005, "PPUSCROLL"),
+    (0x2006, "PPUADDR"),
+    (0x2007, "PPUDATA"),
+    (0x4000, "SQ1_VOL"),
+    (0x4001, "SQ1_SWEEP"),
+    (0x4002, "SQ1_LO"),
+    (0x4003, "SQ1_HI"),
+    (0x4004, "SQ2_VOL"),
+    (0x4005, "SQ2_SWEEP"),
+    (0x4006, "SQ2_LO"),
+    (0x4007, "SQ2_HI"),
+    (0x4008, "TRI_LINEAR"),
+    (0x400A, "TRI_LO"),
+    (0x400B, "TRI_HI"),
+    (0x400C, "NOISE_VOL"),
+    (0x400E, "NOISE_LO"),
+    (0x400F, "NOISE_HI"),
+    (0x4010, "DMC_FREQ"),
+    (0x4011, "DMC_RAW"),
+    (0x4012, "DMC_START"),
+    (0x4013, "DMC_LEN"),
+    (0x4014, "OAMDMA"),
+    (0x4015, "SND_CHN"),
+    (0x4016, "JOY1"),
+    (0x4017, "JOY2"),
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ByteKind {
+    Unknown,
+    Data,
+    Code(Opcode),
+    Operand,
+}
+
+/// PRG の逆アセンブル結果。
+///
+/// 割り込みベクタから到達可能なコードを辿り、既知テーブルはデータとして扱う。
+/// 到達できなかった部分は全てデータとして出力される。
+#[derive(Debug)]
+pub struct Disassembly {
+    prg: Vec<u8>,
+    kinds: Vec<ByteKind>,
+    labels: BTreeMap<u16, String>,
+    tables: Vec<KnownTable>,
+    xrefs: BTreeMap<u16, BTreeSet<u16>>, // テーブル先頭アドレス -> 参照元命令アドレス
+}
+
+impl Disassembly {
+    pub fn new(rom: &Rom, tables: &[KnownTable], extra_entrypoints: &[u16]) -> Self {
+        let mut this = Self {
+            prg: rom.prg.to_vec(),
+            kinds: vec![ByteKind::Unknown; rom.prg.len()],
+            labels: BTreeMap::new(),
+            tables: tables.to_vec(),
+            xrefs: BTreeMap::new(),
+        };
+
+        for table in tables {
+            for i in table.range() {
+                this.kinds[i] = ByteKind::Data;
+            }
+            this.labels
+                .entry(table.addr)
+                .or_insert_with(|| table.name.clone());
+        }
+        for i in prg_offset(ADDR_VECTORS)..rom.prg.len() {
+            this.kinds[i] = ByteKind::Data;
+        }
+        this.labels.insert(ADDR_VECTORS, "Vectors".to_owned());
+
+        let mut entrypoints = Vec::new();
+        for (i, name) in ["Nmi", "Reset", "Irq"].iter().enumerate() {
+            let addr = this.read_word(ADDR_VECTORS + 2 * i as u16);
+            this.labels
+                .entry(addr)
+                .or_insert_with(|| (*name).to_owned());
+            entrypoints.push(addr);
+        }
+        for &addr in extra_entrypoints {
+            this.labels
+                .entry(addr)
+                .or_insert_with(|| format!("Entry_{:04X}", addr));
+            entrypoints.push(addr);
+        }
+
+        this.trace(entrypoints);
+        this.collect_data_refs();
+
+        this
+    }
+
+    /// 指定アドレスを含む既知テーブルを返す。
+    pub fn table_at(&self, addr: u16) -> Option<&KnownTable> {
+        self.tables.iter().find(|table| table.contains(addr))
+    }
+
+    /// 命令先頭として認識されたアドレスを返す。
+    pub fn code_addrs(&self) -> impl Iterator<Item = u16> + '_ {
+        self.kinds
+            .iter()
+            .enumerate()
+            .filter(|(_, kind)| matches!(kind, ByteKind::Code(_)))
+            .map(|(i, _)| 0x8000 + i as u16)
+    }
+
+    /// 各既知テーブルを参照している命令のアドレスを返す。
+    pub fn xrefs(&self, table: &KnownTable) -> impl Iterator<Item = u16> + '_ {
+        self.xrefs.get(&table.addr).into_iter().flatten().copied()
+    }
+
+    /// 割り込みベクタ (0: NMI, 1: RESET, 2: IRQ) の飛び先を返す。
+    pub fn vector(&self, i: u16) -> u16 {
+        self.read_word(ADDR_VECTORS + 2 * i)
+    }
+
+    /// 命令として認識されていれば、そのオペコードとオペランドのバイト列を返す。
+    pub fn instruction(&self, addr: u16) -> Option<(Opcode, &[u8])> {
+        if addr < 0x8000 {
+            return None;
+        }
+        match self.kind(addr) {
+            ByteKind::Code(opcode) => {
+                let offset = prg_offset(addr);
+                Some((opcode, &self.prg[offset + 1..offset + opcode.size()]))
+            }
+            _ => None,
+        }
+    }
+
+    /// addr が命令 (オペランドを含む) の一部として認識されているか。
+    pub fn is_code(&self, addr: u16) -> bool {
+        addr >= 0x8000 && matches!(self.kind(addr), ByteKind::Code(_) | ByteKind::Operand)
+    }
+
+    /// addr の直前で終わる命令があれば、そのアドレスを返す。
+    pub fn previous_instruction(&self, addr: u16) -> Option<u16> {
+        (1..=3).find_map(|size| {
+            let pc = addr.checked_sub(size)?;
+            let (opcode, _) = self.instruction(pc)?;
+            (opcode.size() == usize::from(size)).then_some(pc)
+        })
+    }
+
+    /// JSR 命令の (命令アドレス, 呼び出し先) を返す。
+    pub fn calls(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.code_addrs()
+            .filter_map(move |pc| match self.instruction(pc) {
+                Some((opcode, operand)) if opcode.mnemonic == "JSR" => {
+                    Some((pc, LE::read_u16(operand)))
+                }
+                _ => None,
+            })
+    }
+
+    /// entry から JSR の先には入らずに到達できる命令のアドレスを返す。
+    /// 分岐と JMP (絶対) は辿り、RTS/RTI/JMP (間接) で止まる。
+    pub fn routine_body(&self, entry: u16) -> BTreeSet<u16> {
+        let mut body = BTreeSet::new();
+        let mut worklist = vec![entry];
+
+        while let Some(mut pc) = worklist.pop() {
+            while let Some((opcode, operand)) = self.instruction(pc) {
+                if !body.insert(pc) {
+                    break;
+                }
+                let next = pc.wrapping_add(opcode.size() as u16);
+                match (opcode.mnemonic, opcode.mode) {
+                    ("JMP", AddrMode::Absolute) => {
+                        pc = LE::read_u16(operand);
+                        continue;
+                    }
+                    (_, AddrMode::Relative) => worklist.push(branch_target(next, operand[0])),
+                    _ => {}
+                }
+                if opcode.is_terminal() {
+                    break;
+                }
+                pc = next;
+            }
+        }
+
+        body
+    }
+
+    /// addr を本体に含むサブルーチン (JSR の呼び出し先) を返す。
+    pub fn routines_containing(&self, addr: u16) -> Vec<u16> {
+        let entries: BTreeSet<u16> = self.calls().map(|(_, dst)| dst).collect();
+        entries
+            .into_iter()
+            .filter(|&entry| self.routine_body(entry).contains(&addr))
+            .collect()
+    }
+
+    fn byte(&self, addr: u16) -> u8 {
+        self.prg[prg_offset(addr)]
+    }
+
+    fn read_word(&self, addr: u16) -> u16 {
+        LE::read_u16(&self.prg[prg_offset(addr)..])
+    }
+
+    fn kind(&self, addr: u16) -> ByteKind {
+        self.kinds[prg_offset(addr)]
+    }
+
+    fn trace(&mut self, mut worklist: Vec<u16>) {
+        while let Some(addr) = worklist.pop() {
+            let mut pc = addr;
+            loop {
+                if pc < 0x8000 || self.kind(pc) != ByteKind::Unknown {
+                    break;
+                }
+
+                let opcode = match decode_opcode(self.byte(pc)) {
+                    Some(opcode) => opcode,
+                    None => break,
+                };
+                let end = u32::from(pc) + opcode.size() as u32;
+                if end > 0x10000 {
+                    break;
+                }
+                let operand_ok =
+                    (1..opcode.size()).all(|i| self.kind(pc + i as u16) == ByteKind::Unknown);
+                if !operand_ok {
+                    break;
+                }
+
+                self.kinds[prg_offset(pc)] = ByteKind::Code(opcode);
+                for i in 1..opcode.size() {
+                    self.kinds[prg_offset(pc) + i] = ByteKind::Operand;
+                }
+
+                let next = pc.wrapping_add(opcode.size() as u16);
+                match (opcode.mnemonic, opcode.mode) {
+                    ("JSR", _) => {
+                        let dst = self.read_word(pc + 1);
+                        self.add_code_label(dst, "Sub");
+                        worklist.push(dst);
+                    }
+                    ("JMP", AddrMode::Absolute) => {
+                        let dst = self.read_word(pc + 1);
+                        self.add_code_label(dst, "L");
+                        worklist.push(dst);
+                    }
+                    (_, AddrMode::Relative) => {
+                        let dst = branch_target(next, self.byte(pc + 1));
+                        self.add_code_label(dst, "L");
+                        worklist.push(dst);
+                    }
+                    _ => {}
+                }
+
+                if opcode.is_terminal() || next < pc {
+                    break;
+                }
+                pc = next;
+            }
+        }
+    }
+
+    fn add_code_label(&mut self, addr: u16, prefix: &str) {
+        if (0x8000..ADDR_VECTORS).contains(&addr) {
+            self.labels
+                .entry(addr)
+                .or_insert_with(|| format!("{}_{:04X}", prefix, addr));
+        }
+    }
+
+    /// コードから参照されている PRG 内データにラベルを付け、既知テーブルの参照元を記録する。
+    fn collect_data_refs(&mut self) {
+        let code_addrs: Vec<u16> = self.code_addrs().collect();
+        for pc in code_addrs {
+            let opcode = match self.kind(pc) {
+                ByteKind::Code(opcode) => opcode,
+                _ => unreachable!(),
+            };
+            if !matches!(
+                opcode.mode,
+                AddrMode::Absolute | AddrMode::AbsoluteX | AddrMode::AbsoluteY | AddrMode::Indirect
+            ) {
+                continue;
+            }
+            let dst = self.read_word(pc + 1);
+            if !(0x8000..ADDR_VECTORS).contains(&dst) {
+                continue;
+            }
+
+            if let Some(table) = self.table_at(dst) {
+                let table_addr = table.addr;
+                self.xrefs.entry(table_addr).or_default().insert(pc);
+            } else if !matches!(self.kind(dst), ByteKind::Code(_) | ByteKind::Operand) {
+                self.labels
+                    .entry(dst)
+                    .or_insert_with(|| format!("D_{:04X}", dst));
+            }
+        }
+    }
+
+    /// アドレスをオペランド表記に変換する。
+    fn operand_expr(&self, addr: u16) -> String {
+        if let Some(label) = self.labels.get(&addr) {
+            return label.clone();
+        }
+        if let Some(table) = self.table_at(addr) {
+            return format!("{}+{}", self.labels[&table.addr], addr - table.addr);
+        }
+        if let Some((_, name)) = HW_REGISTERS.iter().find(|(a, _)| *a == addr) {
+            return (*name).to_owned();
+        }
+        format!("${:04X}", addr)
+    }
+
+    fn format_instruction(&self, pc: u16, opcode: Opcode) -> String {
+        let mnemonic = opcode.mnemonic.to_lowercase();
+        let b1 = if opcode.size() >= 2 {
+            self.byte(pc + 1)
+        } else {
+            0
+        };
+        let word = if opcode.size() >= 3 {
+            self.read_word(pc + 1)
+        } else {
+            0
+        };
+
+        // ゼロページに収まる絶対アドレスは ca65 が勝手に短縮しないよう "a:" を付ける。
+        let abs = |addr: u16| {
+            if addr < 0x100 {
+                format!("a:${:04X}", addr)
+            } else {
+                self.operand_expr(addr)
+            }
+        };
+
+        let operand = match opcode.mode {
+            AddrMode::Implied => String::new(),
+            AddrMode::Accumulator => "a".to_owned(),
+            AddrMode::Immediate => format!("#${:02X}", b1),
+            AddrMode::ZeroPage => format!("${:02X}", b1),
+            AddrMode::ZeroPageX => format!("${:02X},x", b1),
+            AddrMode::ZeroPageY => format!("${:02X},y", b1),
+            AddrMode::Absolute => abs(word),
+            AddrMode::AbsoluteX => format!("{},x", abs(word)),
+            AddrMode::AbsoluteY => format!("{},y", abs(word)),
+            AddrMode::Indirect => format!("({})", abs(word)),
+            AddrMode::IndirectX => format!("(${:02X},x)", b1),
+            AddrMode::IndirectY => format!("(${:02X}),y", b1),
+            AddrMode::Relative => {
+                let dst = branch_target(pc.wrapping_add(2), b1);
+                self.operand_expr(dst)
+            }
+        };
+
+        if operand.is_empty() {
+            mnemonic
+        } else {
+            format!("{} {}", mnemonic, operand)
+        }
+    }
+
+    /// ca65 で再アセンブル可能なソースを出力する。
+    pub fn write_ca65<W: Write>(&self, mut wtr: W) -> eyre::Result<()> {
+        writeln!(wtr, "; Star Soldier PRG disassembly")?;
+        writeln!(wtr, ".setcpu \"6502\"")?;
+        writeln!(wtr)?;
+
+        for (addr, name) in HW_REGISTERS {
+            writeln!(wtr, "{} = ${:04X}", name, addr)?;
+        }
+        writeln!(wtr)?;
+
+        // 命令の途中を指すラベルは定数として定義する。
+        for (&addr, label) in &self.labels {
+            if self.kind(addr) == ByteKind::Operand {
+                writeln!(wtr, "{} = ${:04X}", label, addr)?;
+            }
+        }
+        writeln!(wtr)?;
+
+        writeln!(wtr, ".org $8000")?;
+
+        let mut addr: u32 = 0x8000;
+        while addr < 0x10000 {
+            let pc = addr as u16;
+
+            if let Some(label) = self.labels.get(&pc) {
+                writeln!(wtr)?;
+                if let Some(table) = self.tables.iter().find(|table| table.addr == pc) {
+                    writeln!(wtr, "; {} bytes", table.len)?;
+                    let xrefs: Vec<_> = self.xrefs(table).map(|a| format!("${:04X}", a)).collect();
+                    if !xrefs.is_empty() {
+                        writeln!(wtr, "; referenced from {}", xrefs.join(" "))?;
+                    }
+                }
+                writeln!(wtr, "{}:", label)?;
+            }
+
+            if pc == ADDR_VECTORS {
+                for i in 0..3 {
+                    let dst = self.read_word(ADDR_VECTORS + 2 * i);
+                    writeln!(wtr, "    .addr {}", self.operand_expr(dst))?;
+                }
+                break;
+            }
+
+            match self.kind(pc) {
+                ByteKind::Code(opcode) => {
+                    writeln!(
+                        wtr,
+                        "    {:<24}; {:04X}",
+                        self.format_instruction(pc, opcode),
+                        pc
+                    )?;
+                    addr += opcode.size() as u32;
+                }
+                ByteKind::Unknown | ByteKind::Data => {
+                    let mut bytes = vec![self.byte(pc)];
+                    let mut end = addr + 1;
+                    while end < 0x10000 && bytes.len() < 16 {
+                        let a = end as u16;
+                        if a == ADDR_VECTORS
+                            || self.labels.contains_key(&a)
+                            || matches!(self.kind(a), ByteKind::Code(_))
+                        {
+                            break;
+                        }
+                        bytes.push(self.byte(a));
+                        end += 1;
+                    }
+                    let s: Vec<_> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+                    writeln!(wtr, "    .byte {}", s.join(","))?;
+                    addr = end;
+                }
+                ByteKind::Operand => unreachable!(),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn branch_target(next: u16, rel: u8) -> u16 {
+    next.wrapping_add(rel as i8 as u16)
+}
@@ -0,0 +1,130 @@
+// 敵グループごとの資料カード画像。
+
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_hollow_rect_mut};
+use imageproc::rect::Rect;
+
+use crate::*;
+
+const CARD_WIDTH: u32 = 440;
+const CARD_HEIGHT: u32 = 224;
+
+const SPRITE_SCALE: u32 = 2;
+
+const COLOR_BG: Rgba<u8> = Rgba([0x20, 0x20, 0x20, 0xFF]);
+const COLOR_SPRITE_BG: Rgba<u8> = Rgba([0, 0, 0, 0xFF]);
+const COLOR_TEXT: Rgba<u8> = Rgba([0xFF, 0xFF, 0xFF, 0xFF]);
+const COLOR_FRAME: Rgba<u8> = Rgba([0x80, 0x80, 0x80, 0xFF]);
+const COLOR_SPAWN: Rgba<u8> = Rgba([0xFF, 0x40, 0x40, 0xFF]);
+
+/// 敵グループのカード画像を生成する。
+///
+/// 名前と各パラメータ、1 周目/2 周目のメタスプライト、初期位置を描く。
+pub fn enemy_card_image(game: &Game, group: &EnemyGroup, sprite_ids: &[u8]) -> RgbaImage {
+    let mut img = RgbaImage::from_pixel(CARD_WIDTH, CARD_HEIGHT, COLOR_BG);
+
+    let font_title = Font::new(20.0);
+    let font = Font::new(12.0);
+
+    let title = group
+        .object_info()
+        .map_or_else(|| format!("{:02X}", group.id), |info| info.label());
+    font_title.draw(&mut img, 8, 6, COLOR_TEXT, title);
+
+    let flags = [
+        (group.shot_with_rank, "Shot"),
+        (group.accel_shot_with_rank, "AccelShot"),
+        (group.homing_shot_with_rank, "HomingShot"),
+        (group.extra_act_with_rank, "ExtraAct"),
+        (group.accel_with_rank, "Accel"),
+    ]
+    .iter()
+    .filter(|(b, _)| *b)
+    .map(|(_, name)| *name)
+    .collect::<Vec<_>>();
+    let kind = group.object_info().map_or("-", |info| info.kind.name());
+    let lines = [
+        format!(
+            "Kind: {} (assumed)  Sprite: {:02X}  Difficulty: {}",
+            kind, group.sprite_idx_base, group.difficulty
+        ),
+        format!(
+            "Rank: {}",
+            if flags.is_empty() {
+                "-".to_owned()
+            } else {
+                flags.join(" ")
+            }
+        ),
+        format!(
+            "Spawn: ({}, {})  Interval: {}  Count: {}",
+            group.x_ini, group.y_ini, group.spawn_interval, group.spawn_count
+        ),
+        format!(
+            "Entrypoints: {}",
+            group
+                .entrypoints
+                .iter()
+                .map(|e| format!("{:02X}", e))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    ];
+    for (i, line) in lines.iter().enumerate() {
+        font.draw(&mut img, 8, 36 + 16 * i as u32, COLOR_TEXT, line);
+    }
+
+    // メタスプライト (1 周目, 2 周目)
+    let size = 16 * SPRITE_SCALE;
+    for (row, second_round) in [false, true].iter().enumerate() {
+        let y = 110 + (size + 20) * row as u32;
+        font.draw(
+            &mut img,
+            8,
+            y,
+            COLOR_TEXT,
+            if *second_round { "Round 2" } else { "Round 1" },
+        );
+        for (i, &id) in sprite_ids.iter().enumerate() {
+            let x = 8 + (size + 4) * i as u32;
+            let y = y + 14;
+            draw_filled_rect_mut(
+                &mut img,
+                Rect::at(x as i32, y as i32).of_size(size, size),
+                COLOR_SPRITE_BG,
+            );
+            let sprite = game.meta_sprite_image(id, *second_round);
+            let sprite = imageops::resize(
+                &sprite,
+                sprite.width() * SPRITE_SCALE,
+                sprite.height() * SPRITE_SCALE,
+                FilterType::Nearest,
+            );
+            imageops::overlay(&mut img, &sprite, x, y);
+        }
+    }
+
+    // 初期位置 (画面 256x240 を 1/2 に縮小)
+    let (fx, fy) = (CARD_WIDTH - 8 - 128, 36);
+    font.draw(&mut img, fx, fy - 14, COLOR_TEXT, "Spawn position");
+    draw_filled_rect_mut(
+        &mut img,
+        Rect::at(fx as i32, fy as i32).of_size(128, 120),
+        COLOR_SPRITE_BG,
+    );
+    draw_hollow_rect_mut(
+        &mut img,
+        Rect::at(fx as i32, fy as i32).of_size(128, 120),
+        COLOR_FRAME,
+    );
+    let sx = fx as i32 + i32::from(group.x_ini) / 2;
+    let sy = fy as i32 + (i32::from(group.y_ini) / 2).min(119);
+    draw_filled_rect_mut(
+        &mut img,
+        Rect::at(sx - 2, sy - 2).of_size(5, 5),
+        COLOR_SPAWN,
+    );
+
+    img
+}
@@ -1,7 +1,14 @@
 use byteorder::{ByteOrder, LE};
 
+use crate::game::*;
+use crate::meta_sprite_animation::*;
 use crate::rom::*;
 
+pub const ENEMY_GROUP_COUNT: usize = 0x1F;
+
+/// 1 グループに割り当てるメタスプライト数の上限。
+const FRAME_COUNT_MAX: usize = 8;
+
 #[derive(Clone, Debug)]
 pub struct EnemyGroup {
     pub id: u8,
@@ -28,7 +35,7 @@ pub fn load_enemy_groups(rom: &Rom) -> Vec<EnemyGroup> {
     let difficultys = &rom.prg[prg_offset(0xC7E5)..];
     let param_ptrs = load_enemy_group_param_ptrs(rom);
 
-    (0..=0x1E)
+    (0..ENEMY_GROUP_COUNT)
         .map(|i| {
             let attr = attrs[i];
             let shot_with_rank = (attr & (1 << 0)) != 0;
@@ -42,7 +49,7 @@ pub fn load_enemy_groups(rom: &Rom) -> Vec<EnemyGroup> {
             let param = &rom.prg[prg_offset(param_ptrs[i])..];
             let bytecode_ptr = LE::read_u16(&param[0..]);
             let bytecode = (bytecode_ptr >= 0x8000).then(|| {
-                let len = if i == 0x1E {
+                let len = if i == ENEMY_GROUP_COUNT - 1 {
                     0xCA
                 } else {
                     usize::from(param_ptrs[i + 1] - bytecode_ptr)
@@ -79,10 +86,94 @@ pub fn load_enemy_groups(rom: &Rom) -> Vec<EnemyGroup> {
         .collect()
 }
 
+/// 各グループが使うメタスプライト ID を返す。
+///
+/// animations (locate_meta_sprite_animations() の結果) に sprite_idx_base をフレームに含む
+/// アニメーションがあれば、そのフレームの ID を表示順に重複なく返す。
+/// なければ、sprite_idx_base から次に大きい他グループの sprite_idx_base の手前までとみなす
+/// (最大 FRAME_COUNT_MAX 個)。
+pub fn enemy_group_sprite_ids(
+    groups: &[EnemyGroup],
+    animations: Option<&MetaSpriteAnimations>,
+) -> Vec<Vec<u8>> {
+    groups
+        .iter()
+        .map(|group| {
+            let base = group.sprite_idx_base;
+            if let Some(anim) = animations.and_then(|found| found.containing(base)) {
+                return anim.sprite_ids();
+            }
+
+            let next = groups
+                .iter()
+                .map(|g| g.sprite_idx_base)
+                .filter(|&b| b > base)
+                .min()
+                .map_or(usize::from(META_SPRITE_MAX) + 1, usize::from);
+            let end = next.min(usize::from(base) + FRAME_COUNT_MAX);
+            (usize::from(base)..end).map(|id| id as u8).collect()
+        })
+        .collect()
+}
+
 fn load_enemy_group_param_ptrs(rom: &Rom) -> Vec<u16> {
     rom.prg[prg_offset(0xC804)..]
         .chunks(2)
-        .take(0x1F)
-        .map(|buf| LE::read_u16(buf))
+        .take(ENEMY_GROUP_COUNT)
+        .map(LE::read_u16)
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(id: u8, sprite_idx_base: u8) -> EnemyGroup {
+        EnemyGroup {
+            id,
+            sprite_idx_base,
+            difficulty: 0,
+            shot_with_rank: false,
+            accel_shot_with_rank: false,
+            homing_shot_with_rank: false,
+            extra_act_with_rank: false,
+            accel_with_rank: false,
+            x_ini: 0,
+            y_ini: 0,
+            bytecode: None,
+            spawn_interval: 0,
+            spawn_count: 0,
+            entrypoints: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn sprite_ids_prefer_located_animation() {
+        let groups = [group(1, 0x10), group(2, 0x14)];
+        let frames = [0x10, 0x11, 0x10, 0x12]
+            .iter()
+            .map(|&sprite_id| AnimationFrame {
+                sprite_id,
+                duration: 4,
+            })
+            .collect();
+        let animations = MetaSpriteAnimations {
+            sprite_id_array: 0x0400,
+            animations: vec![MetaSpriteAnimation {
+                id: 0,
+                table: 0xA000,
+                counter: 0x20,
+                writers: vec![0x9000],
+                frames,
+            }],
+            other_writers: Vec::new(),
+        };
+
+        let idss = enemy_group_sprite_ids(&groups, Some(&animations));
+        assert_eq!(idss[0], [0x10, 0x11, 0x12]);
+        assert_eq!(idss[1], (0x14..0x1C).collect::<Vec<u8>>());
+
+        let idss = enemy_group_sprite_ids(&groups, None);
+        assert_eq!(idss[0], [0x10, 0x11, 0x12, 0x13]);
+    }
+}
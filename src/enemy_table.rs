@@ -0,0 +1,149 @@
+// 敵グループの一覧表 (Markdown, CSV, HTML)。
+
+use std::io::Write;
+
+use crate::enemy_group::*;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TableFormat {
+    Markdown,
+    Csv,
+    Html,
+}
+
+impl std::str::FromStr for TableFormat {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "md" | "markdown" => Ok(Self::Markdown),
+            "csv" => Ok(Self::Csv),
+            "html" => Ok(Self::Html),
+            _ => Err(eyre::eyre!("unknown table format: {}", s)),
+        }
+    }
+}
+
+const HEADER: [&str; 17] = [
+    "ID",
+    "Name",
+    "Name (EN)",
+    "Kind (assumed)",
+    "Sprite",
+    "Difficulty",
+    "Shot",
+    "AccelShot",
+    "HomingShot",
+    "ExtraAct",
+    "Accel",
+    "X",
+    "Y",
+    "Interval",
+    "Count",
+    "Entrypoints",
+    "Bytecode",
+];
+
+/// 敵グループの一覧表を出力する。ランクフラグの列はランクで強化される項目を表す。
+pub fn write_enemy_group_table<W: Write>(
+    mut wtr: W,
+    groups: &[EnemyGroup],
+    format: TableFormat,
+) -> eyre::Result<()> {
+    let flag = |b: bool| -> String {
+        match (format, b) {
+            (TableFormat::Csv, b) => u8::from(b).to_string(),
+            (_, true) => "✓".to_owned(),
+            (_, false) => String::new(),
+        }
+    };
+
+    let rows: Vec<Vec<String>> = groups
+        .iter()
+        .map(|group| {
+            let info = group.object_info();
+            vec![
+                format!("{:02X}", group.id),
+                info.map_or("", |info| info.name_ja).to_owned(),
+                info.map_or("", |info| info.name_en).to_owned(),
+                info.map_or("", |info| info.kind.name()).to_owned(),
+                format!("{:02X}", group.sprite_idx_base),
+                group.difficulty.to_string(),
+                flag(group.shot_with_rank),
+                flag(group.accel_shot_with_rank),
+                flag(group.homing_shot_with_rank),
+                flag(group.extra_act_with_rank),
+                flag(group.accel_with_rank),
+                group.x_ini.to_string(),
+                group.y_ini.to_string(),
+                group.spawn_interval.to_string(),
+                group.spawn_count.to_string(),
+                group
+                    .entrypoints
+                    .iter()
+                    .map(|e| format!("{:02X}", e))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                group
+                    .bytecode
+                    .as_ref()
+                    .map_or_else(|| "-".to_owned(), |code| format!("{} bytes", code.len())),
+            ]
+        })
+        .collect();
+
+    match format {
+        TableFormat::Markdown => {
+            writeln!(wtr, "| {} |", HEADER.join(" | "))?;
+            writeln!(wtr, "|{}", "---|".repeat(HEADER.len()))?;
+            for row in &rows {
+                let cells: Vec<_> = row.iter().map(|s| s.replace('|', "\\|")).collect();
+                writeln!(wtr, "| {} |", cells.join(" | "))?;
+            }
+        }
+        TableFormat::Csv => {
+            writeln!(wtr, "{}", HEADER.join(","))?;
+            for row in &rows {
+                let cells: Vec<_> = row.iter().map(|s| csv_escape(s)).collect();
+                writeln!(wtr, "{}", cells.join(","))?;
+            }
+        }
+        TableFormat::Html => {
+            writeln!(wtr, "<table>")?;
+            writeln!(wtr, "  <thead>")?;
+            write!(wtr, "    <tr>")?;
+            for name in &HEADER {
+                write!(wtr, "<th>{}</th>", name)?;
+            }
+            writeln!(wtr, "</tr>")?;
+            writeln!(wtr, "  </thead>")?;
+            writeln!(wtr, "  <tbody>")?;
+            for row in &rows {
+                write!(wtr, "    <tr>")?;
+                for cell in row {
+                    write!(wtr, "<td>{}</td>", html_escape(cell))?;
+                }
+                writeln!(wtr, "</tr>")?;
+            }
+            writeln!(wtr, "  </tbody>")?;
+            writeln!(wtr, "</table>")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
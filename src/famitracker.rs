@@ -0,0 +1,288 @@
+// FamiTracker のテキストエクスポート形式 (0.4.2) で曲を出力する。
+// Dn-FamiTracker でも読み込める。
+
+use std::io::Write;
+
+use eyre::ensure;
+
+use crate::mml::*;
+use crate::music::*;
+
+/// 1 パターンの行数。フレーム数が FRAMES_MAX を超える場合は増やす。
+const PATTERN_ROWS: u32 = 64;
+
+/// FamiTracker の 1 パターンの行数の上限。
+const PATTERN_ROWS_MAX: u32 = 256;
+
+/// FamiTracker のフレーム (ORDER) 数の上限。
+const FRAMES_MAX: u32 = 128;
+
+/// FamiTracker の Fxx でスピードとして扱われる値の上限。
+const SPEED_MAX: u32 = 31;
+
+/// 1 行分の 1 チャンネルのセル。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Cell {
+    Empty,
+    Note { octave: u8, note: u8, inst: u8 },
+    Cut,
+}
+
+impl Music {
+    /// FamiTracker のテキストエクスポート形式で出力する。
+    ///
+    /// テンポ 150 ではスピードがそのまま 1 行のフレーム数になるので、
+    /// 全ての音の区切りのフレームの最大公約数を 1 行とする。
+    /// 矩形波の楽器はエンベロープを音量マクロ、デューティをデューティマクロで再現し、
+    /// 三角波の 3/4 発音はノートカットで表す。ただし音長 1 の音符は発音が 0 フレームにならないよう、
+    /// 1 フレーム発音させる。
+    /// Restart は最終行の B00、End は最終行の次の行の C00 で表す。
+    pub fn write_famitracker_text<W: Write>(&self, mut wtr: W) -> eyre::Result<()> {
+        let tracks = [&self.track_sq1, &self.track_sq2, &self.track_tri];
+        let eventss: Vec<_> = tracks.iter().map(|track| track_events(track)).collect();
+        let repeat = matches!(self.track_sq1.last(), Some(MusicCommand::Restart));
+
+        // 三角波のノートカットの位置も含めて行の単位を決める。
+        let boundaries = eventss.iter().enumerate().flat_map(|(ch, events)| {
+            events.iter().flat_map(move |ev| {
+                let cut = if ch == 2 && ev.tone.is_some() {
+                    tri_note_on(ev.length)
+                } else {
+                    0
+                };
+                [ev.frame, ev.frame + cut, ev.frame + ev.length]
+            })
+        });
+        let unit = boundaries.fold(0, gcd).max(1);
+        let speed = (1..=unit.min(SPEED_MAX))
+            .rev()
+            .find(|d| unit % d == 0)
+            .unwrap();
+
+        let n_frame = self.frame_count();
+        let n_row_music = n_frame / speed;
+        let n_row = if repeat { n_row_music } else { n_row_music + 1 };
+
+        let pattern_rows = PATTERN_ROWS.max(n_row.div_ceil(FRAMES_MAX));
+        ensure!(
+            pattern_rows <= PATTERN_ROWS_MAX,
+            "music {} is too long for FamiTracker: {} rows",
+            self.id,
+            n_row
+        );
+
+        let mut cellss = vec![vec![Cell::Empty; n_row as usize]; 3];
+        for (ch, events) in eventss.iter().enumerate() {
+            let cells = &mut cellss[ch];
+            let inst = if ch == 2 { 1 } else { 0 };
+            let mut sounding = false;
+            for ev in events {
+                let row = (ev.frame / speed) as usize;
+                match ev.tone {
+                    Some((octave, note)) => {
+                        // 三角波は同じノートで 1 オクターブ低く鳴る。
+                        let octave = if ch == 2 { octave + 1 } else { octave };
+                        cells[row] = Cell::Note { octave, note, inst };
+                        if ch == 2 {
+                            // 音長 1 の音符が曲の最後にある場合、カット位置が曲の外になる。
+                            let on = tri_note_on(ev.length);
+                            if let Some(cell) = cells.get_mut(((ev.frame + on) / speed) as usize) {
+                                *cell = Cell::Cut;
+                            }
+                        }
+                        sounding = ch != 2;
+                    }
+                    None => {
+                        if sounding {
+                            cells[row] = Cell::Cut;
+                        }
+                        sounding = false;
+                    }
+                }
+            }
+        }
+
+        let mut effects = vec![None; n_row as usize];
+        *effects.last_mut().unwrap() = Some(if repeat { "B00" } else { "C00" });
+
+        writeln!(wtr, "# FamiTracker text export 0.4.2")?;
+        writeln!(wtr)?;
+        writeln!(wtr, "# Song information")?;
+        writeln!(wtr, "TITLE           \"Star Soldier BGM {:02}\"", self.id)?;
+        writeln!(wtr, "AUTHOR          \"\"")?;
+        writeln!(wtr, "COPYRIGHT       \"\"")?;
+        writeln!(wtr)?;
+        writeln!(wtr, "# Song comment")?;
+        writeln!(wtr, "COMMENT \"\"")?;
+        writeln!(wtr)?;
+        writeln!(wtr, "# Global settings")?;
+        writeln!(wtr, "MACHINE         0")?;
+        writeln!(wtr, "FRAMERATE       0")?;
+        writeln!(wtr, "EXPANSION       0")?;
+        writeln!(wtr, "VIBRATO         1")?;
+        writeln!(wtr, "SPLIT           32")?;
+        writeln!(wtr)?;
+
+        writeln!(wtr, "# Macros")?;
+        let volumes = envelope_volumes(self.sq_envelope)
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(
+            wtr,
+            "MACRO{:8}{:4}{:4}{:4}{:4} : {}",
+            0, 0, -1, -1, 0, volumes
+        )?;
+        writeln!(
+            wtr,
+            "MACRO{:8}{:4}{:4}{:4}{:4} : {}",
+            4,
+            0,
+            -1,
+            -1,
+            0,
+            self.sq_duty.value()
+        )?;
+        writeln!(wtr)?;
+
+        writeln!(wtr, "# DPCM samples")?;
+        writeln!(wtr)?;
+
+        writeln!(wtr, "# Instruments")?;
+        writeln!(
+            wtr,
+            "INST2A03{:4}{:6}{:4}{:4}{:4}{:4} \"Square\"",
+            0, 0, -1, -1, -1, 0
+        )?;
+        writeln!(
+            wtr,
+            "INST2A03{:4}{:6}{:4}{:4}{:4}{:4} \"Triangle\"",
+            1, -1, -1, -1, -1, -1
+        )?;
+        writeln!(wtr)?;
+
+        writeln!(wtr, "# Tracks")?;
+        writeln!(wtr)?;
+        writeln!(
+            wtr,
+            "TRACK {:3} {:3} {:3} \"BGM {:02}\"",
+            pattern_rows, speed, 150, self.id
+        )?;
+        writeln!(wtr, "COLUMNS : 1 1 1 1 1")?;
+        writeln!(wtr)?;
+
+        let n_pattern = n_row.div_ceil(pattern_rows);
+        for i in 0..n_pattern {
+            writeln!(wtr, "ORDER {0:02X} : {0:02X} {0:02X} {0:02X} 00 00", i)?;
+        }
+        writeln!(wtr)?;
+
+        for i in 0..n_pattern {
+            writeln!(wtr, "PATTERN {:02X}", i)?;
+            let begin = i * pattern_rows;
+            let end = n_row.min(begin + pattern_rows);
+            for row in begin..end {
+                let row = row as usize;
+                write!(wtr, "ROW {:02X}", row - begin as usize)?;
+                for (ch, cells) in cellss.iter().enumerate() {
+                    let effect = if ch == 0 { effects[row] } else { None };
+                    write!(
+                        wtr,
+                        " : {} {}",
+                        cell_to_str(cells[row]),
+                        effect.unwrap_or("...")
+                    )?;
+                }
+                writeln!(wtr, " : ... .. . ... : ... .. . ...")?;
+            }
+            writeln!(wtr)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn cell_to_str(cell: Cell) -> String {
+    match cell {
+        Cell::Empty => "... .. .".to_owned(),
+        Cell::Note { octave, note, inst } => {
+            let name = note_to_mml(note).replace('+', "#");
+            let name = if name.len() == 1 {
+                format!("{}-", name)
+            } else {
+                name
+            };
+            format!("{}{} {:02X} .", name, octave, inst)
+        }
+        Cell::Cut => "--- .. .".to_owned(),
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn music(tracks: [Vec<MusicCommand>; 3]) -> Music {
+        let [track_sq1, track_sq2, track_tri] = tracks;
+        Music {
+            id: 1,
+            sq_envelope: 0,
+            sq_duty: SquareDuty::Half,
+            track_sq1,
+            track_sq2,
+            track_tri,
+        }
+    }
+
+    fn export(music: &Music) -> String {
+        let mut buf = Vec::new();
+        music.write_famitracker_text(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn triangle_note_of_length_1_is_not_cut_on_its_row() {
+        let sq = vec![
+            MusicCommand::new_set_length(2),
+            MusicCommand::new_rest(),
+            MusicCommand::new_end(),
+        ];
+        let tri = vec![
+            MusicCommand::new_set_length(1),
+            MusicCommand::new_tone(3, 0),
+            MusicCommand::new_rest(),
+            MusicCommand::new_end(),
+        ];
+        let text = export(&music([sq.clone(), sq, tri]));
+
+        let row0 = text.lines().find(|l| l.starts_with("ROW 00")).unwrap();
+        assert!(row0.contains("C-4 01"), "{}", row0);
+        let row1 = text.lines().find(|l| l.starts_with("ROW 01")).unwrap();
+        assert!(row1.contains("---"), "{}", row1);
+    }
+
+    #[test]
+    fn long_music_fits_in_frame_limit() {
+        // 音長 1 で 100 * 255 フレーム
+        let mut sq = vec![
+            MusicCommand::new_set_length(1),
+            MusicCommand::new_loop_begin(100),
+        ];
+        sq.extend(vec![MusicCommand::new_rest(); 255]);
+        sq.push(MusicCommand::new_loop_end());
+        sq.push(MusicCommand::new_end());
+        let text = export(&music([sq.clone(), sq.clone(), sq]));
+
+        let n_order = text.lines().filter(|l| l.starts_with("ORDER")).count();
+        assert!(n_order <= FRAMES_MAX as usize, "{} orders", n_order);
+    }
+}
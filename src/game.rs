@@ -1,3 +1,4 @@
+use std::collections::BTreeSet;
 use std::convert::TryInto;
 use std::io::{self, Read, Write};
 
@@ -51,7 +52,7 @@ impl Game {
 
     pub fn cell_image(&self, id: u8, second_round: bool, palette_set: &[Palette]) -> RgbaImage {
         let cv = &self.cell_visuals[id as usize];
-        let tiles = &self.tiles[(0x100 + if second_round { 0x400 } else { 0 })..];
+        let tiles = &self.tiles[Self::cell_tile_base(second_round)..];
         cv.to_image(tiles, palette_set)
     }
 
@@ -63,20 +64,131 @@ impl Game {
 
     pub fn meta_sprite_image(&self, id: u8, second_round: bool) -> RgbaImage {
         let msv = &self.meta_sprite_visuals[id as usize];
-        let offset = if (0x82..=0x8B).contains(&id) {
-            0x200
-        } else {
-            0
-        } + if second_round { 0x400 } else { 0 };
-        let tiles = &self.tiles[offset..];
+        let tiles = &self.tiles[Self::meta_sprite_tile_base(id, second_round)..];
         msv.to_image(tiles, &self.sprite_palette_set)
     }
 
+    /// メタスプライトを構成する 4 つの 8x8 部品の ((x, y), タイル, 属性) を返す。
+    /// 順序は (左上, 左下, 右上, 右下)。
+    pub fn meta_sprite_parts(
+        &self,
+        id: u8,
+        second_round: bool,
+    ) -> [((u32, u32), &Tile, SpriteAttribute); 4] {
+        let msv = &self.meta_sprite_visuals[id as usize];
+        let tiles = &self.tiles[Self::meta_sprite_tile_base(id, second_round)..];
+        msv.parts(tiles)
+    }
+
     pub fn meta_sprite_images(&self, second_round: bool) -> Vec<RgbaImage> {
         (0..=META_SPRITE_MAX)
             .map(|id| self.meta_sprite_image(id, second_round))
             .collect()
     }
+
+    /// セルおよびメタスプライトから参照される CHR 内タイル番号を全て返す。
+    pub fn referenced_tile_ids(&self) -> BTreeSet<usize> {
+        let mut res = BTreeSet::new();
+
+        for &second_round in &[false, true] {
+            let base = Self::cell_tile_base(second_round);
+            for cv in &self.cell_visuals {
+                res.extend(cv.tile_ids.iter().map(|&id| base + usize::from(id)));
+            }
+
+            for (id, msv) in (0..=META_SPRITE_MAX).zip(&self.meta_sprite_visuals) {
+                let base = Self::meta_sprite_tile_base(id, second_round);
+                res.extend(msv.tile_ids.iter().map(|&id| base + usize::from(id)));
+            }
+        }
+
+        res
+    }
+
+    /// CHR 内タイル番号ごとに、cell_ids のセルおよび meta_sprite_ids のメタスプライトから
+    /// 参照される回数を返す (両周回分)。
+    pub fn tile_reference_counts(&self, cell_ids: &[u8], meta_sprite_ids: &[u8]) -> Vec<u32> {
+        let mut res = vec![0; self.tiles.len()];
+
+        for &second_round in &[false, true] {
+            let base = Self::cell_tile_base(second_round);
+            for &id in cell_ids {
+                for &tile_id in &self.cell_visuals[usize::from(id)].tile_ids {
+                    res[base + usize::from(tile_id)] += 1;
+                }
+            }
+
+            for &id in meta_sprite_ids {
+                let base = Self::meta_sprite_tile_base(id, second_round);
+                for &tile_id in &self.meta_sprite_visuals[usize::from(id)].tile_ids {
+                    res[base + usize::from(tile_id)] += 1;
+                }
+            }
+        }
+
+        res
+    }
+
+    pub fn sprite_palette_set(&self) -> &[Palette] {
+        &self.sprite_palette_set
+    }
+
+    /// セルの (左上, 右上, 左下, 右下) のタイル番号 (BG パターンテーブル内)。
+    pub fn cell_tile_ids(&self, id: u8) -> [u8; 4] {
+        self.cell_visuals[id as usize].tile_ids
+    }
+
+    /// 地形が使う BG パターンテーブル (256 タイル, 4KB)。
+    pub fn bg_pattern_table(&self, second_round: bool) -> Vec<u8> {
+        let base = Self::cell_tile_base(second_round);
+        self.tiles[base..base + 0x100]
+            .iter()
+            .flat_map(|tile| tile.as_bytes().iter().copied())
+            .collect()
+    }
+
+    /// BG パターンテーブルのうち、全ピクセルが色 0 の最初のタイル番号。
+    pub fn blank_bg_tile(&self, second_round: bool) -> Option<u8> {
+        let base = Self::cell_tile_base(second_round);
+        self.tiles[base..base + 0x100]
+            .iter()
+            .position(|tile| tile.as_bytes().iter().all(|&b| b == 0))
+            .map(|i| i as u8)
+    }
+
+    /// ステージ stage の前半/後半 (half) の地形設定。
+    pub fn ground_config(&self, stage: u8, half: usize) -> &GroundConfig {
+        &self.ground_configs[stage as usize - 1][half]
+    }
+
+    pub fn ground_palette_count(&self) -> usize {
+        self.ground_palettes.len()
+    }
+
+    /// セルが使うパレットセット内のパレット番号。
+    pub fn cell_palette_index(&self, id: u8) -> u8 {
+        self.cell_visuals[id as usize].plt_idx
+    }
+
+    /// メタスプライトの各タイルが使うスプライトパレット番号。
+    pub fn meta_sprite_palette_indices(&self, id: u8) -> [u8; 4] {
+        self.meta_sprite_visuals[id as usize]
+            .attrs
+            .map(SpriteAttribute::palette_index)
+    }
+
+    fn cell_tile_base(second_round: bool) -> usize {
+        0x100 + if second_round { 0x400 } else { 0 }
+    }
+
+    fn meta_sprite_tile_base(id: u8, second_round: bool) -> usize {
+        let special = if (0x82..=0x8B).contains(&id) {
+            0x200
+        } else {
+            0
+        };
+        special + if second_round { 0x400 } else { 0 }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -131,23 +243,7 @@ impl Ground {
 
     /// 隠しセルの visual_id を返す。ゼグも含む。
     pub fn hidden_visual_id(&self, r: u8, c: u8) -> Option<u8> {
-        if let Some(secret) = self
-            .secrets
-            .iter()
-            .find(|secret| secret.r == r && secret.c == c)
-        {
-            return Some(match secret.cell {
-                0 => c % 4,
-                cell => cell,
-            });
-        }
-
-        const HIDDEN_ZEG_MIN: u8 = CELL_ZEG_INI + 1;
-        const HIDDEN_ZEG_MAX: u8 = CELL_ZEG_INI + 5;
-        match self.cell(r, c) {
-            HIDDEN_ZEG_MIN..=HIDDEN_ZEG_MAX => Some(CELL_ZEG_INI),
-            _ => None,
-        }
+        self.secret_item(r, c).map(SecretItem::visual_id)
     }
 
     pub fn palette_set_half(&self, i: usize) -> &[Palette] {
@@ -157,6 +253,21 @@ impl Ground {
     pub fn secrets(&self) -> &[GroundSecret] {
         &self.secrets
     }
+
+    /// 前半/後半 (half) で使われるセルの visual_id を返す。隠しセルも含む。
+    pub fn used_cells(&self, half: usize) -> BTreeSet<u8> {
+        let mut res = BTreeSet::new();
+
+        for r in 128 * half..128 * (half + 1) {
+            let r = r as u8;
+            for c in 0..20 {
+                res.insert(self.cell(r, c));
+                res.extend(self.hidden_visual_id(r, c));
+            }
+        }
+
+        res
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -261,16 +372,21 @@ impl MetaSpriteVisual {
         Self { tile_ids, attrs }
     }
 
+    /// 4 つの 8x8 部品の ((x, y), タイル, 属性) を返す。
+    /// 順序は (左上, 左下, 右上, 右下)。
+    pub fn parts<'a>(&self, tiles: &'a [Tile]) -> [((u32, u32), &'a Tile, SpriteAttribute); 4] {
+        [0, 1, 2, 3].map(|i| {
+            let x = if i / 2 == 0 { 0 } else { 8 };
+            let y = if i % 2 == 0 { 0 } else { 8 };
+            ((x, y), &tiles[self.tile_ids[i] as usize], self.attrs[i])
+        })
+    }
+
     pub fn to_image(&self, tiles: &[Tile], palette_set: &[Palette]) -> RgbaImage {
         let mut img = RgbaImage::new(16, 16);
 
-        for i in 0..4 {
-            let tile = &tiles[self.tile_ids[i] as usize];
-            let attr = self.attrs[i];
-
+        for ((x, y), tile, attr) in self.parts(tiles) {
             let img_part = sprite_image(tile, attr, palette_set);
-            let x = if i / 2 == 0 { 0 } else { 8 };
-            let y = if i % 2 == 0 { 0 } else { 8 };
             imageops::overlay(&mut img, &img_part, x, y);
         }
 
@@ -279,40 +395,90 @@ impl MetaSpriteVisual {
 }
 
 fn load_ground_cells(rom: &Rom) -> Vec<Vec<Vec<u8>>> {
-    let ptrs = rom.prg[prg_offset(0xD5D9)..]
+    load_ground_cells_ptrs(rom)
+        .into_iter()
+        .map(|ps| {
+            let mut cells = Vec::with_capacity(256);
+            for &p in &ps {
+                load_ground_cells_one_half(&mut cells, rom, p);
+            }
+            cells
+        })
+        .collect()
+}
+
+/// 各ステージの前半/後半の地形データについて (アドレス, バイト数) を返す。
+/// 0xDB による参照先は含まない。
+pub(crate) fn load_ground_cells_extents(rom: &Rom) -> Vec<[(u16, usize); 2]> {
+    load_ground_cells_ptrs(rom)
+        .into_iter()
+        .map(|ps| {
+            let mut extents = [(0, 0); 2];
+            for (extent, &p) in itertools::zip(&mut extents, &ps) {
+                let len = load_ground_cells_one_half(&mut Vec::new(), rom, p);
+                *extent = (p, len);
+            }
+            extents
+        })
+        .collect()
+}
+
+fn load_ground_cells_ptrs(rom: &Rom) -> Vec<[u16; 2]> {
+    rom.prg[prg_offset(0xD5D9)..]
         .chunks(2 * 2)
         .take(16)
-        .map(|buf| [LE::read_u16(&buf[..2]), LE::read_u16(&buf[2..4])]);
-
-    ptrs.map(|ps| {
-        let mut cells = Vec::with_capacity(256);
-        for &p in &ps {
-            load_ground_cells_one_half(&mut cells, rom, p);
-        }
-        cells
-    })
-    .collect()
+        .map(|buf| [LE::read_u16(&buf[..2]), LE::read_u16(&buf[2..4])])
+        .collect()
 }
 
-fn load_ground_cells_one_half(cells: &mut Vec<Vec<u8>>, rom: &Rom, addr: u16) {
-    let mut offset = prg_offset(addr);
+/// 128 行分の地形データを読み込み、消費したバイト数を返す。
+fn load_ground_cells_one_half(cells: &mut Vec<Vec<u8>>, rom: &Rom, addr: u16) -> usize {
+    let mut len = 0;
     for _ in 0..128 {
-        let mut row = Vec::with_capacity(20);
-        let wtr = io::Cursor::new(&mut row);
-
-        if rom.prg[offset] == 0xDB {
-            let ptr = LE::read_u16(&rom.prg[offset + 1..]);
-            let rdr = &rom.prg[prg_offset(ptr)..];
-            load_ground_cells_row(rdr, wtr).unwrap();
-            offset += 3;
-        } else {
-            let rdr = &rom.prg[offset..];
-            let n_read = load_ground_cells_row(rdr, wtr).unwrap();
-            offset += n_read;
-        }
+        let (row, n_read) = load_ground_row(rom, addr + len as u16);
+        len += n_read;
 
         cells.push(row);
     }
+
+    len
+}
+
+/// 全ステージの各行の地形データのアドレスを返す。
+pub(crate) fn load_ground_row_addrs(rom: &Rom) -> Vec<u16> {
+    let mut addrs = Vec::new();
+
+    for ps in load_ground_cells_ptrs(rom) {
+        for &p in &ps {
+            let mut addr = p;
+            for _ in 0..128 {
+                addrs.push(addr);
+                addr += load_ground_row(rom, addr).1 as u16;
+            }
+        }
+    }
+
+    addrs
+}
+
+/// addr にある 1 行分の地形データを展開し、(行, 消費したバイト数) を返す。
+/// 0xDB で始まる場合は参照先の行を展開する。
+pub(crate) fn load_ground_row(rom: &Rom, addr: u16) -> (Vec<u8>, usize) {
+    let offset = prg_offset(addr);
+
+    let mut row = Vec::with_capacity(20);
+    let wtr = io::Cursor::new(&mut row);
+
+    if rom.prg[offset] == 0xDB {
+        let ptr = LE::read_u16(&rom.prg[offset + 1..]);
+        let rdr = &rom.prg[prg_offset(ptr)..];
+        load_ground_cells_row(rdr, wtr).unwrap();
+        (row, 3)
+    } else {
+        let rdr = &rom.prg[offset..];
+        let n_read = load_ground_cells_row(rdr, wtr).unwrap();
+        (row, n_read)
+    }
 }
 
 fn load_ground_cells_row<R: Read, W: Write>(mut rdr: R, mut wtr: W) -> eyre::Result<usize> {
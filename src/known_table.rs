@@ -0,0 +1,134 @@
+use byteorder::{ByteOrder, LE};
+
+use crate::enemy_group::*;
+use crate::game::*;
+use crate::music::*;
+use crate::rom::*;
+
+/// 解析済みの PRG 内データテーブル。
+///
+/// entry_size が 0 の場合、エントリの区切りは不明(可変長データ)。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KnownTable {
+    pub name: String,
+    pub addr: u16,
+    pub len: usize,
+    pub entry_size: usize,
+    pub entry_id_base: usize,
+}
+
+impl KnownTable {
+    fn new(name: impl Into<String>, addr: u16, len: usize) -> Self {
+        Self {
+            name: name.into(),
+            addr,
+            len,
+            entry_size: 0,
+            entry_id_base: 0,
+        }
+    }
+
+    fn with_entries(
+        name: impl Into<String>,
+        addr: u16,
+        entry_size: usize,
+        entry_count: usize,
+        entry_id_base: usize,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            addr,
+            len: entry_size * entry_count,
+            entry_size,
+            entry_id_base,
+        }
+    }
+
+    pub fn range(&self) -> std::ops::Range<usize> {
+        let start = prg_offset(self.addr);
+        start..start + self.len
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        addr >= 0x8000 && self.range().contains(&prg_offset(addr))
+    }
+}
+
+/// ローダーが読み込む全テーブルをアドレス順に返す。
+pub fn load_known_tables(rom: &Rom) -> Vec<KnownTable> {
+    let mut tables = vec![
+        KnownTable::with_entries("SpritePalette", 0xB143, 4, 4, 0),
+        KnownTable::with_entries("MusicConfig", 0xB716, 1, MUSIC_COUNT, 1),
+        KnownTable::with_entries("MusicPointer", ADDR_MUSIC_PTR_TABLE, 6, MUSIC_COUNT, 1),
+        KnownTable::with_entries(
+            "MetaSpriteVisual",
+            0xC344,
+            8,
+            usize::from(META_SPRITE_MAX) + 1,
+            0,
+        ),
+        KnownTable::with_entries("EnemyGroupAttr", 0xC7C5, 1, ENEMY_GROUP_COUNT, 1),
+        KnownTable::with_entries("EnemyGroupDifficulty", 0xC7E5, 1, ENEMY_GROUP_COUNT, 1),
+        KnownTable::with_entries("EnemyParamPointer", 0xC804, 2, ENEMY_GROUP_COUNT, 1),
+        KnownTable::new("SpawnTable", 0xD30D, 0x100),
+        KnownTable::with_entries("GroundConfig", 0xD48D, 8, 16, 1),
+        KnownTable::with_entries("GroundPalette", 0xD50D, 4, 43, 0),
+        KnownTable::with_entries("GroundSecretPointer", 0xD5B9, 2, 16, 1),
+        KnownTable::with_entries("GroundCellPointer", 0xD5D9, 4, 16, 1),
+        KnownTable::with_entries("CellPaletteIndex", 0xD619, 1, usize::from(CELL_MAX) + 1, 0),
+        KnownTable::with_entries("CellTile", 0xD6B0, 4, usize::from(CELL_MAX) + 1, 0),
+    ];
+
+    for group in load_enemy_groups(rom) {
+        let id = usize::from(group.id);
+        let param_ptr = LE::read_u16(&rom.prg[prg_offset(0xC804 + 2 * (id as u16 - 1))..]);
+        tables.push(KnownTable::new(
+            format!("EnemyParam_{:02X}", id),
+            param_ptr,
+            7 + usize::from(group.spawn_count),
+        ));
+
+        if let Some(bytecode) = &group.bytecode {
+            let bytecode_ptr = LE::read_u16(&rom.prg[prg_offset(param_ptr)..]);
+            tables.push(KnownTable::new(
+                format!("EnemyBytecode_{:02X}", id),
+                bytecode_ptr,
+                bytecode.len(),
+            ));
+        }
+    }
+
+    for (i, extents) in load_ground_cells_extents(rom).into_iter().enumerate() {
+        for (half, (addr, len)) in extents.iter().enumerate() {
+            tables.push(KnownTable::new(
+                format!("GroundCells_{:02}_{}", i + 1, half),
+                *addr,
+                *len,
+            ));
+        }
+    }
+
+    let game = Game::from_rom(rom);
+    for stage in 1..=16u16 {
+        let ptr = LE::read_u16(&rom.prg[prg_offset(0xD5B9 + 2 * (stage - 1))..]);
+        let n = game.ground(stage as u8).secrets().len();
+        tables.push(KnownTable::new(
+            format!("GroundSecret_{:02}", stage),
+            ptr,
+            2 * n + 1,
+        ));
+    }
+
+    for (i, extents) in load_music_track_extents(rom).into_iter().enumerate() {
+        for (ch, (addr, len)) in itertools::zip(&["Sq1", "Sq2", "Tri"], &extents) {
+            tables.push(KnownTable::new(
+                format!("Music_{:02}_{}", i + 1, ch),
+                *addr,
+                *len,
+            ));
+        }
+    }
+
+    tables.sort_by_key(|table| table.addr);
+    tables
+}
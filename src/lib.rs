@@ -1,59 +1,73 @@
+mod apu;
+mod aseprite;
+mod atlas;
+mod cdl;
+mod cell_kind;
+mod cli;
+mod cpu;
+mod disasm;
+mod enemy_gallery;
 mod enemy_group;
+mod enemy_table;
+mod famitracker;
 mod font;
 mod game;
+mod known_table;
+mod meta_sprite_animation;
+mod mml;
 mod music;
+mod music_analysis;
+mod music_compiler;
+mod music_timeline;
+mod nametable;
+mod nsf;
+mod object;
+mod opcode;
 mod ppu;
 mod rom;
+mod scaling;
+mod score;
+mod secret;
+mod sound_driver;
+mod sound_effect;
 mod spawn_table;
+mod symbol;
+mod usage;
+mod validate;
+mod viewport;
 
+pub use crate::apu::*;
+pub use crate::aseprite::*;
+pub use crate::atlas::*;
+pub use crate::cdl::*;
+pub use crate::cell_kind::*;
+pub use crate::cli::*;
+pub use crate::cpu::*;
+pub use crate::disasm::*;
+pub use crate::enemy_gallery::*;
 pub use crate::enemy_group::*;
+pub use crate::enemy_table::*;
 pub use crate::font::*;
 pub use crate::game::*;
+pub use crate::known_table::*;
+pub use crate::meta_sprite_animation::*;
+pub use crate::mml::*;
 pub use crate::music::*;
+pub use crate::music_analysis::*;
+pub use crate::music_compiler::*;
+pub use crate::music_timeline::*;
+pub use crate::nametable::*;
+pub use crate::nsf::*;
+pub use crate::object::*;
+pub use crate::opcode::*;
 pub use crate::ppu::*;
 pub use crate::rom::*;
+pub use crate::scaling::*;
+pub use crate::secret::*;
+pub use crate::sound_driver::*;
+pub use crate::sound_effect::*;
 pub use crate::spawn_table::*;
-
-pub const OBJECT_NAME: [&str; 0x29] = [
-    "",
-    "レウス",
-    "テュラ",
-    "エイク",
-    "ソレル",
-    "ディダ",
-    "ペンド",
-    "リアード",
-    "バタフ",
-    "スラント",
-    "カルゴ",
-    "アトリス",
-    "メルス",
-    "プリング",
-    "ヤール",
-    "ビーグ",
-    "メーバ",
-    "ルイド",
-    "ジェラ",
-    "ルダン",
-    "リューク",
-    "ビータ",
-    "テミス",
-    "パトラ",
-    "ドラク",
-    "プリズン",
-    "カディス",
-    "ステリア",
-    "リーデ",
-    "グハ",
-    "ジェリコ",
-    "ラザロ",
-    "",
-    "ソープラー",
-    "スターブレインのコア",
-    "スターブレインの砲台",
-    "パワーアップアイテム",
-    "空中物の大爆発",
-    "地上物の大爆発",
-    "地上物の小爆発",
-    "空中物の小爆発",
-];
+pub use crate::symbol::*;
+pub use crate::usage::*;
+pub use crate::validate::*;
+pub use crate::viewport::*;
@@ -0,0 +1,445 @@
+// メタスプライトのアニメーション。
+//
+// アニメーションはコードから探す。
+//
+// 1. MetaSpriteVisual を引く描画処理の直前で、メタスプライト ID を読む RAM 配列を調べる。
+// 2. その配列に `LDA table,X/Y; STA array,X` で書き込む箇所を集める。table が ID の列。
+// 3. 添字が `LDA counter; (LSR A | AND #imm)*; TAX/TAY` で作られ、counter を INC する
+//    コードがあれば、counter を毎フレーム 1 増えるカウンタとみなして
+//    各フレームの ID と表示時間を求める。
+//
+// 添字をこの形で作っていない書き込みはアニメーションとして扱わず、書き込み元だけ返す。
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+use eyre::{ensure, eyre};
+use gif::{DisposalMethod, Encoder, Repeat};
+use image::RgbaImage;
+use png::{BitDepth, ColorType, DisposeOp};
+
+use crate::disasm::*;
+use crate::game::*;
+use crate::known_table::*;
+use crate::opcode::*;
+use crate::rom::*;
+use crate::scaling::*;
+
+/// 書き込み箇所から遡って調べる命令数の上限。
+const LOOKBACK_MAX: usize = 16;
+
+/// GIF のフレームを作るときの色の量子化速度 (1..=30、小さいほど高品質)。
+const GIF_SPEED: i32 = 10;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct AnimationFrame {
+    pub sprite_id: u8,
+    /// 表示フレーム数 (1/60 秒単位)。
+    pub duration: u8,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MetaSpriteAnimation {
+    /// 見つかった順の通し番号。
+    pub id: u8,
+    /// メタスプライト ID の列のアドレス。
+    pub table: u16,
+    /// 添字の元になるフレームカウンタのアドレス。
+    pub counter: u16,
+    /// メタスプライト ID 配列への書き込み命令のアドレス。
+    pub writers: Vec<u16>,
+    pub frames: Vec<AnimationFrame>,
+}
+
+/// コードから見つけたアニメーション。
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MetaSpriteAnimations {
+    /// オブジェクトごとのメタスプライト ID を持つ RAM 配列のアドレス。
+    pub sprite_id_array: u16,
+    pub animations: Vec<MetaSpriteAnimation>,
+    /// アニメーションとして解釈できなかった書き込み命令のアドレス。
+    pub other_writers: Vec<u16>,
+}
+
+/// 添字の計算に使われる命令。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum IndexOp {
+    Lsr,
+    And(u8),
+}
+
+impl MetaSpriteAnimations {
+    /// sprite_id をフレームに含む最初のアニメーション。
+    pub fn containing(&self, sprite_id: u8) -> Option<&MetaSpriteAnimation> {
+        self.animations
+            .iter()
+            .find(|anim| anim.frames.iter().any(|frame| frame.sprite_id == sprite_id))
+    }
+}
+
+impl MetaSpriteAnimation {
+    /// フレームのメタスプライト ID を表示順に重複なく返す。
+    pub fn sprite_ids(&self) -> Vec<u8> {
+        let mut ids = Vec::new();
+        for frame in &self.frames {
+            if !ids.contains(&frame.sprite_id) {
+                ids.push(frame.sprite_id);
+            }
+        }
+        ids
+    }
+
+    /// 各フレームを 16x16 の画像にする。
+    pub fn frame_images(&self, game: &Game, second_round: bool) -> Vec<RgbaImage> {
+        self.frames
+            .iter()
+            .map(|frame| game.meta_sprite_image(frame.sprite_id, second_round))
+            .collect()
+    }
+
+    /// ループするアニメーション GIF を出力する。各フレームは表示後に背景で消去する。
+    /// 0x82..=0x8B の CHR オフセットは meta_sprite_image 側で考慮される。
+    pub fn write_gif<W: std::io::Write>(
+        &self,
+        wtr: W,
+        game: &Game,
+        second_round: bool,
+        scaling: &ImageScaling,
+    ) -> eyre::Result<()> {
+        let imgs: Vec<_> = self
+            .frame_images(game, second_round)
+            .iter()
+            .map(|img| scaling.apply(img))
+            .collect();
+        let (w, h) = frame_size(&imgs)?;
+
+        let mut encoder = Encoder::new(wtr, w, h, &[])?;
+        encoder.set_repeat(Repeat::Infinite)?;
+        for (frame, img) in itertools::zip(&self.frames, imgs) {
+            let mut buf = img.into_raw();
+            let mut gif_frame = gif::Frame::from_rgba_speed(w, h, &mut buf, GIF_SPEED);
+            gif_frame.delay = (100 * u16::from(frame.duration) + 30) / 60;
+            gif_frame.dispose = DisposalMethod::Background;
+            encoder.write_frame(&gif_frame)?;
+        }
+
+        Ok(())
+    }
+
+    /// ループするアニメーション PNG (APNG) を出力する。各フレームは表示後に背景で消去する。
+    pub fn write_apng<W: std::io::Write>(
+        &self,
+        wtr: W,
+        game: &Game,
+        second_round: bool,
+        scaling: &ImageScaling,
+    ) -> eyre::Result<()> {
+        let imgs: Vec<_> = self
+            .frame_images(game, second_round)
+            .iter()
+            .map(|img| scaling.apply(img))
+            .collect();
+        let (w, h) = frame_size(&imgs)?;
+
+        let mut encoder = png::Encoder::new(wtr, u32::from(w), u32::from(h));
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.set_animated(imgs.len() as u32, 0)?;
+        encoder.set_dispose_op(DisposeOp::Background)?;
+
+        let mut writer = encoder.write_header()?;
+        for (frame, img) in itertools::zip(&self.frames, imgs) {
+            writer.set_frame_delay(u16::from(frame.duration), 60)?;
+            writer.write_image_data(img.as_raw())?;
+        }
+        writer.finish()?;
+
+        Ok(())
+    }
+}
+
+/// 全フレーム共通の画像サイズを返す。
+fn frame_size(imgs: &[RgbaImage]) -> eyre::Result<(u16, u16)> {
+    let first = imgs
+        .first()
+        .ok_or_else(|| eyre!("animation has no frames"))?;
+    ensure!(
+        imgs.iter()
+            .all(|img| img.dimensions() == first.dimensions()),
+        "animation frames differ in size"
+    );
+
+    Ok((
+        u16::try_from(first.width())?,
+        u16::try_from(first.height())?,
+    ))
+}
+
+/// ROM のコードからメタスプライトのアニメーションを探す。
+pub fn locate_meta_sprite_animations(rom: &Rom) -> eyre::Result<MetaSpriteAnimations> {
+    let tables = load_known_tables(rom);
+    let disasm = Disassembly::new(rom, &tables, &[]);
+
+    let visual = tables
+        .iter()
+        .find(|table| table.name == "MetaSpriteVisual")
+        .expect("MetaSpriteVisual table must be known");
+
+    locate_meta_sprite_animations_in(rom, &disasm, visual)
+}
+
+fn locate_meta_sprite_animations_in(
+    rom: &Rom,
+    disasm: &Disassembly,
+    visual: &KnownTable,
+) -> eyre::Result<MetaSpriteAnimations> {
+    let sprite_id_array = disasm
+        .xrefs(visual)
+        .find_map(|xref| {
+            lookback(disasm, xref).find_map(|pc| {
+                let (opcode, operand) = disasm.instruction(pc)?;
+                if !matches!(opcode.mnemonic, "LDA" | "LDX" | "LDY") {
+                    return None;
+                }
+                ram_indexed_addr(opcode, operand)
+            })
+        })
+        .ok_or_else(|| eyre!("meta-sprite ID array not found"))?;
+
+    let incremented: BTreeSet<u16> = disasm
+        .code_addrs()
+        .filter_map(|pc| {
+            let (opcode, operand) = disasm.instruction(pc)?;
+            (opcode.mnemonic == "INC").then(|| ram_addr(opcode, operand))?
+        })
+        .collect();
+
+    let mut animations: Vec<MetaSpriteAnimation> = Vec::new();
+    let mut other_writers = Vec::new();
+    for pc in disasm.code_addrs() {
+        let (opcode, operand) = disasm.instruction(pc).unwrap();
+        if opcode.mnemonic != "STA" || ram_indexed_addr(opcode, operand) != Some(sprite_id_array) {
+            continue;
+        }
+
+        let found = animation_source(disasm, pc)
+            .filter(|(_, counter, _)| incremented.contains(counter))
+            .and_then(|(table, counter, ops)| {
+                Some((table, counter, animation_frames(rom, table, &ops)?))
+            });
+        let (table, counter, frames) = match found {
+            Some(found) => found,
+            None => {
+                other_writers.push(pc);
+                continue;
+            }
+        };
+
+        match animations
+            .iter_mut()
+            .find(|anim| anim.table == table && anim.frames == frames)
+        {
+            Some(anim) => anim.writers.push(pc),
+            None => animations.push(MetaSpriteAnimation {
+                id: animations.len() as u8,
+                table,
+                counter,
+                writers: vec![pc],
+                frames,
+            }),
+        }
+    }
+
+    Ok(MetaSpriteAnimations {
+        sprite_id_array,
+        animations,
+        other_writers,
+    })
+}
+
+/// addr の直前の命令から順に、最大 LOOKBACK_MAX 個の命令アドレスを返す。
+fn lookback(disasm: &Disassembly, addr: u16) -> impl Iterator<Item = u16> + '_ {
+    std::iter::successors(disasm.previous_instruction(addr), move |&pc| {
+        disasm.previous_instruction(pc)
+    })
+    .take(LOOKBACK_MAX)
+}
+
+/// 直接アドレスで RAM を指すオペランドならそのアドレスを返す。
+fn ram_addr(opcode: Opcode, operand: &[u8]) -> Option<u16> {
+    let addr = match opcode.mode {
+        AddrMode::ZeroPage => u16::from(operand[0]),
+        AddrMode::Absolute => u16::from_le_bytes([operand[0], operand[1]]),
+        _ => return None,
+    };
+    (addr < 0x800).then_some(addr)
+}
+
+/// インデックス付きで RAM を指すオペランドなら、その配列のアドレスを返す。
+fn ram_indexed_addr(opcode: Opcode, operand: &[u8]) -> Option<u16> {
+    let addr = match opcode.mode {
+        AddrMode::ZeroPageX | AddrMode::ZeroPageY => u16::from(operand[0]),
+        AddrMode::AbsoluteX | AddrMode::AbsoluteY => u16::from_le_bytes([operand[0], operand[1]]),
+        _ => return None,
+    };
+    (addr < 0x800).then_some(addr)
+}
+
+/// 書き込み命令 store の直前を調べ、(ID の列のアドレス, カウンタのアドレス, 添字の計算) を返す。
+fn animation_source(disasm: &Disassembly, store: u16) -> Option<(u16, u16, Vec<IndexOp>)> {
+    let mut pcs = lookback(disasm, store);
+
+    let (opcode, operand) = disasm.instruction(pcs.next()?)?;
+    let transfer = match (opcode.mnemonic, opcode.mode) {
+        ("LDA", AddrMode::AbsoluteX) => "TAX",
+        ("LDA", AddrMode::AbsoluteY) => "TAY",
+        _ => return None,
+    };
+    let table = u16::from_le_bytes([operand[0], operand[1]]);
+    if table < 0x8000 {
+        return None;
+    }
+
+    let (opcode, _) = disasm.instruction(pcs.next()?)?;
+    if opcode.mnemonic != transfer {
+        return None;
+    }
+
+    let mut ops = Vec::new();
+    for pc in pcs {
+        let (opcode, operand) = disasm.instruction(pc)?;
+        match (opcode.mnemonic, opcode.mode) {
+            ("LSR", AddrMode::Accumulator) => ops.push(IndexOp::Lsr),
+            ("AND", AddrMode::Immediate) => ops.push(IndexOp::And(operand[0])),
+            ("LDA", _) => {
+                let counter = ram_addr(opcode, operand)?;
+                if !ops.iter().any(|op| matches!(op, IndexOp::And(_))) {
+                    return None;
+                }
+                ops.reverse();
+                return Some((table, counter, ops));
+            }
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+/// カウンタを 0 から 1 ずつ増やしたときの ID の列を、1 周期分のフレーム列にする。
+/// ID が 1 種類だけの場合や、不正な ID を含む場合は None。
+fn animation_frames(rom: &Rom, table: u16, ops: &[IndexOp]) -> Option<Vec<AnimationFrame>> {
+    let index = |counter: u8| {
+        ops.iter().fold(counter, |value, op| match op {
+            IndexOp::Lsr => value >> 1,
+            IndexOp::And(mask) => value & mask,
+        })
+    };
+    // 周期は 256 の約数になる。
+    let period = (0..=8).map(|k| 1usize << k).find(|&period| {
+        (0..=255).all(|c: u8| index(c) == index((usize::from(c) % period) as u8))
+    })?;
+
+    let mut frames: Vec<AnimationFrame> = Vec::new();
+    for counter in 0..period {
+        let addr = table.checked_add(u16::from(index(counter as u8)))?;
+        let sprite_id = rom.prg[prg_offset(addr)];
+        if sprite_id > META_SPRITE_MAX {
+            return None;
+        }
+        match frames.last_mut() {
+            // 256 フレーム続く (ID が 1 種類だけの) 場合は u8 に収まらない。
+            Some(frame) if frame.sprite_id == sprite_id => {
+                frame.duration = frame.duration.checked_add(1)?
+            }
+            _ => frames.push(AnimationFrame {
+                sprite_id,
+                duration: 1,
+            }),
+        }
+    }
+
+    (frames.len() >= 2).then_some(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locate_animation_from_code() {
+        let mut prg = [0xEA; 0x8000]; // NOP で埋める
+        let code: &[(u16, &[u8])] = &[
+            // RESET: JSR $9000; JSR $9100; JMP *
+            (
+                0x8000,
+                &[0x20, 0x00, 0x90, 0x20, 0x00, 0x91, 0x4C, 0x06, 0x80],
+            ),
+            // NMI: INC $14; RTI
+            (0x8100, &[0xE6, 0x14, 0x40]),
+            // LDA $14; LSR; LSR; AND #3; TAY; LDA $A000,Y; STA $0400,X; RTS
+            (
+                0x9000,
+                &[
+                    0xA5, 0x14, 0x4A, 0x4A, 0x29, 0x03, 0xA8, 0xB9, 0x00, 0xA0, 0x9D, 0x00, 0x04,
+                    0x60,
+                ],
+            ),
+            // LDA $0400,X; ASL; ASL; ASL; TAY; LDA $C344,Y; RTS
+            (
+                0x9100,
+                &[
+                    0xBD, 0x00, 0x04, 0x0A, 0x0A, 0x0A, 0xA8, 0xB9, 0x44, 0xC3, 0x60,
+                ],
+            ),
+            (0xA000, &[0x05, 0x06, 0x07, 0x06]),
+            (0xFFFA, &[0x00, 0x81, 0x00, 0x80]),
+        ];
+        for &(addr, bytes) in code {
+            prg[prg_offset(addr)..][..bytes.len()].copy_from_slice(bytes);
+        }
+        let rom = Rom {
+            prg,
+            chr: [0; 0x8000],
+        };
+        let visual = KnownTable {
+            name: "MetaSpriteVisual".to_owned(),
+            addr: 0xC344,
+            len: 8 * (usize::from(META_SPRITE_MAX) + 1),
+            entry_size: 8,
+            entry_id_base: 0,
+        };
+        let disasm = Disassembly::new(&rom, std::slice::from_ref(&visual), &[]);
+
+        let found = locate_meta_sprite_animations_in(&rom, &disasm, &visual).unwrap();
+        let frame = |sprite_id| AnimationFrame {
+            sprite_id,
+            duration: 4,
+        };
+        assert_eq!(
+            found,
+            MetaSpriteAnimations {
+                sprite_id_array: 0x0400,
+                animations: vec![MetaSpriteAnimation {
+                    id: 0,
+                    table: 0xA000,
+                    counter: 0x14,
+                    writers: vec![0x900A],
+                    frames: vec![frame(5), frame(6), frame(7), frame(6)],
+                }],
+                other_writers: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn constant_table_without_index_ops_is_not_animation() {
+        let rom = Rom {
+            prg: [0; 0x8000],
+            chr: [0; 0x8000],
+        };
+
+        assert_eq!(animation_frames(&rom, 0xA000, &[]), None);
+        assert_eq!(animation_frames(&rom, 0xA000, &[IndexOp::And(0xFF)]), None);
+    }
+}
@@ -0,0 +1,395 @@
+// MML の方言ごとの出力。
+//
+// ゲーム内では音長をフレーム単位で扱っているので、各方言ともテンポを調整して
+// 1F が整数カウントになるようにし、音長はカウント直接指定 (%n) で書く。
+
+use std::io::Write;
+
+use crate::apu::*;
+use crate::music::*;
+
+/// MML の方言。トラックの走査は write_music_mml() が行い、個々の要素の書式を方言が決める。
+pub trait MmlDialect {
+    /// 曲全体のヘッダ (テンポ、音色定義など) を出力する。
+    fn write_header(&self, wtr: &mut dyn Write, music: &Music) -> eyre::Result<()>;
+
+    /// トラック冒頭の設定を出力する。repeat はトラックが Restart で終わるかどうか。
+    fn write_track_begin(
+        &self,
+        wtr: &mut dyn Write,
+        music: &Music,
+        ch: ApuChannel,
+        repeat: bool,
+    ) -> eyre::Result<()>;
+
+    fn write_track_end(&self, wtr: &mut dyn Write) -> eyre::Result<()>;
+
+    fn write_tone(
+        &self,
+        wtr: &mut dyn Write,
+        octave: u8,
+        note: u8,
+        length: u32,
+        tri: bool,
+    ) -> eyre::Result<()>;
+
+    fn write_rest(&self, wtr: &mut dyn Write, length: u32) -> eyre::Result<()>;
+
+    fn write_loop_begin(&self, wtr: &mut dyn Write, count: u8) -> eyre::Result<()>;
+
+    fn write_loop_end(&self, wtr: &mut dyn Write, count: u8) -> eyre::Result<()>;
+}
+
+/// 曲を指定した方言の MML として出力する。
+pub fn write_music_mml(
+    wtr: &mut dyn Write,
+    music: &Music,
+    dialect: &dyn MmlDialect,
+) -> eyre::Result<()> {
+    dialect.write_header(wtr, music)?;
+
+    let tracks = [
+        (ApuChannel::Square1, &music.track_sq1),
+        (ApuChannel::Square2, &music.track_sq2),
+        (ApuChannel::Triangle, &music.track_tri),
+    ];
+    for &(ch, track) in &tracks {
+        let repeat = matches!(track.last(), Some(MusicCommand::Restart));
+        dialect.write_track_begin(wtr, music, ch, repeat)?;
+        write_track_body(wtr, track, ch == ApuChannel::Triangle, dialect)?;
+        dialect.write_track_end(wtr)?;
+    }
+
+    Ok(())
+}
+
+fn write_track_body(
+    wtr: &mut dyn Write,
+    track: &[MusicCommand],
+    tri: bool,
+    dialect: &dyn MmlDialect,
+) -> eyre::Result<()> {
+    let mut length_cur = None;
+    let mut loop_count = None;
+
+    for &cmd in track {
+        match cmd {
+            MusicCommand::Tone { octave, note } => {
+                let length = length_cur.expect("length_cur not set");
+                dialect.write_tone(wtr, octave, note, length, tri)?;
+            }
+            MusicCommand::Rest => {
+                dialect.write_rest(wtr, length_cur.expect("length_cur not set"))?;
+            }
+            MusicCommand::SetLength { length } => length_cur = Some(u32::from(length)),
+            MusicCommand::LoopBegin { count } => {
+                loop_count = Some(count);
+                dialect.write_loop_begin(wtr, count)?;
+            }
+            MusicCommand::LoopEnd => {
+                let count = loop_count.take().expect("not in loop");
+                dialect.write_loop_end(wtr, count)?;
+            }
+            MusicCommand::Restart | MusicCommand::End => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// 三角波の発音部分と消音部分の長さ。三角波は 3/4 発音、1/4 消音する。
+pub(crate) fn tri_articulation(length: u32) -> (u32, u32) {
+    let on = length * 3 / 4;
+    (on, length - on)
+}
+
+/// 三角波の音符の発音フレーム数 (1 以上)。
+pub(crate) fn tri_note_on(length: u32) -> u32 {
+    tri_articulation(length).0.max(1)
+}
+
+/// ソフトウェアエンベロープで APU のハードウェアエンベロープ (ループなし) を再現した、
+/// フレームごとの音量列を返す。
+///
+/// エンベロープの周期は (envelope+1)/4 フレームなので、その間隔で 15 から 0 まで減衰する。
+pub(crate) fn envelope_volumes(envelope: u8) -> Vec<u32> {
+    let period = u32::from(envelope) + 1;
+    (0..)
+        .map(|frame: u32| 15u32.saturating_sub(4 * frame / period))
+        .take_while(|&v| v > 0)
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+fn join_volumes(volumes: &[u32], sep: &str) -> String {
+    volumes
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
+/// FlMML (https://gist.github.com/anonymous/975e4cf634c2b156621e662b5fd12e4a)
+///
+/// 384 分音符が 1 単位なので、192 分音符=1F とすると、4 分音符=48F より、BPM は 3600/48=75 となる。
+/// 無限ループは書けないので Restart は無視する。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FlMml;
+
+impl FlMml {
+    fn length_to_tick(length: u32) -> u32 {
+        2 * length
+    }
+}
+
+impl MmlDialect for FlMml {
+    fn write_header(&self, wtr: &mut dyn Write, _music: &Music) -> eyre::Result<()> {
+        writeln!(wtr, "T75")?;
+        Ok(())
+    }
+
+    fn write_track_begin(
+        &self,
+        wtr: &mut dyn Write,
+        music: &Music,
+        ch: ApuChannel,
+        _repeat: bool,
+    ) -> eyre::Result<()> {
+        if ch == ApuChannel::Triangle {
+            writeln!(wtr, "V15 @6-1")?;
+            return Ok(());
+        }
+
+        // APU のエンベロープの周期は t=(envelope+1)/240 秒。
+        // エンベロープありの場合、初期音量は 15 なので、無音になるまで 15*t 秒かかる。
+        // これを x/127 秒に補正する。
+        let decay = {
+            let t = f64::from(music.sq_envelope + 1) / 240.0;
+            (127.0 * 15.0 * t).round() as u32
+        };
+
+        writeln!(
+            wtr,
+            "@5@W{} V15 @E1,0,{},0,0 ",
+            match music.sq_duty {
+                SquareDuty::Eighth => 1,
+                SquareDuty::Quarter => 2,
+                SquareDuty::Half => 4,
+                SquareDuty::QuarterNeg => 6,
+            },
+            decay
+        )?;
+
+        Ok(())
+    }
+
+    fn write_track_end(&self, wtr: &mut dyn Write) -> eyre::Result<()> {
+        writeln!(wtr, ";")?;
+        Ok(())
+    }
+
+    fn write_tone(
+        &self,
+        wtr: &mut dyn Write,
+        octave: u8,
+        note: u8,
+        length: u32,
+        tri: bool,
+    ) -> eyre::Result<()> {
+        let note_str = note_to_mml(note);
+        let tick = Self::length_to_tick(length);
+        if tri {
+            write!(
+                wtr,
+                "O{}{}%{} R%{} ",
+                octave,
+                note_str,
+                tick * 3 / 4,
+                tick / 4
+            )?;
+        } else {
+            write!(wtr, "O{}{}%{} ", octave, note_str, tick)?;
+        }
+        Ok(())
+    }
+
+    fn write_rest(&self, wtr: &mut dyn Write, length: u32) -> eyre::Result<()> {
+        write!(wtr, "R%{} ", Self::length_to_tick(length))?;
+        Ok(())
+    }
+
+    fn write_loop_begin(&self, wtr: &mut dyn Write, count: u8) -> eyre::Result<()> {
+        write!(wtr, "/:{} ", count)?;
+        Ok(())
+    }
+
+    fn write_loop_end(&self, wtr: &mut dyn Write, _count: u8) -> eyre::Result<()> {
+        write!(wtr, ":/ ")?;
+        Ok(())
+    }
+}
+
+/// PPMCK/MCK
+///
+/// 全音符が 192 カウントなので、T75 で 1 カウント=1F となる。
+/// トラック A, B, C がそれぞれ sq1, sq2, tri に対応する。
+/// 矩形波のエンベロープは @v0 のソフトウェアエンベロープで再現し、Restart はトラック先頭の L で表す。
+/// 三角波は同じオクターブ指定で矩形波より 1 オクターブ低く鳴るので、1 オクターブ上げて書く。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ppmck;
+
+impl MmlDialect for Ppmck {
+    fn write_header(&self, wtr: &mut dyn Write, music: &Music) -> eyre::Result<()> {
+        writeln!(wtr, "#TITLE Star Soldier BGM {:02}", music.id)?;
+        writeln!(wtr)?;
+        writeln!(
+            wtr,
+            "@v0 = {{ {} }}",
+            join_volumes(&envelope_volumes(music.sq_envelope), " ")
+        )?;
+        writeln!(wtr)?;
+        Ok(())
+    }
+
+    fn write_track_begin(
+        &self,
+        wtr: &mut dyn Write,
+        music: &Music,
+        ch: ApuChannel,
+        repeat: bool,
+    ) -> eyre::Result<()> {
+        match ch {
+            ApuChannel::Square1 | ApuChannel::Square2 => {
+                let name = if ch == ApuChannel::Square1 { 'A' } else { 'B' };
+                write!(wtr, "{} t75 @{} @v0 ", name, music.sq_duty.value())?;
+            }
+            ApuChannel::Triangle => write!(wtr, "C t75 ")?,
+            ApuChannel::Noise => unreachable!(),
+        }
+        if repeat {
+            write!(wtr, "L ")?;
+        }
+        Ok(())
+    }
+
+    fn write_track_end(&self, wtr: &mut dyn Write) -> eyre::Result<()> {
+        writeln!(wtr)?;
+        Ok(())
+    }
+
+    fn write_tone(
+        &self,
+        wtr: &mut dyn Write,
+        octave: u8,
+        note: u8,
+        length: u32,
+        tri: bool,
+    ) -> eyre::Result<()> {
+        let note_str = note_to_mml(note).to_ascii_lowercase();
+        if tri {
+            // 発音が 1 フレーム以下になる音符 (音長 1, 2) は休符を付けずにそのまま書く。
+            // 「%1 r%1」は音長 1 の音符と本物の休符の並びと区別できないため。
+            let (on, off) = tri_articulation(length);
+            if on <= 1 {
+                write!(wtr, "o{}{}%{} ", octave + 1, note_str, length)?;
+            } else {
+                write!(wtr, "o{}{}%{} r%{} ", octave + 1, note_str, on, off)?;
+            }
+        } else {
+            write!(wtr, "o{}{}%{} ", octave, note_str, length)?;
+        }
+        Ok(())
+    }
+
+    fn write_rest(&self, wtr: &mut dyn Write, length: u32) -> eyre::Result<()> {
+        write!(wtr, "r%{} ", length)?;
+        Ok(())
+    }
+
+    fn write_loop_begin(&self, wtr: &mut dyn Write, _count: u8) -> eyre::Result<()> {
+        write!(wtr, "[ ")?;
+        Ok(())
+    }
+
+    fn write_loop_end(&self, wtr: &mut dyn Write, count: u8) -> eyre::Result<()> {
+        write!(wtr, "]{} ", count)?;
+        Ok(())
+    }
+}
+
+/// NSD.Lib
+///
+/// #TimeBase 24 (4 分音符=24 カウント) とし、T150 で 1 カウント=1F となる。
+/// トラック TR1, TR2, TR3 がそれぞれ sq1, sq2, tri に対応する。
+/// 矩形波のエンベロープは E0 のソフトウェアエンベロープで再現し、Restart はトラック先頭の L で表す。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NsdLib;
+
+impl MmlDialect for NsdLib {
+    fn write_header(&self, wtr: &mut dyn Write, music: &Music) -> eyre::Result<()> {
+        writeln!(wtr, "#Title \"Star Soldier BGM {:02}\"", music.id)?;
+        writeln!(wtr, "#TimeBase 24")?;
+        writeln!(wtr)?;
+        writeln!(
+            wtr,
+            "E(0){{{}}}",
+            join_volumes(&envelope_volumes(music.sq_envelope), ",")
+        )?;
+        writeln!(wtr)?;
+        Ok(())
+    }
+
+    fn write_track_begin(
+        &self,
+        wtr: &mut dyn Write,
+        music: &Music,
+        ch: ApuChannel,
+        repeat: bool,
+    ) -> eyre::Result<()> {
+        match ch {
+            ApuChannel::Square1 | ApuChannel::Square2 => {
+                let n = if ch == ApuChannel::Square1 { 1 } else { 2 };
+                writeln!(wtr, "TR{}", n)?;
+                write!(wtr, "t150 @{} E0 v15 ", music.sq_duty.value())?;
+            }
+            ApuChannel::Triangle => {
+                writeln!(wtr, "TR3")?;
+                write!(wtr, "t150 v15 ")?;
+            }
+            ApuChannel::Noise => unreachable!(),
+        }
+        if repeat {
+            write!(wtr, "L ")?;
+        }
+        Ok(())
+    }
+
+    fn write_track_end(&self, wtr: &mut dyn Write) -> eyre::Result<()> {
+        writeln!(wtr)?;
+        writeln!(wtr)?;
+        Ok(())
+    }
+
+    fn write_tone(
+        &self,
+        wtr: &mut dyn Write,
+        octave: u8,
+        note: u8,
+        length: u32,
+        tri: bool,
+    ) -> eyre::Result<()> {
+        Ppmck.write_tone(wtr, octave, note, length, tri)
+    }
+
+    fn write_rest(&self, wtr: &mut dyn Write, length: u32) -> eyre::Result<()> {
+        Ppmck.write_rest(wtr, length)
+    }
+
+    fn write_loop_begin(&self, wtr: &mut dyn Write, count: u8) -> eyre::Result<()> {
+        Ppmck.write_loop_begin(wtr, count)
+    }
+
+    fn write_loop_end(&self, wtr: &mut dyn Write, count: u8) -> eyre::Result<()> {
+        Ppmck.write_loop_end(wtr, count)
+    }
+}
@@ -1,13 +1,18 @@
 use std::convert::TryFrom;
 
 use byteorder::{ByteOrder, LE};
+use serde::{Deserialize, Serialize};
 
+use crate::mml::*;
 use crate::rom::*;
 
 // BGM ID 10 はただの無音なので無視する。
-const MUSIC_COUNT: usize = 9;
+pub(crate) const MUSIC_COUNT: usize = 9;
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+/// 曲ごとの (sq1, sq2, tri) トラックのポインタテーブル。
+pub(crate) const ADDR_MUSIC_PTR_TABLE: u16 = 0xBBA6;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum SquareDuty {
     Eighth,
     Quarter,
@@ -36,7 +41,8 @@ impl SquareDuty {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum MusicCommand {
     Tone { octave: u8, note: u8 },
     Rest,
@@ -80,7 +86,7 @@ impl MusicCommand {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Music {
     pub id: u8,
     pub sq_envelope: u8,
@@ -91,135 +97,169 @@ pub struct Music {
 }
 
 impl Music {
-    pub fn write_mml<W: std::io::Write>(&self, mut wtr: W) -> eyre::Result<()> {
-        // FlMML reference: https://gist.github.com/anonymous/975e4cf634c2b156621e662b5fd12e4a
-
-        // ゲーム内では音長をフレーム単位で扱っているが、MMLでは384分音符が1単位となる。
-        // そこで、192分音符=1F とすると、4分音符=48F より、BPMは 3600/48=75 となる。
-
-        writeln!(wtr, "T75")?;
-
-        Self::write_mml_sq_track(&mut wtr, self.sq_envelope, self.sq_duty, &self.track_sq1)?;
-        Self::write_mml_sq_track(&mut wtr, self.sq_envelope, self.sq_duty, &self.track_sq2)?;
-        Self::write_mml_tri_track(&mut wtr, &self.track_tri)?;
+    /// 先頭から Restart/End までのフレーム数を返す。ループは展開する。
+    pub fn frame_count(&self) -> u32 {
+        track_events(&self.track_sq1)
+            .last()
+            .map_or(0, |ev| ev.frame + ev.length)
+    }
 
-        Ok(())
+    /// FlMML 形式で出力する。
+    pub fn write_mml<W: std::io::Write>(&self, mut wtr: W) -> eyre::Result<()> {
+        write_music_mml(&mut wtr, self, &FlMml)
     }
 
-    fn write_mml_sq_track<W: std::io::Write>(
-        wtr: &mut W,
-        envelope: u8,
-        duty: SquareDuty,
-        track: &[MusicCommand],
+    /// 指定した方言の MML として出力する。
+    pub fn write_mml_dialect<W: std::io::Write>(
+        &self,
+        mut wtr: W,
+        dialect: &dyn MmlDialect,
     ) -> eyre::Result<()> {
-        // APU のエンベロープの周期は t=(envelope+1)/240 秒。
-        // エンベロープありの場合、初期音量は 15 なので、無音になるまで 15*t 秒かかる。
-        // これを x/127 秒に補正する。
-        let decay = {
-            let t = f64::from(envelope + 1) / 240.0;
-            (127.0 * 15.0 * t).round() as u32
-        };
-
-        writeln!(
-            wtr,
-            "@5@W{} V15 @E1,0,{},0,0 ",
-            match duty {
-                SquareDuty::Eighth => 1,
-                SquareDuty::Quarter => 2,
-                SquareDuty::Half => 4,
-                SquareDuty::QuarterNeg => 6,
-            },
-            decay
-        )?;
-
-        Self::write_mml_track(wtr, track, false)?;
-
-        writeln!(wtr, ";")?;
+        write_music_mml(&mut wtr, self, dialect)
+    }
+}
 
-        Ok(())
+/// 音名 (0..=11) を MML の表記に変換する。
+pub fn note_to_mml(note: u8) -> &'static str {
+    match note {
+        0 => "C",
+        1 => "C+",
+        2 => "D",
+        3 => "D+",
+        4 => "E",
+        5 => "F",
+        6 => "F+",
+        7 => "G",
+        8 => "G+",
+        9 => "A",
+        10 => "A+",
+        11 => "B",
+        _ => unreachable!(),
     }
+}
 
-    fn write_mml_tri_track<W: std::io::Write>(
-        wtr: &mut W,
-        track: &[MusicCommand],
-    ) -> eyre::Result<()> {
-        writeln!(wtr, "V15 @6-1")?;
+/// ループを展開したトラック内の 1 音。tone が None なら休符。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct NoteEvent {
+    pub frame: u32,
+    pub length: u32,
+    pub tone: Option<(u8, u8)>, // (octave, note)
+}
 
-        Self::write_mml_track(wtr, track, true)?;
+/// トラックのループを展開し、先頭から Restart/End までの音を返す。
+pub fn track_events(track: &[MusicCommand]) -> Vec<NoteEvent> {
+    let mut events = vec![];
+
+    let mut frame = 0;
+    let mut length_cur = None;
+    let mut i = 0;
+    let mut loop_state: Option<(usize, u8)> = None; // (ループ本体の先頭, 残り回数)
+
+    while i < track.len() {
+        match track[i] {
+            MusicCommand::Tone { octave, note } => {
+                let length = length_cur.expect("length_cur not set");
+                events.push(NoteEvent {
+                    frame,
+                    length,
+                    tone: Some((octave, note)),
+                });
+                frame += length;
+            }
+            MusicCommand::Rest => {
+                let length = length_cur.expect("length_cur not set");
+                events.push(NoteEvent {
+                    frame,
+                    length,
+                    tone: None,
+                });
+                frame += length;
+            }
+            MusicCommand::SetLength { length } => length_cur = Some(u32::from(length)),
+            MusicCommand::LoopBegin { count } => loop_state = Some((i + 1, count)),
+            MusicCommand::LoopEnd => {
+                let (body, count) = loop_state.expect("not in loop");
+                if count > 1 {
+                    loop_state = Some((body, count - 1));
+                    i = body;
+                    continue;
+                }
+                loop_state = None;
+            }
+            MusicCommand::Restart | MusicCommand::End => break,
+        }
+        i += 1;
+    }
 
-        writeln!(wtr, ";")?;
+    events
+}
 
-        Ok(())
-    }
+/// トラック内のループ 1 つ。フレーム数はループを展開した位置で数える。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct LoopInfo {
+    /// ループ開始位置 (フレーム)。
+    pub start_frame: u32,
+    /// ループ本体 1 回分の長さ (フレーム)。
+    pub body_frames: u32,
+    pub count: u8,
+}
 
-    fn write_mml_track<W: std::io::Write>(
-        wtr: &mut W,
-        track: &[MusicCommand],
-        tri: bool,
-    ) -> eyre::Result<()> {
-        let mut length_cur = None;
-
-        for &cmd in track {
-            match cmd {
-                MusicCommand::Tone { octave, note } => {
-                    let note_str = Self::note_to_str(note);
-                    let tick = Self::length_to_tick(length_cur.expect("length_cur not set"));
-                    if tri {
-                        // 三角波の場合、3/4 発音、1/4 消音する。
-                        write!(
-                            wtr,
-                            "O{}{}%{} R%{} ",
-                            octave,
-                            note_str,
-                            tick * 3 / 4,
-                            tick / 4
-                        )?;
-                    } else {
-                        write!(wtr, "O{}{}%{} ", octave, note_str, tick,)?;
-                    }
-                }
-                MusicCommand::Rest => {
-                    write!(
-                        wtr,
-                        "R%{} ",
-                        Self::length_to_tick(length_cur.expect("length_cur not set"))
-                    )?;
+/// トラック内のループを展開後の位置とともに返す。
+pub fn track_loops(track: &[MusicCommand]) -> Vec<LoopInfo> {
+    let mut loops = Vec::new();
+
+    let mut frame = 0;
+    let mut length = 0;
+    let mut current: Option<LoopInfo> = None;
+    for &cmd in track {
+        match cmd {
+            MusicCommand::Tone { .. } | MusicCommand::Rest => match &mut current {
+                Some(info) => info.body_frames += length,
+                None => frame += length,
+            },
+            MusicCommand::SetLength { length: l } => length = u32::from(l),
+            MusicCommand::LoopBegin { count } => {
+                current = Some(LoopInfo {
+                    start_frame: frame,
+                    body_frames: 0,
+                    count,
+                });
+            }
+            MusicCommand::LoopEnd => {
+                if let Some(info) = current.take() {
+                    frame += u32::from(info.count) * info.body_frames;
+                    loops.push(info);
                 }
-                MusicCommand::SetLength { length } => length_cur = Some(u32::from(length)),
-                MusicCommand::LoopBegin { count } => write!(wtr, "/:{} ", count)?,
-                MusicCommand::LoopEnd => write!(wtr, ":/ ")?,
-                MusicCommand::Restart => break, // MML では無限ループは書けないらしい
-                MusicCommand::End => break,
             }
+            MusicCommand::Restart | MusicCommand::End => break,
         }
-
-        Ok(())
     }
 
-    fn note_to_str(note: u8) -> &'static str {
-        match note {
-            0 => "C",
-            1 => "C+",
-            2 => "D",
-            3 => "D+",
-            4 => "E",
-            5 => "F",
-            6 => "F+",
-            7 => "G",
-            8 => "G+",
-            9 => "A",
-            10 => "A+",
-            11 => "B",
-            _ => unreachable!(),
-        }
-    }
+    loops
+}
 
-    fn length_to_tick(length: u32) -> u32 {
-        2 * length
-    }
+/// 音符の周波数 (Hz) を返す。O4A が 440Hz。
+pub fn tone_frequency(octave: u8, note: u8) -> f64 {
+    let semitone = 12 * (i32::from(octave) - 4) + i32::from(note) - 9;
+    440.0 * 2f64.powf(f64::from(semitone) / 12.0)
 }
 
 pub fn load_musics(rom: &Rom) -> Vec<Music> {
+    load_musics_with_extents(rom)
+        .into_iter()
+        .map(|(music, _)| music)
+        .collect()
+}
+
+/// 各曲の (sq1, sq2, tri) トラックの (アドレス, バイト数) を返す。
+pub(crate) fn load_music_track_extents(rom: &Rom) -> Vec<[(u16, usize); 3]> {
+    load_musics_with_extents(rom)
+        .into_iter()
+        .map(|(_, extents)| extents)
+        .collect()
+}
+
+fn load_musics_with_extents(rom: &Rom) -> Vec<(Music, [(u16, usize); 3])> {
     let cfgs = load_music_cfgs(rom);
     let ptrss = load_music_ptrss(rom);
 
@@ -229,14 +269,16 @@ pub fn load_musics(rom: &Rom) -> Vec<Music> {
             let id = u8::try_from(i + 1).unwrap();
 
             // sq1 トラックは必ず 0xFE または 0xFF で終端されている。
-            let (track_sq1, length_sq1) = load_track(rom, ptrs[0], None, false);
+            let (track_sq1, length_sq1, size_sq1) = load_track(rom, ptrs[0], None, false);
 
             // ループ曲(sq1 が 0xFE 終端)の場合、sq2, tri トラックには 0xFE 終端がない。
             // よって、load_track() に length_expect 引数を与える必要がある。
             let music_loop = matches!(track_sq1.last().unwrap(), MusicCommand::Restart);
             let length_expect = if music_loop { Some(length_sq1) } else { None };
-            let (mut track_sq2, length_sq2) = load_track(rom, ptrs[1], length_expect, false);
-            let (mut track_tri, length_tri) = load_track(rom, ptrs[2], length_expect, true);
+            let (mut track_sq2, length_sq2, size_sq2) =
+                load_track(rom, ptrs[1], length_expect, false);
+            let (mut track_tri, length_tri, size_tri) =
+                load_track(rom, ptrs[2], length_expect, true);
             if music_loop {
                 track_sq2.push(MusicCommand::Restart);
                 track_tri.push(MusicCommand::Restart);
@@ -245,14 +287,21 @@ pub fn load_musics(rom: &Rom) -> Vec<Music> {
             assert_eq!(length_sq1, length_sq2);
             assert_eq!(length_sq1, length_tri);
 
-            Music {
+            let music = Music {
                 id,
                 sq_envelope,
                 sq_duty,
                 track_sq1,
                 track_sq2,
                 track_tri,
-            }
+            };
+            let extents = [
+                (ptrs[0], size_sq1),
+                (ptrs[1], size_sq2),
+                (ptrs[2], size_tri),
+            ];
+
+            (music, extents)
         })
         .collect()
 }
@@ -272,22 +321,31 @@ fn load_music_cfgs(rom: &Rom) -> Vec<(u8, SquareDuty)> {
 }
 
 fn load_music_ptrss(rom: &Rom) -> Vec<[u16; 3]> {
-    rom.prg[prg_offset(0xBBA6)..]
+    rom.prg[prg_offset(ADDR_MUSIC_PTR_TABLE)..]
         .chunks(6)
         .take(MUSIC_COUNT)
-        .map(|buf| {
-            [
-                LE::read_u16(&buf[0..]),
-                LE::read_u16(&buf[2..]),
-                LE::read_u16(&buf[4..]),
-            ]
-        })
+        .map(read_music_ptrs)
         .collect()
 }
 
+/// 読み込みで無視している無音の BGM (ID 10) の (sq1, sq2, tri) トラックのアドレス。
+/// ポインタテーブルで MUSIC_COUNT 曲の直後にある。
+pub(crate) fn load_silent_music_ptrs(rom: &Rom) -> [u16; 3] {
+    let offset = prg_offset(ADDR_MUSIC_PTR_TABLE) + 6 * MUSIC_COUNT;
+    read_music_ptrs(&rom.prg[offset..])
+}
+
+fn read_music_ptrs(buf: &[u8]) -> [u16; 3] {
+    [
+        LE::read_u16(&buf[0..]),
+        LE::read_u16(&buf[2..]),
+        LE::read_u16(&buf[4..]),
+    ]
+}
+
 /// rom 内アドレス ptr からトラックを読み込む。
 /// length_expect が指定された場合、音長の総和がちょうど length_expect になるまで読み込む。
-/// (トラック, 音長の総和) を返す。
+/// (トラック, 音長の総和, 読み込んだバイト数) を返す。
 ///
 /// length_expect 引数が必要な理由は、ループ曲(sq1 トラックが 0xFE で終わるもの)の場合、
 /// sq2, tri トラックには 0xFE 終端がないため。
@@ -296,7 +354,7 @@ fn load_track(
     ptr: u16,
     length_expect: Option<u32>,
     tri: bool,
-) -> (Vec<MusicCommand>, u32) {
+) -> (Vec<MusicCommand>, u32, usize) {
     let mut track = vec![];
 
     let prg = &rom.prg;
@@ -383,5 +441,5 @@ fn load_track(
         }
     }
 
-    (track, length)
+    (track, length, usize::from(offset))
 }
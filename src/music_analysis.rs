@@ -0,0 +1,158 @@
+// 曲の統計情報。資料作成や、デコード結果の確認に使う。
+
+use crate::music::*;
+
+/// Krumhansl-Kessler の長調/短調のキープロファイル (主音から順に)。
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+#[derive(Clone, Debug)]
+pub struct ChannelAnalysis {
+    /// ループを展開した長さ (フレーム)。
+    pub frame_count: u32,
+    pub note_count: usize,
+    pub rest_frames: u32,
+    /// (octave, note) の最低音と最高音。
+    pub lowest: Option<(u8, u8)>,
+    pub highest: Option<(u8, u8)>,
+    /// 音名 (C, C+, ..., B) ごとの音符数。
+    pub histogram: [u32; 12],
+    /// トラック内のループ (出現順)。
+    pub loops: Vec<LoopInfo>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MusicAnalysis {
+    pub id: u8,
+    pub frame_count: u32,
+    /// 先頭に戻るループ曲かどうか。
+    pub repeat: bool,
+    /// sq1, sq2, tri の順。
+    pub channels: [ChannelAnalysis; 3],
+    /// 全チャンネルの音符で最も多い音長 (フレーム)。
+    pub beat_frames: Option<u32>,
+    /// 推定した調の (主音, 長調か)。
+    pub key: Option<(u8, bool)>,
+}
+
+impl MusicAnalysis {
+    /// 1 フレーム = 1/60 秒とした演奏時間。
+    pub fn seconds(&self) -> f64 {
+        f64::from(self.frame_count) / 60.0
+    }
+
+    /// 推定テンポ。
+    ///
+    /// 曲データには音長 (フレーム数) しかなく、テンポや拍の情報はない。
+    /// そこで最も多い音長を 8 分音符と仮定して求める。仮定が外れると 2 倍や 1/2 倍の値になる。
+    pub fn estimated_bpm(&self) -> Option<f64> {
+        self.beat_frames.map(|frames| 1800.0 / f64::from(frames))
+    }
+
+    pub fn key_name(&self) -> Option<String> {
+        self.key.map(|(tonic, major)| {
+            format!(
+                "{} {}",
+                note_to_mml(tonic).replace('+', "#"),
+                if major { "major" } else { "minor" }
+            )
+        })
+    }
+}
+
+pub fn analyze_music(music: &Music) -> MusicAnalysis {
+    let tracks = [&music.track_sq1, &music.track_sq2, &music.track_tri];
+
+    let mut length_counts = std::collections::BTreeMap::<u32, u32>::new();
+    let mut weights = [0.0; 12];
+
+    let channels = [0, 1, 2].map(|ch| {
+        let track = tracks[ch];
+        let events = track_events(track);
+
+        let mut res = ChannelAnalysis {
+            frame_count: events.last().map_or(0, |ev| ev.frame + ev.length),
+            note_count: 0,
+            rest_frames: 0,
+            lowest: None,
+            highest: None,
+            histogram: [0; 12],
+            loops: track_loops(track),
+        };
+
+        for ev in &events {
+            match ev.tone {
+                Some(tone) => {
+                    res.note_count += 1;
+                    res.lowest = Some(res.lowest.map_or(tone, |t| t.min(tone)));
+                    res.highest = Some(res.highest.map_or(tone, |t| t.max(tone)));
+                    res.histogram[usize::from(tone.1)] += 1;
+                    weights[usize::from(tone.1)] += f64::from(ev.length);
+                    *length_counts.entry(ev.length).or_default() += 1;
+                }
+                None => res.rest_frames += ev.length,
+            }
+        }
+
+        res
+    });
+
+    let beat_frames = length_counts
+        .iter()
+        .max_by_key(|&(&length, &count)| (count, std::cmp::Reverse(length)))
+        .map(|(&length, _)| length);
+
+    MusicAnalysis {
+        id: music.id,
+        frame_count: music.frame_count(),
+        repeat: matches!(music.track_sq1.last(), Some(MusicCommand::Restart)),
+        channels,
+        beat_frames,
+        key: estimate_key(&weights),
+    }
+}
+
+/// 音長で重み付けした音名分布から、Krumhansl-Schmuckler 法で調を推定する。
+fn estimate_key(weights: &[f64; 12]) -> Option<(u8, bool)> {
+    if weights.iter().all(|&w| w == 0.0) {
+        return None;
+    }
+
+    let mut best = None;
+    for tonic in 0..12 {
+        for &(major, profile) in &[(true, &MAJOR_PROFILE), (false, &MINOR_PROFILE)] {
+            let rotated: Vec<f64> = (0..12).map(|i| profile[(i + 12 - tonic) % 12]).collect();
+            let r = correlation(weights, &rotated);
+            if best.map_or(true, |(r_best, _)| r > r_best) {
+                best = Some((r, (tonic as u8, major)));
+            }
+        }
+    }
+
+    best.map(|(_, key)| key)
+}
+
+fn correlation(xs: &[f64], ys: &[f64]) -> f64 {
+    let n = xs.len() as f64;
+    let mean_x = xs.iter().sum::<f64>() / n;
+    let mean_y = ys.iter().sum::<f64>() / n;
+
+    let mut cov = 0.0;
+    let mut var_x = 0.0;
+    let mut var_y = 0.0;
+    for (x, y) in itertools::zip(xs, ys) {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x).powi(2);
+        var_y += (y - mean_y).powi(2);
+    }
+
+    if var_x == 0.0 || var_y == 0.0 {
+        0.0
+    } else {
+        cov / (var_x * var_y).sqrt()
+    }
+}
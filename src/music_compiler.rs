@@ -0,0 +1,657 @@
+// 曲データをゲームのバイト列に変換して ROM に書き込む。load_track() の逆変換。
+
+use std::convert::TryFrom;
+
+use eyre::{bail, ensure, eyre};
+
+use crate::mml::*;
+use crate::music::*;
+use crate::rom::*;
+
+/// 音長設定命令 (0x80..=0xEF) で指定できる最大の音長。
+const LENGTH_MAX: u8 = 0x6F;
+
+/// 曲がゲームのデータとして表現できるか検査する。
+///
+/// load_musics() と同じく、各トラックの音長の総和が一致することも確かめる。
+pub fn validate_music(music: &Music) -> eyre::Result<()> {
+    ensure!(
+        music.sq_envelope <= 0x0F,
+        "music {}: invalid envelope: {}",
+        music.id,
+        music.sq_envelope
+    );
+
+    let terminator = *music
+        .track_sq1
+        .last()
+        .ok_or_else(|| eyre!("music {}: sq1 track is empty", music.id))?;
+    ensure!(
+        matches!(terminator, MusicCommand::Restart | MusicCommand::End),
+        "music {}: sq1 track must end with Restart or End",
+        music.id
+    );
+
+    let tracks = [&music.track_sq1, &music.track_sq2, &music.track_tri];
+    for (ch, track) in tracks.iter().enumerate() {
+        validate_track(track, ch == 2).map_err(|e| eyre!("music {} ch{}: {}", music.id, ch, e))?;
+        ensure!(
+            track.last() == Some(&terminator),
+            "music {} ch{}: track must end with {:?}",
+            music.id,
+            ch,
+            terminator
+        );
+    }
+
+    let lengths: Vec<u32> = tracks
+        .iter()
+        .map(|track| {
+            track_events(track)
+                .last()
+                .map_or(0, |ev| ev.frame + ev.length)
+        })
+        .collect();
+    ensure!(
+        lengths.iter().all(|&len| len == lengths[0]),
+        "music {}: track length mismatch: {:?}",
+        music.id,
+        lengths
+    );
+
+    Ok(())
+}
+
+fn validate_track(track: &[MusicCommand], tri: bool) -> eyre::Result<()> {
+    let mut length_set = false;
+    let mut in_loop = false;
+
+    for (i, &cmd) in track.iter().enumerate() {
+        match cmd {
+            MusicCommand::Tone { octave, note } => {
+                ensure!(length_set, "length is not set before tone");
+                ensure!(note <= 11, "invalid note: {}", note);
+                tone_to_op(octave, note, tri)?;
+            }
+            MusicCommand::Rest => ensure!(length_set, "length is not set before rest"),
+            MusicCommand::SetLength { length } => {
+                ensure!(
+                    (1..=LENGTH_MAX).contains(&length),
+                    "invalid length: {}",
+                    length
+                );
+                length_set = true;
+            }
+            MusicCommand::LoopBegin { count } => {
+                ensure!(!in_loop, "nested loop is not permitted");
+                ensure!(count > 0, "invalid loop count: {}", count);
+                in_loop = true;
+            }
+            MusicCommand::LoopEnd => {
+                ensure!(in_loop, "not in loop");
+                in_loop = false;
+            }
+            MusicCommand::Restart | MusicCommand::End => {
+                ensure!(!in_loop, "unclosed loop");
+                ensure!(i == track.len() - 1, "command after {:?}", cmd);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn tone_to_op(octave: u8, note: u8, tri: bool) -> eyre::Result<u8> {
+    // 三角波以外は load_track() で 1 オクターブ上げている。
+    let octave_raw = if tri {
+        Some(octave)
+    } else {
+        octave.checked_sub(1)
+    };
+    let op = octave_raw
+        .and_then(|o| o.checked_mul(12))
+        .and_then(|v| v.checked_add(note + 1))
+        .filter(|op| (25..=0x7F).contains(op))
+        .ok_or_else(|| eyre!("tone out of range: O{}{}", octave, note_to_mml(note)))?;
+
+    Ok(op)
+}
+
+/// トラックをゲームのバイト列に変換する。
+///
+/// ループ曲の sq2, tri トラックには 0xFE 終端がないので、omit_restart を真にすると
+/// 末尾の Restart を書き出さない。
+pub fn encode_track(
+    track: &[MusicCommand],
+    tri: bool,
+    omit_restart: bool,
+) -> eyre::Result<Vec<u8>> {
+    validate_track(track, tri)?;
+
+    let mut buf = Vec::with_capacity(track.len());
+    for &cmd in track {
+        match cmd {
+            MusicCommand::Tone { octave, note } => buf.push(tone_to_op(octave, note, tri)?),
+            MusicCommand::Rest => buf.push(0),
+            MusicCommand::SetLength { length } => buf.push(0x80 | length),
+            MusicCommand::LoopBegin { count } => buf.extend_from_slice(&[0xFD, count]),
+            MusicCommand::LoopEnd => buf.push(0xFC),
+            MusicCommand::Restart => {
+                if !omit_restart {
+                    buf.push(0xFE);
+                }
+            }
+            MusicCommand::End => buf.push(0xFF),
+        }
+    }
+
+    Ok(buf)
+}
+
+/// 曲の (sq1, sq2, tri) トラックをバイト列に変換する。
+pub fn encode_music(music: &Music) -> eyre::Result<[Vec<u8>; 3]> {
+    validate_music(music)?;
+
+    let music_loop = matches!(music.track_sq1.last(), Some(MusicCommand::Restart));
+
+    Ok([
+        encode_track(&music.track_sq1, false, false)?,
+        encode_track(&music.track_sq2, false, music_loop)?,
+        encode_track(&music.track_tri, true, music_loop)?,
+    ])
+}
+
+/// musics で指定した曲を差し替えて、全曲のトラックを元の曲データ領域に配置し直す。
+///
+/// 配置先は元の全トラックが占めていた領域に限る。余った領域は 0xFF で埋める。
+/// ポインタテーブル (ADDR_MUSIC_PTR_TABLE) と設定テーブル (0xB716) も更新する。
+pub fn patch_musics(rom: &mut Rom, musics: Vec<Music>) -> eyre::Result<()> {
+    let mut all = load_musics(rom);
+    for music in musics {
+        let i = usize::from(music.id)
+            .checked_sub(1)
+            .filter(|&i| i < all.len())
+            .ok_or_else(|| eyre!("invalid music id: {}", music.id))?;
+        all[i] = music;
+    }
+
+    let mut regions = music_data_regions(rom);
+    for &(begin, end) in &regions {
+        rom.prg[begin..end].fill(0xFF);
+    }
+
+    // 大きいトラックから先に詰める。
+    let mut tracks = Vec::new();
+    for (i, music) in all.iter().enumerate() {
+        for (ch, buf) in encode_music(music)?.iter().enumerate() {
+            tracks.push((i, ch, buf.clone()));
+        }
+    }
+    tracks.sort_by_key(|(_, _, buf)| std::cmp::Reverse(buf.len()));
+
+    for (i, ch, buf) in tracks {
+        let region = regions
+            .iter_mut()
+            .find(|(begin, end)| end - begin >= buf.len())
+            .ok_or_else(|| {
+                eyre!(
+                    "no space for music {} ch{} ({} bytes)",
+                    i + 1,
+                    ch,
+                    buf.len()
+                )
+            })?;
+        let offset = region.0;
+        rom.prg[offset..][..buf.len()].copy_from_slice(&buf);
+        region.0 += buf.len();
+
+        let addr = 0x8000 + u16::try_from(offset).unwrap();
+        let ptr = prg_offset(ADDR_MUSIC_PTR_TABLE) + 6 * i + 2 * ch;
+        rom.prg[ptr..][..2].copy_from_slice(&addr.to_le_bytes());
+    }
+
+    for (i, music) in all.iter().enumerate() {
+        rom.prg[prg_offset(0xB716) + i] = music.sq_envelope | (music.sq_duty.value() << 6);
+    }
+
+    Ok(())
+}
+
+/// 元の全トラックが占める PRG オフセットの範囲 (重なりや隣接はまとめる)。
+fn music_data_regions(rom: &Rom) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<_> = load_music_track_extents(rom)
+        .into_iter()
+        .flatten()
+        .map(|(addr, len)| (prg_offset(addr), prg_offset(addr) + len))
+        .collect();
+    ranges.sort_unstable();
+
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    for (begin, end) in ranges {
+        match regions.last_mut() {
+            Some(last) if begin <= last.1 => last.1 = last.1.max(end),
+            _ => regions.push((begin, end)),
+        }
+    }
+
+    regions
+}
+
+/// 制限付きの MML (Ppmck 方言の出力と同じ形式) を読み込む。
+///
+/// - `#` で始まる行は無視する。
+/// - `@v0 = { ... }` の音量列は、一致するハードウェアエンベロープの値に変換する。
+/// - トラック A, B, C がそれぞれ sq1, sq2, tri に対応する。
+/// - 音長は `%n` (フレーム数) でのみ指定できる。t, v, @vN は無視する。
+/// - @n は矩形波のデューティとする。
+/// - トラック先頭の L は曲の先頭からの無限ループ (Restart) とする。
+/// - C トラックの音符は 1 オクターブ上げて書かれているものとする (三角波は 1 オクターブ低く鳴るため)。
+/// - C トラックで音符の直後に 3/4 発音となる休符がある場合、1 つの音符にまとめる。
+pub fn parse_music_mml(src: &str, id: u8) -> eyre::Result<Music> {
+    let mut sq_envelope = None;
+    let mut sources = [String::new(), String::new(), String::new()];
+
+    for line in src.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("@v0") {
+            let volumes = rest
+                .trim()
+                .trim_start_matches('=')
+                .trim()
+                .trim_start_matches('{')
+                .trim_end_matches('}')
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|s| !s.is_empty())
+                .map(str::parse)
+                .collect::<Result<Vec<u32>, _>>()?;
+            let envelope = (0..=0x0F)
+                .find(|&e| envelope_volumes(e) == volumes)
+                .ok_or_else(|| eyre!("volume envelope is not a hardware envelope"))?;
+            sq_envelope = Some(envelope);
+            continue;
+        }
+
+        let mut chars = line.chars();
+        let ch = match chars.next() {
+            Some('A') => 0,
+            Some('B') => 1,
+            Some('C') => 2,
+            _ => bail!("unknown line: {}", line),
+        };
+        let body = chars.as_str();
+        sources[ch].push(' ');
+        sources[ch].push_str(body);
+    }
+
+    let mut duty = None;
+    let mut tracks = Vec::new();
+    let mut repeats = Vec::new();
+    for (ch, src) in sources.iter().enumerate() {
+        let parsed = parse_mml_track(src, ch == 2).map_err(|e| eyre!("track {}: {}", ch, e))?;
+        if let Some(d) = parsed.duty {
+            ensure!(
+                duty.map_or(true, |duty| duty == d),
+                "sq1 and sq2 duties differ"
+            );
+            duty = Some(d);
+        }
+        repeats.push(parsed.repeat);
+        tracks.push(parsed.track);
+    }
+    ensure!(
+        repeats.iter().all(|&r| r == repeats[0]),
+        "L must be given to all tracks or none"
+    );
+
+    let music = Music {
+        id,
+        sq_envelope: sq_envelope.ok_or_else(|| eyre!("@v0 is not defined"))?,
+        sq_duty: SquareDuty::new(duty.unwrap_or(2)),
+        track_tri: tracks.pop().unwrap(),
+        track_sq2: tracks.pop().unwrap(),
+        track_sq1: tracks.pop().unwrap(),
+    };
+    validate_music(&music)?;
+
+    Ok(music)
+}
+
+#[derive(Debug)]
+struct ParsedTrack {
+    track: Vec<MusicCommand>,
+    duty: Option<u8>,
+    repeat: bool,
+}
+
+/// MML のトラック内の要素。音長の設定はまだ含まない。
+#[derive(Clone, Copy, Debug)]
+enum MmlItem {
+    Tone { octave: u8, note: u8, length: u32 },
+    Rest { length: u32 },
+    LoopBegin,
+    LoopEnd { count: u8 },
+}
+
+fn parse_mml_track(src: &str, tri: bool) -> eyre::Result<ParsedTrack> {
+    let mut chars = src.chars().peekable();
+    let mut items = Vec::new();
+    let mut octave = 4;
+    let mut duty = None;
+    let mut repeat = false;
+
+    fn number(chars: &mut std::iter::Peekable<std::str::Chars>) -> eyre::Result<u32> {
+        let mut s = String::new();
+        while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            s.push(c);
+            chars.next();
+        }
+        ensure!(!s.is_empty(), "number expected");
+        Ok(s.parse()?)
+    }
+
+    fn length(chars: &mut std::iter::Peekable<std::str::Chars>) -> eyre::Result<u32> {
+        ensure!(chars.next() == Some('%'), "length must be given as %n");
+        number(chars)
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            _ if c.is_whitespace() => {}
+            't' | 'v' => {
+                number(&mut chars)?;
+            }
+            '@' => {
+                if chars.peek() == Some(&'v') {
+                    chars.next();
+                    number(&mut chars)?;
+                } else {
+                    let d = number(&mut chars)?;
+                    ensure!(!tri && d <= 3, "invalid duty: @{}", d);
+                    duty = Some(d as u8);
+                }
+            }
+            'L' => {
+                ensure!(items.is_empty(), "L must be at the beginning of track");
+                repeat = true;
+            }
+            'o' => octave = u8::try_from(number(&mut chars)?)?,
+            'a'..='g' => {
+                let base = match c {
+                    'c' => 0,
+                    'd' => 2,
+                    'e' => 4,
+                    'f' => 5,
+                    'g' => 7,
+                    'a' => 9,
+                    'b' => 11,
+                    _ => unreachable!(),
+                };
+                let shift: i32 = match chars.peek() {
+                    Some('+') | Some('#') => 1,
+                    Some('-') => -1,
+                    _ => 0,
+                };
+                if shift != 0 {
+                    chars.next();
+                }
+                let value = 12 * i32::from(octave) + base + shift - if tri { 12 } else { 0 };
+                ensure!(value >= 0, "tone out of range");
+                items.push(MmlItem::Tone {
+                    octave: (value / 12) as u8,
+                    note: (value % 12) as u8,
+                    length: length(&mut chars)?,
+                });
+            }
+            'r' => items.push(MmlItem::Rest {
+                length: length(&mut chars)?,
+            }),
+            '[' => items.push(MmlItem::LoopBegin),
+            ']' => items.push(MmlItem::LoopEnd {
+                count: u8::try_from(number(&mut chars)?)?,
+            }),
+            _ => bail!("unexpected character: {}", c),
+        }
+    }
+
+    if tri {
+        items = merge_tri_articulation(items);
+    }
+
+    let mut track = items_to_track(&items)?;
+    track.push(if repeat {
+        MusicCommand::Restart
+    } else {
+        MusicCommand::End
+    });
+
+    Ok(ParsedTrack {
+        track,
+        duty,
+        repeat,
+    })
+}
+
+/// 三角波の 3/4 発音を表す「音符+休符」を 1 つの音符に戻す。
+/// 1 つの音符にまとめる休符は直後の 1 つだけで、その次の休符は本物の休符として残す。
+/// 休符の長さが tri_articulation() の消音部分と一致する場合だけまとめる。
+/// 音長 1 の音符は単独で書かれるので、その後の休符は本物の休符としてまとめない。
+fn merge_tri_articulation(items: Vec<MmlItem>) -> Vec<MmlItem> {
+    let mut res: Vec<MmlItem> = Vec::with_capacity(items.len());
+    let mut merged = false;
+
+    for item in items {
+        if !merged {
+            if let (Some(MmlItem::Tone { length: on, .. }), MmlItem::Rest { length: off }) =
+                (res.last_mut(), item)
+            {
+                if *on > 1 && tri_articulation(*on + off) == (*on, off) {
+                    *on += off;
+                    merged = true;
+                    continue;
+                }
+            }
+        }
+        res.push(item);
+        merged = false;
+    }
+
+    res
+}
+
+/// 音長設定を挟みつつ MusicCommand 列に変換する。
+///
+/// ループの 2 周目以降も正しい音長になるよう、ループ本体の最初の音の前では必ず音長を設定する。
+/// 音長の上限を超える休符は分割する。
+fn items_to_track(items: &[MmlItem]) -> eyre::Result<Vec<MusicCommand>> {
+    fn set_length(
+        track: &mut Vec<MusicCommand>,
+        length_cur: &mut Option<u8>,
+        length: u32,
+    ) -> eyre::Result<()> {
+        let length = u8::try_from(length)
+            .ok()
+            .filter(|l| (1..=LENGTH_MAX).contains(l))
+            .ok_or_else(|| eyre!("invalid length: {}", length))?;
+        if *length_cur != Some(length) {
+            track.push(MusicCommand::new_set_length(length));
+            *length_cur = Some(length);
+        }
+        Ok(())
+    }
+
+    let mut track = Vec::new();
+    let mut length_cur = None;
+
+    for &item in items {
+        match item {
+            MmlItem::Tone {
+                octave,
+                note,
+                length,
+            } => {
+                set_length(&mut track, &mut length_cur, length)?;
+                track.push(MusicCommand::new_tone(octave, note));
+            }
+            MmlItem::Rest { mut length } => {
+                while length > 0 {
+                    let l = length.min(u32::from(LENGTH_MAX));
+                    set_length(&mut track, &mut length_cur, l)?;
+                    track.push(MusicCommand::new_rest());
+                    length -= l;
+                }
+            }
+            MmlItem::LoopBegin => {
+                // 回数は LoopEnd で確定する。
+                track.push(MusicCommand::LoopBegin { count: 0 });
+                length_cur = None;
+            }
+            MmlItem::LoopEnd { count } => {
+                ensure!(count > 0, "invalid loop count: {}", count);
+                let begin = track
+                    .iter()
+                    .rposition(|cmd| matches!(cmd, MusicCommand::LoopBegin { count: 0 }))
+                    .ok_or_else(|| eyre!("not in loop"))?;
+                track[begin] = MusicCommand::new_loop_begin(count);
+                track.push(MusicCommand::new_loop_end());
+            }
+        }
+    }
+
+    Ok(track)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use MusicCommand::*;
+
+    fn tone(octave: u8, note: u8) -> MusicCommand {
+        MusicCommand::new_tone(octave, note)
+    }
+
+    fn len(length: u8) -> MusicCommand {
+        MusicCommand::new_set_length(length)
+    }
+
+    fn new_loop(count: u8) -> MusicCommand {
+        MusicCommand::new_loop_begin(count)
+    }
+
+    /// Ppmck で書き出して読み戻し、コンパイルする。
+    fn round_trip(music: &Music) -> Music {
+        let mut buf = Vec::new();
+        music.write_mml_dialect(&mut buf, &Ppmck).unwrap();
+        let src = String::from_utf8(buf).unwrap();
+        let parsed = parse_music_mml(&src, music.id).unwrap_or_else(|e| panic!("{}\n{}", e, src));
+        encode_music(&parsed).unwrap();
+        parsed
+    }
+
+    fn assert_same_events(a: &Music, b: &Music) {
+        let tracks = |m: &Music| {
+            [&m.track_sq1, &m.track_sq2, &m.track_tri]
+                .iter()
+                .map(|track| {
+                    track_events(track)
+                        .into_iter()
+                        .map(|ev| (ev.frame, ev.length, ev.tone))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tracks(a), tracks(b));
+        assert_eq!(a.track_sq1.last(), b.track_sq1.last());
+    }
+
+    #[test]
+    fn round_trip_music() {
+        let music = Music {
+            id: 3,
+            sq_envelope: 5,
+            sq_duty: SquareDuty::Quarter,
+            track_sq1: vec![
+                len(8),
+                tone(4, 0),
+                tone(4, 4),
+                new_loop(2),
+                len(4),
+                tone(5, 7),
+                Rest,
+                LoopEnd,
+                len(16),
+                tone(3, 11),
+                Restart,
+            ],
+            track_sq2: vec![len(32), Rest, len(16), tone(3, 9), Restart],
+            track_tri: vec![
+                len(6),
+                tone(2, 0),
+                len(2),
+                Rest,
+                len(8),
+                tone(3, 5),
+                tone(2, 1),
+                len(1),
+                tone(2, 2),
+                Rest,
+                len(2),
+                tone(2, 3),
+                len(20),
+                Rest,
+                Restart,
+            ],
+        };
+        validate_music(&music).unwrap();
+
+        let parsed = round_trip(&music);
+        assert_same_events(&music, &parsed);
+        assert_eq!(parsed.sq_envelope, music.sq_envelope);
+        assert_eq!(parsed.sq_duty, music.sq_duty);
+    }
+
+    #[test]
+    fn triangle_rest_after_articulation_is_kept() {
+        // 音長 6 の音符は %4 r%2 と書かれ、その後の r%2 は本物の休符。
+        let items = vec![
+            MmlItem::Tone {
+                octave: 3,
+                note: 0,
+                length: 4,
+            },
+            MmlItem::Rest { length: 2 },
+            MmlItem::Rest { length: 2 },
+        ];
+        let merged = merge_tri_articulation(items);
+        assert!(matches!(
+            merged.as_slice(),
+            [MmlItem::Tone { length: 6, .. }, MmlItem::Rest { length: 2 }]
+        ));
+    }
+
+    #[test]
+    fn triangle_rest_after_length_1_note_is_kept() {
+        let items = vec![
+            MmlItem::Tone {
+                octave: 3,
+                note: 0,
+                length: 1,
+            },
+            MmlItem::Rest { length: 1 },
+        ];
+        let merged = merge_tri_articulation(items);
+        assert!(matches!(
+            merged.as_slice(),
+            [MmlItem::Tone { length: 1, .. }, MmlItem::Rest { length: 1 }]
+        ));
+    }
+
+    #[test]
+    fn non_ascii_line_is_error() {
+        assert!(parse_music_mml("あ o4c%8", 1).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+// 曲をフレーム単位のチャンネルイベント列、および APU レジスタ書き込み列に変換する。
+//
+// 矩形波はハードウェアエンベロープ、三角波は 3/4 発音で鳴らす。
+// 周期レジスタ値は ROM 内の周期テーブル (locate_period_table()) から引く。
+// 休符の先頭ではその時点で鳴っている音を止める。
+
+use eyre::eyre;
+use serde::Serialize;
+
+use crate::apu::*;
+use crate::mml::*;
+use crate::music::*;
+use crate::sound_driver::*;
+
+/// 長さカウンタのロード値のインデックス (254。120 Hz で減るので約 127 フレーム)。
+/// 音符の途中で長さカウンタにより止まらないよう、最大の値を使う。
+const LENGTH_INDEX: u8 = 1;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum ChannelEvent {
+    /// period は NES のタイマー周期レジスタ値。duty, envelope は矩形波のみ。
+    NoteOn {
+        channel: ApuChannel,
+        octave: u8,
+        note: u8,
+        period: u16,
+        duty: Option<SquareDuty>,
+        envelope: Option<u8>,
+    },
+    NoteOff {
+        channel: ApuChannel,
+    },
+}
+
+/// 1 フレーム分のイベント。
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+pub struct MusicFrame {
+    pub frame: u32,
+    pub events: Vec<ChannelEvent>,
+}
+
+/// フレームごとのイベントを返すイテレータ。イベントのないフレームも返す。
+#[derive(Debug)]
+pub struct MusicTimeline {
+    events: Vec<(u32, ChannelEvent)>,
+    pos: usize,
+    frame: u32,
+    n_frame: u32,
+}
+
+impl MusicTimeline {
+    /// 曲の先頭から Restart/End までを返す。
+    /// 周期テーブルにない音符を含む場合はエラー。
+    pub fn new(music: &Music, periods: &PeriodTable) -> eyre::Result<Self> {
+        let tracks = [
+            (ApuChannel::Square1, &music.track_sq1),
+            (ApuChannel::Square2, &music.track_sq2),
+            (ApuChannel::Triangle, &music.track_tri),
+        ];
+
+        let mut events = Vec::new();
+        for &(channel, track) in &tracks {
+            let tri = channel == ApuChannel::Triangle;
+            let mut sounding = false;
+            for ev in track_events(track) {
+                match ev.tone {
+                    Some((octave, note)) => {
+                        let period = periods.tone_period(octave, note, tri).ok_or_else(|| {
+                            eyre!(
+                                "music {}: {:?} octave {} note {} is out of the period table",
+                                music.id,
+                                channel,
+                                octave,
+                                note
+                            )
+                        })?;
+                        events.push((
+                            ev.frame,
+                            ChannelEvent::NoteOn {
+                                channel,
+                                octave,
+                                note,
+                                period,
+                                duty: (!tri).then_some(music.sq_duty),
+                                envelope: (!tri).then_some(music.sq_envelope),
+                            },
+                        ));
+                        if tri {
+                            let on = tri_note_on(ev.length);
+                            events.push((ev.frame + on, ChannelEvent::NoteOff { channel }));
+                        }
+                        sounding = !tri;
+                    }
+                    None => {
+                        if sounding {
+                            events.push((ev.frame, ChannelEvent::NoteOff { channel }));
+                        }
+                        sounding = false;
+                    }
+                }
+            }
+        }
+        // 同じフレームでは NoteOff を先にし、その中では sq1, sq2, tri の順とする。
+        events.sort_by_key(|&(frame, ev)| (frame, matches!(ev, ChannelEvent::NoteOn { .. })));
+
+        Ok(Self {
+            events,
+            pos: 0,
+            frame: 0,
+            n_frame: music.frame_count(),
+        })
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.n_frame
+    }
+}
+
+impl Iterator for MusicTimeline {
+    type Item = MusicFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame >= self.n_frame {
+            return None;
+        }
+
+        let mut events = Vec::new();
+        while let Some(&(_, ev)) = self.events[self.pos..]
+            .first()
+            .filter(|(frame, _)| *frame == self.frame)
+        {
+            events.push(ev);
+            self.pos += 1;
+        }
+
+        let res = MusicFrame {
+            frame: self.frame,
+            events,
+        };
+        self.frame += 1;
+
+        Some(res)
+    }
+}
+
+/// 曲を APU レジスタ書き込み列に変換する。
+/// フレーム 0 の最初に $4015 でチャンネルを有効化する。
+pub fn music_apu_writes(music: &Music, periods: &PeriodTable) -> eyre::Result<Vec<ApuWrite>> {
+    let mut writes = vec![ApuWrite {
+        frame: 0,
+        addr: 0x4015,
+        value: 0x07,
+    }];
+
+    for music_frame in MusicTimeline::new(music, periods)? {
+        let frame = music_frame.frame;
+        let mut push = |addr: u16, value: u8| writes.push(ApuWrite { frame, addr, value });
+
+        for ev in music_frame.events {
+            match ev {
+                ChannelEvent::NoteOn {
+                    channel,
+                    period,
+                    duty,
+                    envelope,
+                    ..
+                } => {
+                    let base = channel.reg_base();
+                    let [lo, hi] = period.to_le_bytes();
+                    match channel {
+                        ApuChannel::Triangle => push(base, 0xFF),
+                        _ => {
+                            let duty = duty.unwrap().value();
+                            push(base, (duty << 6) | envelope.unwrap());
+                            push(base + 1, 0x08); // スイープ無効
+                        }
+                    }
+                    push(base + 2, lo);
+                    push(base + 3, (LENGTH_INDEX << 3) | (hi & 7));
+                }
+                ChannelEvent::NoteOff { channel } => {
+                    let base = channel.reg_base();
+                    match channel {
+                        ApuChannel::Triangle => push(base, 0x80),
+                        _ => push(base, (music.sq_duty.value() << 6) | 0x30),
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(writes)
+}
+
+impl Music {
+    /// APU レジスタ書き込みを VGM 形式で出力する。Restart で終わる曲は全体をループ区間とする。
+    pub fn write_vgm<W: std::io::Write>(&self, wtr: W, periods: &PeriodTable) -> eyre::Result<()> {
+        let repeat = matches!(self.track_sq1.last(), Some(MusicCommand::Restart));
+        let loop_frame = if repeat { Some(0) } else { None };
+
+        let writes = music_apu_writes(self, periods)?;
+
+        write_apu_vgm(wtr, &writes, self.frame_count(), loop_frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triangle_note_of_length_1_is_cut() {
+        let sq = vec![
+            MusicCommand::new_set_length(3),
+            MusicCommand::new_rest(),
+            MusicCommand::new_end(),
+        ];
+        let tri = vec![
+            MusicCommand::new_set_length(1),
+            MusicCommand::new_tone(3, 0),
+            MusicCommand::new_rest(),
+            MusicCommand::new_rest(),
+            MusicCommand::new_end(),
+        ];
+        let music = Music {
+            id: 1,
+            sq_envelope: 0,
+            sq_duty: SquareDuty::Half,
+            track_sq1: sq.clone(),
+            track_sq2: sq,
+            track_tri: tri,
+        };
+        let periods = PeriodTable {
+            addr_lo: 0,
+            addr_hi: 0,
+            stride: 1,
+            value_first: 0,
+            periods: vec![0x100; 96],
+        };
+
+        let frames: Vec<MusicFrame> = MusicTimeline::new(&music, &periods).unwrap().collect();
+        assert!(matches!(
+            frames[0].events.as_slice(),
+            [ChannelEvent::NoteOn {
+                channel: ApuChannel::Triangle,
+                ..
+            }]
+        ));
+        assert_eq!(
+            frames[1].events,
+            [ChannelEvent::NoteOff {
+                channel: ApuChannel::Triangle
+            }]
+        );
+    }
+}
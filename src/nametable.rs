@@ -0,0 +1,143 @@
+// 地形を 1 画面ずつ PPU のネームテーブル/属性テーブルに変換する。
+//
+// 1 画面は 16x15 セル (32x30 タイル)。地形は 20 セル幅なので、左端の列を指定して 16 列を切り出す。
+// パレットセットは地形の前半 (行 0..128) と後半 (行 128..256) で異なるので、
+// 画面は前半/後半それぞれの行 0 から 15 行ずつ区切る。各半分の最後の画面は 8 行しかない。
+// その上の段は、セル 0 (アイテム) ではなく空白のタイルとパレット 0 で埋める。
+
+use std::ops::Range;
+
+use eyre::eyre;
+
+use crate::game::*;
+
+pub const SCREEN_CELL_COLUMNS: u8 = 16;
+pub const SCREEN_CELL_ROWS: u32 = 15;
+
+/// パレットセットが切り替わる行。
+const HALF_ROWS: u32 = 128;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Nametable {
+    /// 32x30 のタイル番号。
+    pub tiles: Vec<u8>,
+    /// 8x8 の属性バイト。
+    pub attrs: Vec<u8>,
+}
+
+impl Nametable {
+    /// 属性テーブル込みの 1KB (NES Screen Tool, NEXXT の .nam 形式)。
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = self.tiles.clone();
+        res.extend(&self.attrs);
+        res
+    }
+}
+
+/// 地形の 1 画面分の範囲。
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct GroundScreen {
+    /// 地形の前半 (0) /後半 (1)。パレットセットの選択に使う。
+    pub half: usize,
+    /// 表示する行。最下段が rows.start。
+    pub rows: Range<u32>,
+}
+
+/// 地形 256 行を下から覆う画面を返す。どの画面も前半/後半の境界をまたがない。
+pub fn ground_screens() -> Vec<GroundScreen> {
+    (0..2)
+        .flat_map(|half| {
+            let end = HALF_ROWS * (half as u32 + 1);
+            (HALF_ROWS * half as u32..end)
+                .step_by(SCREEN_CELL_ROWS as usize)
+                .map(move |start| GroundScreen {
+                    half,
+                    rows: start..(start + SCREEN_CELL_ROWS).min(end),
+                })
+        })
+        .collect()
+}
+
+/// 画面 screen を column 列目から 16 列分ネームテーブルにする。
+/// 隠しセルは出現前の状態とする。screen.rows より上の段は空白のタイルとパレット 0 で埋める。
+/// BG パターンテーブルに空白のタイルがなければエラー。
+pub fn ground_nametable(
+    game: &Game,
+    ground: &Ground,
+    screen: &GroundScreen,
+    column: u8,
+    second_round: bool,
+) -> eyre::Result<Nametable> {
+    assert!(column + SCREEN_CELL_COLUMNS <= 20, "column out of range");
+
+    let blank = game
+        .blank_bg_tile(second_round)
+        .ok_or_else(|| eyre!("no blank tile in the BG pattern table"))?;
+
+    let mut tiles = vec![blank; 32 * 30];
+    let mut attrs = vec![0; 8 * 8];
+
+    for (i, j) in itertools::iproduct!(0..SCREEN_CELL_ROWS, 0..u32::from(SCREEN_CELL_COLUMNS)) {
+        // i は画面上端からのセル行
+        let r = screen.rows.start + (SCREEN_CELL_ROWS - 1 - i);
+        if !screen.rows.contains(&r) {
+            continue;
+        }
+        let cell = ground.cell(r as u8, column + j as u8);
+
+        let [tl, tr, bl, br] = game.cell_tile_ids(cell);
+        let (tx, ty) = (2 * j as usize, 2 * i as usize);
+        tiles[32 * ty + tx] = tl;
+        tiles[32 * ty + tx + 1] = tr;
+        tiles[32 * (ty + 1) + tx] = bl;
+        tiles[32 * (ty + 1) + tx + 1] = br;
+
+        let shift = 2 * (j % 2) + 4 * (i % 2);
+        attrs[(8 * (i / 2) + j / 2) as usize] |= game.cell_palette_index(cell) << shift;
+    }
+
+    Ok(Nametable { tiles, attrs })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screens_do_not_straddle_halves() {
+        let screens = ground_screens();
+
+        assert_eq!(screens.len(), 18);
+        assert_eq!(
+            screens[8],
+            GroundScreen {
+                half: 0,
+                rows: 120..128
+            }
+        );
+        assert_eq!(
+            screens[9],
+            GroundScreen {
+                half: 1,
+                rows: 128..143
+            }
+        );
+        assert_eq!(
+            screens[17],
+            GroundScreen {
+                half: 1,
+                rows: 248..256
+            }
+        );
+
+        let rows: Vec<u32> = screens
+            .iter()
+            .flat_map(|screen| screen.rows.clone())
+            .collect();
+        assert_eq!(rows, (0..256).collect::<Vec<_>>());
+        assert!(screens.iter().all(|screen| screen
+            .rows
+            .clone()
+            .all(|r| r / HALF_ROWS == screen.half as u32)));
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::Write;
+
+use eyre::ensure;
+
+use crate::music::*;
+use crate::rom::*;
+use crate::sound_driver::*;
+
+const NSF_HEADER_LEN: usize = 0x80;
+
+/// NTSC の 1 フレームの長さ (マイクロ秒)。
+const NTSC_SPEED: u16 = 16639;
+const PAL_SPEED: u16 = 19997;
+
+/// PRG 全体をそのまま載せた NSF を生成する。PRG は一切書き換えない。
+///
+/// NSF の init は A レジスタに 0 始まりの曲番号を渡し、$4015 への $0F の書き込みはプレイヤーが行う。
+/// よってサウンドドライバの init/play をそのまま NSF の init/play にできる。
+/// driver.id_base が 0 でない場合は、先頭に id_base 個の余分な曲を置き、開始曲を曲 1 に合わせる。
+pub fn build_nsf(rom: &Rom, driver: MusicDriver, title: &str) -> eyre::Result<Vec<u8>> {
+    let mut buf = vec![0; NSF_HEADER_LEN];
+    buf[..5].copy_from_slice(b"NESM\x1A");
+    buf[5] = 1;
+    buf[6] = (MUSIC_COUNT + usize::from(driver.id_base)) as u8;
+    buf[7] = nsf_track(driver, 1);
+    buf[8..10].copy_from_slice(&0x8000u16.to_le_bytes());
+    buf[0x0A..0x0C].copy_from_slice(&driver.init.to_le_bytes());
+    buf[0x0C..0x0E].copy_from_slice(&driver.play.to_le_bytes());
+    write_nsf_string(&mut buf[0x0E..0x2E], title)?;
+    write_nsf_string(&mut buf[0x2E..0x4E], "<?>")?;
+    write_nsf_string(&mut buf[0x4E..0x6E], "<?>")?;
+    buf[0x6E..0x70].copy_from_slice(&NTSC_SPEED.to_le_bytes());
+    buf[0x78..0x7A].copy_from_slice(&PAL_SPEED.to_le_bytes());
+
+    buf.extend_from_slice(&rom.prg);
+
+    Ok(buf)
+}
+
+/// 曲 music_id の NSF 上の曲番号 (1 始まり)。
+pub fn nsf_track(driver: MusicDriver, music_id: u8) -> u8 {
+    music_id + driver.id_base
+}
+
+fn write_nsf_string(dst: &mut [u8], s: &str) -> eyre::Result<()> {
+    ensure!(s.is_ascii(), "NSF string must be ASCII: {}", s);
+    ensure!(s.len() < dst.len(), "NSF string too long: {}", s);
+
+    dst[..s.len()].copy_from_slice(s.as_bytes());
+
+    Ok(())
+}
+
+/// Game Music Emu 形式の .m3u プレイリストを出力する。
+/// 演奏時間はループ曲なら 1 周分の長さとする。
+pub fn write_nsf_m3u<W: Write>(
+    mut wtr: W,
+    nsf_file_name: &str,
+    driver: MusicDriver,
+    musics: &[Music],
+    titles: &[String],
+) -> eyre::Result<()> {
+    for (music, title) in itertools::zip(musics, titles) {
+        let n_frame = music.frame_count();
+        let sec = (f64::from(n_frame) / 60.0).ceil() as u32;
+
+        writeln!(
+            wtr,
+            "{}::NSF,{},{},{}:{:02},,",
+            nsf_file_name,
+            nsf_track(driver, music.id),
+            title.replace(',', "\\,"),
+            sec / 60,
+            sec % 60,
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+// オブジェクト (敵、アイテム、爆発) の一覧。
+//
+// オブジェクト ID は出現テーブルの ID と共通で、1..=ENEMY_GROUP_COUNT は敵グループ ID でもある。
+
+use crate::enemy_group::*;
+
+/// オブジェクトの種類。
+///
+/// 空中物と地上物は当たり判定で区別する。空中物は自機と接触するが、地上物は自機が上を通過でき、
+/// 破壊されると地上物の爆発 (0x26, 0x27) になる。
+/// スターブレインは地形と一体の要塞なので、コアと砲台は地上物。
+/// OBJECT_INFOS の Air/Ground は ROM から求めたものではなく、この基準でゲーム画面から割り当てた仮定。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ObjectKind {
+    Air,
+    Ground,
+    Item,
+    Effect,
+}
+
+impl ObjectKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Air => "air",
+            Self::Ground => "ground",
+            Self::Item => "item",
+            Self::Effect => "effect",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ObjectInfo {
+    pub id: u8,
+    pub name_ja: &'static str,
+    /// 固有名はカタカナのローマ字表記 (長音は省略し、子音で終わる音には u を補う)。
+    /// 固有名でないもの (スターブレインの部位、アイテム、爆発) は英語の説明。
+    pub name_en: &'static str,
+    pub kind: ObjectKind,
+}
+
+impl ObjectInfo {
+    const fn new(id: u8, name_ja: &'static str, name_en: &'static str, kind: ObjectKind) -> Self {
+        Self {
+            id,
+            name_ja,
+            name_en,
+            kind,
+        }
+    }
+
+    /// "ID 日本語名 (英語名)" の形の表示用ラベル。
+    pub fn label(&self) -> String {
+        format!("{:02X} {} ({})", self.id, self.name_ja, self.name_en)
+    }
+}
+
+/// オブジェクト ID に対応する情報を返す。未使用の ID には None を返す。
+pub fn object_info(id: u8) -> Option<&'static ObjectInfo> {
+    OBJECT_INFOS
+        .iter()
+        .find(|info| info.id == id && !info.name_ja.is_empty())
+}
+
+impl EnemyGroup {
+    pub fn object_info(&self) -> Option<&'static ObjectInfo> {
+        object_info(self.id)
+    }
+}
+
+pub const OBJECT_INFOS: [ObjectInfo; 0x29] = {
+    use ObjectKind::*;
+    [
+        ObjectInfo::new(0x00, "", "", Air),
+        ObjectInfo::new(0x01, "レウス", "Reusu", Air),
+        ObjectInfo::new(0x02, "テュラ", "Tyura", Air),
+        ObjectInfo::new(0x03, "エイク", "Eiku", Air),
+        ObjectInfo::new(0x04, "ソレル", "Soreru", Air),
+        ObjectInfo::new(0x05, "ディダ", "Dida", Air),
+        ObjectInfo::new(0x06, "ペンド", "Pendo", Air),
+        ObjectInfo::new(0x07, "リアード", "Riado", Air),
+        ObjectInfo::new(0x08, "バタフ", "Batafu", Air),
+        ObjectInfo::new(0x09, "スラント", "Suranto", Air),
+        ObjectInfo::new(0x0A, "カルゴ", "Karugo", Air),
+        ObjectInfo::new(0x0B, "アトリス", "Atorisu", Air),
+        ObjectInfo::new(0x0C, "メルス", "Merusu", Air),
+        ObjectInfo::new(0x0D, "プリング", "Puringu", Air),
+        ObjectInfo::new(0x0E, "ヤール", "Yaru", Air),
+        ObjectInfo::new(0x0F, "ビーグ", "Bigu", Air),
+        ObjectInfo::new(0x10, "メーバ", "Meba", Air),
+        ObjectInfo::new(0x11, "ルイド", "Ruido", Air),
+        ObjectInfo::new(0x12, "ジェラ", "Jera", Air),
+        ObjectInfo::new(0x13, "ルダン", "Rudan", Air),
+        ObjectInfo::new(0x14, "リューク", "Ryuku", Air),
+        ObjectInfo::new(0x15, "ビータ", "Bita", Air),
+        ObjectInfo::new(0x16, "テミス", "Temisu", Air),
+        ObjectInfo::new(0x17, "パトラ", "Patora", Air),
+        ObjectInfo::new(0x18, "ドラク", "Doraku", Air),
+        ObjectInfo::new(0x19, "プリズン", "Purizun", Air),
+        ObjectInfo::new(0x1A, "カディス", "Kadisu", Air),
+        ObjectInfo::new(0x1B, "ステリア", "Suteria", Air),
+        ObjectInfo::new(0x1C, "リーデ", "Ride", Air),
+        ObjectInfo::new(0x1D, "グハ", "Guha", Air),
+        ObjectInfo::new(0x1E, "ジェリコ", "Jeriko", Ground),
+        ObjectInfo::new(0x1F, "ラザロ", "Razaro", Ground),
+        ObjectInfo::new(0x20, "", "", Air),
+        ObjectInfo::new(0x21, "ソープラー", "Sopura", Air),
+        ObjectInfo::new(0x22, "スターブレインのコア", "Star Brain core", Ground),
+        ObjectInfo::new(0x23, "スターブレインの砲台", "Star Brain turret", Ground),
+        ObjectInfo::new(0x24, "パワーアップアイテム", "Power-up item", Item),
+        ObjectInfo::new(0x25, "空中物の大爆発", "Air explosion (large)", Effect),
+        ObjectInfo::new(0x26, "地上物の大爆発", "Ground explosion (large)", Effect),
+        ObjectInfo::new(0x27, "地上物の小爆発", "Ground explosion (small)", Effect),
+        ObjectInfo::new(0x28, "空中物の小爆発", "Air explosion (small)", Effect),
+    ]
+};
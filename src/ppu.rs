@@ -4,7 +4,7 @@ use image::imageops;
 use image::{Rgba, RgbaImage};
 use once_cell::sync::Lazy;
 
-fn nes_color(id: u8) -> Rgba<u8> {
+pub(crate) fn nes_color(id: u8) -> Rgba<u8> {
     static COLORS: Lazy<[Rgba<u8>; 0x40]> = Lazy::new(|| {
         let buf = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/asset/fceux.pal"));
         assert!(buf.len() >= 3 * 0x40, "incomplete NES palette");
@@ -36,6 +36,10 @@ impl Palette {
     pub fn from_bytes(buf: impl AsRef<[u8]>) -> Self {
         Self::new(buf.as_ref()[..4].try_into().expect("incomplete palette"))
     }
+
+    pub fn color_ids(self) -> [u8; 4] {
+        self.0
+    }
 }
 
 impl std::ops::Index<usize> for Palette {
@@ -58,25 +62,41 @@ impl Tile {
         Self::new(buf.as_ref()[..16].try_into().expect("incomplete pattern"))
     }
 
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
     pub fn to_image(&self, plt: Palette, transparent: bool) -> RgbaImage {
         let mut img = RgbaImage::new(8, 8);
 
-        for y in 0..8u32 {
-            let byte_lo = self.0[y as usize];
-            let byte_hi = self.0[y as usize + 8];
-            for x in 0..8u32 {
-                let lo = (byte_lo >> (7 - x)) & 1;
-                let hi = (byte_hi >> (7 - x)) & 1;
-                let idx = lo | (hi << 1);
+        for (y, row) in self.pixel_indices().iter().enumerate() {
+            for (x, &idx) in row.iter().enumerate() {
                 if transparent && idx == 0 {
                     continue;
                 }
-                img.put_pixel(x, y, nes_color(plt[idx as usize]));
+                img.put_pixel(x as u32, y as u32, nes_color(plt[idx as usize]));
             }
         }
 
         img
     }
+
+    /// [y][x] の順の、各ピクセルのパレット内インデックス (0..=3)。
+    pub fn pixel_indices(&self) -> [[u8; 8]; 8] {
+        let mut res = [[0; 8]; 8];
+
+        for (y, row) in res.iter_mut().enumerate() {
+            let byte_lo = self.0[y];
+            let byte_hi = self.0[y + 8];
+            for (x, idx) in row.iter_mut().enumerate() {
+                let lo = (byte_lo >> (7 - x)) & 1;
+                let hi = (byte_hi >> (7 - x)) & 1;
+                *idx = lo | (hi << 1);
+            }
+        }
+
+        res
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -0,0 +1,281 @@
+// 画像出力の拡大処理。画像を出力する全コマンドで共通のオプションとして使う。
+//
+// 倍率 scale のうち、フィルタの倍率 (2 または 3) で割り切れる分はフィルタで拡大し、残りは最近傍で拡大する。
+// フィルタを指定した場合、scale はフィルタの倍率の倍数でなければならない。
+// 最後に、必要なら NES のピクセル比 8:7 に合わせて横方向に引き伸ばす。
+
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use structopt::StructOpt;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ScaleFilter {
+    Nearest,
+    Scale2x,
+    Scale3x,
+}
+
+impl std::str::FromStr for ScaleFilter {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Self::Nearest),
+            "scale2x" => Ok(Self::Scale2x),
+            "scale3x" => Ok(Self::Scale3x),
+            _ => Err(eyre::eyre!("unknown scale filter: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct ImageScaling {
+    /// 拡大倍率
+    #[structopt(long, default_value = "1", parse(try_from_str = parse_scale))]
+    pub scale: u32,
+
+    /// 拡大フィルタ (nearest, scale2x, scale3x)
+    #[structopt(long, default_value = "nearest")]
+    pub filter: ScaleFilter,
+
+    /// NES のピクセル比 8:7 に合わせて横に引き伸ばす
+    #[structopt(long)]
+    pub aspect: bool,
+}
+
+fn parse_scale(s: &str) -> eyre::Result<u32> {
+    let scale: u32 = s.parse()?;
+    eyre::ensure!(scale >= 1, "scale must be at least 1");
+
+    Ok(scale)
+}
+
+impl ScaleFilter {
+    /// フィルタ 1 回の倍率。Nearest は None。
+    fn step(self) -> Option<u32> {
+        match self {
+            Self::Nearest => None,
+            Self::Scale2x => Some(2),
+            Self::Scale3x => Some(3),
+        }
+    }
+}
+
+impl ImageScaling {
+    /// 倍率がフィルタに合っているか確認する。コマンドライン引数の解析後に呼ぶ。
+    pub fn validate(&self) -> eyre::Result<()> {
+        if let Some(step) = self.filter.step() {
+            eyre::ensure!(
+                self.scale % step == 0,
+                "scale {} is not a multiple of {} required by the filter",
+                self.scale,
+                step
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn apply(&self, img: &RgbaImage) -> RgbaImage {
+        let mut res = img.clone();
+
+        let mut scale = self.scale;
+        if let Some(step) = self.filter.step() {
+            while scale % step == 0 {
+                res = if step == 2 {
+                    scale2x(&res)
+                } else {
+                    scale3x(&res)
+                };
+                scale /= step;
+            }
+        }
+        if scale > 1 {
+            res = imageops::resize(
+                &res,
+                res.width() * scale,
+                res.height() * scale,
+                FilterType::Nearest,
+            );
+        }
+
+        if self.aspect {
+            let w = (res.width() * 8 + 3) / 7;
+            res = imageops::resize(&res, w, res.height(), FilterType::Nearest);
+        }
+
+        res
+    }
+
+    /// 横方向の拡大倍率。aspect なら 8:7 の引き伸ばし分を含む。
+    pub fn scale_x(&self) -> f64 {
+        if self.aspect {
+            f64::from(self.scale) * 8.0 / 7.0
+        } else {
+            f64::from(self.scale)
+        }
+    }
+
+    /// apply() してから保存する。
+    pub fn save(&self, img: &RgbaImage, path: impl AsRef<std::path::Path>) -> eyre::Result<()> {
+        self.apply(img).save(path)?;
+        Ok(())
+    }
+}
+
+/// 範囲外は端のピクセルとみなして (x+dx, y+dy) のピクセルを返す。
+fn pixel_clamped(img: &RgbaImage, x: u32, y: u32, dx: i32, dy: i32) -> Rgba<u8> {
+    let x = (x as i32 + dx).clamp(0, img.width() as i32 - 1) as u32;
+    let y = (y as i32 + dy).clamp(0, img.height() as i32 - 1) as u32;
+    *img.get_pixel(x, y)
+}
+
+fn scale2x(img: &RgbaImage) -> RgbaImage {
+    let mut res = RgbaImage::new(2 * img.width(), 2 * img.height());
+
+    for (x, y, &e) in img.enumerate_pixels() {
+        let px = |dx, dy| pixel_clamped(img, x, y, dx, dy);
+        let (b, d, f, h) = (px(0, -1), px(-1, 0), px(1, 0), px(0, 1));
+
+        let mut out = [e; 4];
+        if b != h && d != f {
+            if d == b {
+                out[0] = d;
+            }
+            if b == f {
+                out[1] = f;
+            }
+            if d == h {
+                out[2] = d;
+            }
+            if h == f {
+                out[3] = f;
+            }
+        }
+
+        for (k, &color) in out.iter().enumerate() {
+            let k = k as u32;
+            res.put_pixel(2 * x + k % 2, 2 * y + k / 2, color);
+        }
+    }
+
+    res
+}
+
+fn scale3x(img: &RgbaImage) -> RgbaImage {
+    let mut res = RgbaImage::new(3 * img.width(), 3 * img.height());
+
+    for (x, y, &e) in img.enumerate_pixels() {
+        let px = |dx, dy| pixel_clamped(img, x, y, dx, dy);
+        let (a, b, c) = (px(-1, -1), px(0, -1), px(1, -1));
+        let (d, f) = (px(-1, 0), px(1, 0));
+        let (g, h, i) = (px(-1, 1), px(0, 1), px(1, 1));
+
+        let mut out = [e; 9];
+        if b != h && d != f {
+            if d == b {
+                out[0] = d;
+            }
+            if (d == b && e != c) || (b == f && e != a) {
+                out[1] = b;
+            }
+            if b == f {
+                out[2] = f;
+            }
+            if (d == b && e != g) || (d == h && e != a) {
+                out[3] = d;
+            }
+            if (b == f && e != i) || (h == f && e != c) {
+                out[5] = f;
+            }
+            if d == h {
+                out[6] = d;
+            }
+            if (d == h && e != i) || (h == f && e != g) {
+                out[7] = h;
+            }
+            if h == f {
+                out[8] = f;
+            }
+        }
+
+        for (k, &color) in out.iter().enumerate() {
+            let k = k as u32;
+            res.put_pixel(3 * x + k % 3, 3 * y + k / 3, color);
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const X: Rgba<u8> = Rgba([0xFF, 0xFF, 0xFF, 0xFF]);
+    const O: Rgba<u8> = Rgba([0x00, 0x00, 0x00, 0xFF]);
+
+    /// 'X' と 'O' の行から画像を作る。
+    fn image_from_rows(rows: &[&str]) -> RgbaImage {
+        let mut img = RgbaImage::new(rows[0].len() as u32, rows.len() as u32);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, ch) in row.chars().enumerate() {
+                img.put_pixel(x as u32, y as u32, if ch == 'X' { X } else { O });
+            }
+        }
+        img
+    }
+
+    fn checkerboard() -> RgbaImage {
+        image_from_rows(&["XO", "OX"])
+    }
+
+    #[test]
+    fn scale2x_checkerboard() {
+        let expected = image_from_rows(&["XXOO", "XOXO", "OXOX", "OOXX"]);
+        assert_eq!(scale2x(&checkerboard()), expected);
+    }
+
+    #[test]
+    fn scale3x_checkerboard() {
+        let expected =
+            image_from_rows(&["XXXOOO", "XXOXOO", "XOOXXO", "OXXOOX", "OOXOXX", "OOOXXX"]);
+        assert_eq!(scale3x(&checkerboard()), expected);
+    }
+
+    #[test]
+    fn scale3x_keeps_isolated_pixel() {
+        let img = image_from_rows(&["OOO", "OXO", "OOO"]);
+        let res = scale3x(&img);
+        for (x, y, &px) in res.enumerate_pixels() {
+            let inside = (3..6).contains(&x) && (3..6).contains(&y);
+            assert_eq!(px, if inside { X } else { O }, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn apply_scale6_with_scale3x() {
+        let scaling = ImageScaling {
+            scale: 6,
+            filter: ScaleFilter::Scale3x,
+            aspect: false,
+        };
+        scaling.validate().unwrap();
+
+        let res = scaling.apply(&checkerboard());
+        assert_eq!(res.dimensions(), (12, 12));
+        // Scale3x の後に最近傍で 2 倍にしている。
+        let expected = imageops::resize(&scale3x(&checkerboard()), 12, 12, FilterType::Nearest);
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn validate_rejects_scale3_with_scale2x() {
+        let scaling = ImageScaling {
+            scale: 3,
+            filter: ScaleFilter::Scale2x,
+            aspect: false,
+        };
+        assert!(scaling.validate().is_err());
+    }
+}
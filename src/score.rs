@@ -0,0 +1,414 @@
+// 曲を 3 段の楽譜 (MusicXML, LilyPond) として出力する。
+//
+// write_mml() と同じく 1F = 192 分音符 (4 分音符 = 48F) とし、4/4 拍子で小節に区切る。
+// 楽譜上の最小単位は 64 分音符 (3F) なので、音の開始/終了時刻を 3F 単位に丸める。
+// 時刻を絶対値で丸めるので、誤差は蓄積しない。
+
+use std::io::Write;
+
+use crate::music::*;
+
+/// 64 分音符のフレーム数。
+const GRID_FRAMES: u32 = 3;
+
+/// 1 小節 (4/4) の 64 分音符の数。
+const MEASURE_UNITS: u32 = 64;
+
+const MEASURE_FRAMES: u32 = GRID_FRAMES * MEASURE_UNITS;
+
+/// 音符 1 つで表せる長さ (64 分音符単位)。付点を含む。長い順。
+const NOTE_UNITS: [u32; 12] = [64, 48, 32, 24, 16, 12, 8, 6, 4, 3, 2, 1];
+
+const PART_NAMES: [&str; 3] = ["Square 1", "Square 2", "Triangle"];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct ScoreNote {
+    tone: Option<(u8, u8)>,
+    units: u32,
+    tie_start: bool,
+    tie_stop: bool,
+}
+
+/// 繰り返し記号で表すループ。小節番号は 0 始まりで、end は含まない。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct ScoreRepeat {
+    begin: usize,
+    end: usize,
+    count: u8,
+}
+
+#[derive(Debug)]
+struct Score {
+    /// measures[i][part] が i 小節目のパートの音符列。
+    measures: Vec<[Vec<ScoreNote>; 3]>,
+    repeats: Vec<ScoreRepeat>,
+}
+
+impl Score {
+    /// 全トラックのループが同じ位置にあり、かつ小節線に揃っている場合は繰り返し記号で表す。
+    /// そうでなければループを展開する。
+    fn new(music: &Music) -> Self {
+        let tracks = [&music.track_sq1, &music.track_sq2, &music.track_tri];
+
+        let loopss: Vec<_> = tracks.iter().map(|track| track_loops(track)).collect();
+        let use_repeats = loopss.iter().all(|loops| *loops == loopss[0])
+            && loopss[0].iter().all(|info| {
+                info.start_frame % MEASURE_FRAMES == 0
+                    && info.body_frames % MEASURE_FRAMES == 0
+                    && info.body_frames > 0
+            });
+        let loops = if use_repeats {
+            loopss[0].clone()
+        } else {
+            vec![]
+        };
+
+        let parts: Vec<_> = tracks
+            .iter()
+            .map(|track| {
+                let events = written_events(&track_events(track), &loops);
+                split_measures(&events)
+            })
+            .collect();
+
+        let n_measure = parts.iter().map(Vec::len).max().unwrap_or(0);
+        let measures = (0..n_measure)
+            .map(|i| {
+                [0, 1, 2].map(|part| {
+                    parts[part].get(i).cloned().unwrap_or_else(|| {
+                        vec![ScoreNote {
+                            tone: None,
+                            units: MEASURE_UNITS,
+                            tie_start: false,
+                            tie_stop: false,
+                        }]
+                    })
+                })
+            })
+            .collect();
+
+        // 展開後の時刻から、楽譜上の小節番号に変換する。
+        let mut repeats = Vec::new();
+        let mut removed = 0;
+        for info in &loops {
+            let begin = (info.start_frame - removed) / MEASURE_FRAMES;
+            let len = info.body_frames / MEASURE_FRAMES;
+            repeats.push(ScoreRepeat {
+                begin: begin as usize,
+                end: (begin + len) as usize,
+                count: info.count,
+            });
+            removed += info.body_frames * (u32::from(info.count) - 1);
+        }
+
+        Self { measures, repeats }
+    }
+}
+
+/// 展開済みの音から、繰り返しで省略される 2 周目以降を除き、時刻を詰める。
+fn written_events(events: &[NoteEvent], loops: &[LoopInfo]) -> Vec<NoteEvent> {
+    events
+        .iter()
+        .filter_map(|ev| {
+            let mut frame = ev.frame;
+            for info in loops.iter().rev() {
+                let repeat_begin = info.start_frame + info.body_frames;
+                let repeat_end = info.start_frame + info.body_frames * u32::from(info.count);
+                if (repeat_begin..repeat_end).contains(&ev.frame) {
+                    return None;
+                }
+                if ev.frame >= repeat_end {
+                    frame -= repeat_end - repeat_begin;
+                }
+            }
+            Some(NoteEvent { frame, ..*ev })
+        })
+        .collect()
+}
+
+/// 音を 64 分音符単位に丸め、小節ごとに音符 (タイ付き) に分割する。
+fn split_measures(events: &[NoteEvent]) -> Vec<Vec<ScoreNote>> {
+    let quantize = |frame: u32| (frame + GRID_FRAMES / 2) / GRID_FRAMES;
+
+    // (音, 開始, 終了) を 64 分音符単位で。丸めで長さ 0 になった音は捨てる。
+    let spans: Vec<_> = events
+        .iter()
+        .map(|ev| (ev.tone, quantize(ev.frame), quantize(ev.frame + ev.length)))
+        .filter(|&(_, begin, end)| end > begin)
+        .collect();
+
+    let total = spans.last().map_or(0, |&(_, _, end)| end);
+    let n_measure = total.div_ceil(MEASURE_UNITS);
+    let mut measures = vec![Vec::new(); n_measure as usize];
+
+    let mut pos = 0;
+    let mut push_span = |tone: Option<(u8, u8)>, begin: u32, end: u32| {
+        let mut t = begin;
+        while t < end {
+            let measure_end = (t / MEASURE_UNITS + 1) * MEASURE_UNITS;
+            let seg_end = end.min(measure_end);
+            let mut units = seg_end - t;
+            while units > 0 {
+                let u = *NOTE_UNITS.iter().find(|&&u| u <= units).unwrap();
+                let is_first = t == begin;
+                units -= u;
+                t += u;
+                let measure = &mut measures[((t - u) / MEASURE_UNITS) as usize];
+                measure.push(ScoreNote {
+                    tone,
+                    units: u,
+                    tie_start: tone.is_some() && t < end,
+                    tie_stop: tone.is_some() && !is_first,
+                });
+            }
+        }
+    };
+
+    for &(tone, begin, end) in &spans {
+        // 丸めによる隙間は休符で埋める。
+        if begin > pos {
+            push_span(None, pos, begin);
+        }
+        push_span(tone, begin.max(pos), end);
+        pos = pos.max(end);
+    }
+    if pos < n_measure * MEASURE_UNITS {
+        push_span(None, pos, n_measure * MEASURE_UNITS);
+    }
+
+    measures
+}
+
+/// 64 分音符単位の長さを (音符の種類, 付点の有無) に変換する。
+fn units_to_type(units: u32) -> (u32, bool) {
+    let (base, dotted) = match units {
+        48 | 24 | 12 | 6 | 3 => (units / 3 * 2, true),
+        _ => (units, false),
+    };
+    // base は 64 分音符単位の 2 の冪。何分音符かに変換する。
+    (64 / base, dotted)
+}
+
+impl Music {
+    /// MusicXML (partwise) で出力する。
+    pub fn write_musicxml<W: Write>(&self, mut wtr: W) -> eyre::Result<()> {
+        let score = Score::new(self);
+
+        writeln!(
+            wtr,
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#
+        )?;
+        writeln!(
+            wtr,
+            r#"<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 3.1 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">"#
+        )?;
+        writeln!(wtr, r#"<score-partwise version="3.1">"#)?;
+        writeln!(wtr, "  <work>")?;
+        writeln!(
+            wtr,
+            "    <work-title>Star Soldier BGM {:02}</work-title>",
+            self.id
+        )?;
+        writeln!(wtr, "  </work>")?;
+
+        writeln!(wtr, "  <part-list>")?;
+        for (i, name) in PART_NAMES.iter().enumerate() {
+            writeln!(wtr, r#"    <score-part id="P{}">"#, i + 1)?;
+            writeln!(wtr, "      <part-name>{}</part-name>", name)?;
+            writeln!(wtr, "    </score-part>")?;
+        }
+        writeln!(wtr, "  </part-list>")?;
+
+        for part in 0..3 {
+            writeln!(wtr, r#"  <part id="P{}">"#, part + 1)?;
+            for (i, measure) in score.measures.iter().enumerate() {
+                writeln!(wtr, r#"    <measure number="{}">"#, i + 1)?;
+                if i == 0 {
+                    let (sign, line) = if part == 2 { ("F", 4) } else { ("G", 2) };
+                    writeln!(wtr, "      <attributes>")?;
+                    writeln!(wtr, "        <divisions>16</divisions>")?;
+                    writeln!(wtr, "        <key><fifths>0</fifths></key>")?;
+                    writeln!(
+                        wtr,
+                        "        <time><beats>4</beats><beat-type>4</beat-type></time>"
+                    )?;
+                    writeln!(
+                        wtr,
+                        "        <clef><sign>{}</sign><line>{}</line></clef>",
+                        sign, line
+                    )?;
+                    writeln!(wtr, "      </attributes>")?;
+                }
+                if score.repeats.iter().any(|r| r.begin == i) {
+                    writeln!(wtr, r#"      <barline location="left">"#)?;
+                    writeln!(wtr, "        <bar-style>heavy-light</bar-style>")?;
+                    writeln!(wtr, r#"        <repeat direction="forward"/>"#)?;
+                    writeln!(wtr, "      </barline>")?;
+                }
+
+                for note in &measure[part] {
+                    write_musicxml_note(&mut wtr, note)?;
+                }
+
+                if let Some(repeat) = score.repeats.iter().find(|r| r.end == i + 1) {
+                    writeln!(wtr, r#"      <barline location="right">"#)?;
+                    writeln!(wtr, "        <bar-style>light-heavy</bar-style>")?;
+                    writeln!(
+                        wtr,
+                        r#"        <repeat direction="backward" times="{}"/>"#,
+                        repeat.count
+                    )?;
+                    writeln!(wtr, "      </barline>")?;
+                }
+                writeln!(wtr, "    </measure>")?;
+            }
+            writeln!(wtr, "  </part>")?;
+        }
+
+        writeln!(wtr, "</score-partwise>")?;
+
+        Ok(())
+    }
+
+    /// LilyPond 形式で出力する。
+    pub fn write_lilypond<W: Write>(&self, mut wtr: W) -> eyre::Result<()> {
+        let score = Score::new(self);
+
+        writeln!(wtr, r#"\version "2.22.0""#)?;
+        writeln!(wtr)?;
+        writeln!(
+            wtr,
+            r#"\header {{ title = "Star Soldier BGM {:02}" }}"#,
+            self.id
+        )?;
+        writeln!(wtr)?;
+        writeln!(wtr, r"\score {{")?;
+        writeln!(wtr, "  <<")?;
+
+        for (part, name) in PART_NAMES.iter().enumerate() {
+            writeln!(
+                wtr,
+                r#"    \new Staff \with {{ instrumentName = "{}" }} {{"#,
+                name
+            )?;
+            let clef = if part == 2 { "bass" } else { "treble" };
+            writeln!(wtr, r"      \clef {} \time 4/4", clef)?;
+
+            for (i, measure) in score.measures.iter().enumerate() {
+                if let Some(repeat) = score.repeats.iter().find(|r| r.begin == i) {
+                    writeln!(wtr, r"      \repeat volta {} {{", repeat.count)?;
+                }
+
+                write!(wtr, "      ")?;
+                for note in &measure[part] {
+                    write!(wtr, "{} ", lilypond_note(note))?;
+                }
+                writeln!(wtr, "|")?;
+
+                if score.repeats.iter().any(|r| r.end == i + 1) {
+                    writeln!(wtr, "      }}")?;
+                }
+            }
+
+            writeln!(wtr, "    }}")?;
+        }
+
+        writeln!(wtr, "  >>")?;
+        writeln!(wtr, r"  \layout {{ }}")?;
+        writeln!(wtr, "}}")?;
+
+        Ok(())
+    }
+}
+
+fn write_musicxml_note<W: Write>(wtr: &mut W, note: &ScoreNote) -> eyre::Result<()> {
+    const STEPS: [(&str, i32); 12] = [
+        ("C", 0),
+        ("C", 1),
+        ("D", 0),
+        ("D", 1),
+        ("E", 0),
+        ("F", 0),
+        ("F", 1),
+        ("G", 0),
+        ("G", 1),
+        ("A", 0),
+        ("A", 1),
+        ("B", 0),
+    ];
+    const TYPE_NAMES: [(u32, &str); 7] = [
+        (1, "whole"),
+        (2, "half"),
+        (4, "quarter"),
+        (8, "eighth"),
+        (16, "16th"),
+        (32, "32nd"),
+        (64, "64th"),
+    ];
+
+    writeln!(wtr, "      <note>")?;
+    match note.tone {
+        Some((octave, n)) => {
+            let (step, alter) = STEPS[usize::from(n)];
+            write!(wtr, "        <pitch><step>{}</step>", step)?;
+            if alter != 0 {
+                write!(wtr, "<alter>{}</alter>", alter)?;
+            }
+            writeln!(wtr, "<octave>{}</octave></pitch>", octave)?;
+        }
+        None => writeln!(wtr, "        <rest/>")?,
+    }
+    writeln!(wtr, "        <duration>{}</duration>", note.units)?;
+    if note.tie_stop {
+        writeln!(wtr, r#"        <tie type="stop"/>"#)?;
+    }
+    if note.tie_start {
+        writeln!(wtr, r#"        <tie type="start"/>"#)?;
+    }
+    writeln!(wtr, "        <voice>1</voice>")?;
+
+    let (ty, dotted) = units_to_type(note.units);
+    let name = TYPE_NAMES.iter().find(|(t, _)| *t == ty).unwrap().1;
+    writeln!(wtr, "        <type>{}</type>", name)?;
+    if dotted {
+        writeln!(wtr, "        <dot/>")?;
+    }
+
+    if note.tie_start || note.tie_stop {
+        writeln!(wtr, "        <notations>")?;
+        if note.tie_stop {
+            writeln!(wtr, r#"          <tied type="stop"/>"#)?;
+        }
+        if note.tie_start {
+            writeln!(wtr, r#"          <tied type="start"/>"#)?;
+        }
+        writeln!(wtr, "        </notations>")?;
+    }
+    writeln!(wtr, "      </note>")?;
+
+    Ok(())
+}
+
+fn lilypond_note(note: &ScoreNote) -> String {
+    const NAMES: [&str; 12] = [
+        "c", "cis", "d", "dis", "e", "f", "fis", "g", "gis", "a", "ais", "b",
+    ];
+
+    let (ty, dotted) = units_to_type(note.units);
+    let duration = format!("{}{}", ty, if dotted { "." } else { "" });
+
+    match note.tone {
+        Some((octave, n)) => {
+            // LilyPond の絶対音高では c' が中央のド (C4)。
+            let shift = i32::from(octave) - 3;
+            let marks = if shift >= 0 {
+                "'".repeat(shift as usize)
+            } else {
+                ",".repeat((-shift) as usize)
+            };
+            let tie = if note.tie_start { "~" } else { "" };
+            format!("{}{}{}{}", NAMES[usize::from(n)], marks, duration, tie)
+        }
+        None => format!("r{}", duration),
+    }
+}
@@ -0,0 +1,105 @@
+// 地形に隠されたアイテムの解釈と一覧。
+//
+// 隠しセルの値 (3bit) はそのまま出現するセルの visual_id になっている。
+// 0..=3 はパワーアップの 4 種で、値 0 のときは列番号で種類が決まる。
+// 4..=7 はパワーアップ以外のボーナス。効果は取得処理から確かめていないので、値だけを持つ。
+// ゼグは隠しセル表ではなく地形セル自体で表される。
+
+use crate::game::*;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SecretItem {
+    /// variant は 0..=3。by_column なら隠しセルの値は 0 で、variant は列番号から決まる。
+    PowerUp { variant: u8, by_column: bool },
+    /// variant は 4..=7 (隠しセルの値)。
+    Bonus { variant: u8 },
+    /// 地形セル CELL_ZEG_INI+1..=CELL_ZEG_INI+5 に埋まっているゼグ。
+    Zeg { cell: u8 },
+}
+
+impl SecretItem {
+    /// 隠しセル表の値 (3bit) と列番号から解釈する。
+    pub fn from_secret_cell(cell: u8, c: u8) -> Self {
+        match cell {
+            0 => Self::PowerUp {
+                variant: c % 4,
+                by_column: true,
+            },
+            1..=3 => Self::PowerUp {
+                variant: cell,
+                by_column: false,
+            },
+            4..=7 => Self::Bonus { variant: cell },
+            _ => panic!("secret cell value must be 3 bits: {}", cell),
+        }
+    }
+
+    /// 出現するセルの visual_id。
+    pub fn visual_id(self) -> u8 {
+        match self {
+            Self::PowerUp { variant, .. } => variant,
+            Self::Bonus { variant } => variant,
+            Self::Zeg { .. } => CELL_ZEG_INI,
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Self::PowerUp {
+                variant,
+                by_column: false,
+            } => format!("Power-up {}", variant),
+            Self::PowerUp {
+                variant,
+                by_column: true,
+            } => format!("Power-up {} (by column)", variant),
+            Self::Bonus { variant } => format!("Bonus {}", variant),
+            Self::Zeg { cell } => format!("Zeg (cell {:02X})", cell),
+        }
+    }
+}
+
+impl Ground {
+    /// (r, c) に隠されたアイテムを返す。
+    pub fn secret_item(&self, r: u8, c: u8) -> Option<SecretItem> {
+        const HIDDEN_ZEG_MIN: u8 = CELL_ZEG_INI + 1;
+        const HIDDEN_ZEG_MAX: u8 = CELL_ZEG_INI + 5;
+
+        if let Some(secret) = self
+            .secrets()
+            .iter()
+            .find(|secret| secret.r() == r && secret.c() == c)
+        {
+            return Some(SecretItem::from_secret_cell(secret.cell(), c));
+        }
+
+        match self.cell(r, c) {
+            cell @ HIDDEN_ZEG_MIN..=HIDDEN_ZEG_MAX => Some(SecretItem::Zeg { cell }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SecretEntry {
+    pub stage: u8,
+    pub r: u8,
+    pub c: u8,
+    pub item: SecretItem,
+}
+
+/// 全 16 ステージの隠しアイテムを (ステージ, 行, 列) の順に返す。
+pub fn secret_catalogue(game: &Game) -> Vec<SecretEntry> {
+    let mut res = Vec::new();
+
+    for stage in 1..=16 {
+        let ground = game.ground(stage);
+        for (r, c) in itertools::iproduct!(0..=255, 0..20) {
+            if let Some(item) = ground.secret_item(r, c) {
+                res.push(SecretEntry { stage, r, c, item });
+            }
+        }
+    }
+
+    res
+}
@@ -0,0 +1,366 @@
+// サウンドドライバのエントリポイントと周期テーブルを PRG から探す。
+//
+// - 曲初期化ルーチン: MusicPointer テーブルを参照しているサブルーチン。
+// - 毎フレーム処理ルーチン: NMI ハンドラから呼ばれるサブルーチンのうち、
+//   曲初期化の後に呼ぶと矩形波の周期レジスタに書き込むもの。
+// - 周期テーブル: 半音ずつ周期が短くなる 16 bit 値の列。
+//   下位/上位バイトが交互に並ぶ形式と、下位バイト列/上位バイト列が分かれた形式の両方を探す。
+
+use std::collections::BTreeSet;
+
+use eyre::{bail, ensure, eyre};
+
+use crate::cpu::*;
+use crate::disasm::*;
+use crate::known_table::*;
+use crate::music::*;
+use crate::opcode::*;
+use crate::rom::*;
+
+const MAX_STEPS: usize = 1_000_000;
+
+/// 周期テーブルとみなす最小の長さ (2 オクターブ)。
+const PERIOD_TABLE_LEN_MIN: usize = 24;
+
+/// 隣り合う周期の比の、半音 (2^(1/12)) からの許容誤差。
+const SEMITONE_RATIO_TOLERANCE: f64 = 0.02;
+
+/// サウンドドライバ。
+/// A レジスタに (曲 ID - 1 + id_base) を入れて init を呼び、以後 1 フレームごとに play を呼ぶ。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct MusicDriver {
+    pub init: u16,
+    pub play: u16,
+    pub id_base: u8,
+}
+
+/// ドライバが音符を周期レジスタ値に変換するテーブル。
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PeriodTable {
+    /// 下位バイトの先頭アドレス。
+    pub addr_lo: u16,
+    /// 上位バイトの先頭アドレス。
+    pub addr_hi: u16,
+    /// 隣り合うエントリのアドレスの差 (交互形式なら 2、分割形式なら 1)。
+    pub stride: u16,
+    /// エントリ 0 に対応する音符値 (トラックデータの音符バイト - 1)。
+    pub value_first: i32,
+    pub periods: Vec<u16>,
+}
+
+impl PeriodTable {
+    /// トラックデータの音符値 (音符バイト - 1) の周期レジスタ値を返す。
+    pub fn period(&self, value: u8) -> Option<u16> {
+        let i = i32::from(value) - self.value_first;
+        if i < 0 {
+            return None;
+        }
+        self.periods.get(i as usize).copied()
+    }
+
+    /// 音符 (octave, note) の周期レジスタ値を返す。
+    /// 三角波は load_musics() で 1 オクターブ下げてあるので、その分を戻してから引く。
+    pub fn tone_period(&self, octave: u8, note: u8, tri: bool) -> Option<u16> {
+        let octave = if tri { octave } else { octave.checked_sub(1)? };
+        self.period(12 * octave + note)
+    }
+}
+
+/// サウンドドライバのエントリポイントを探す。
+pub fn locate_music_driver(rom: &Rom) -> eyre::Result<MusicDriver> {
+    let tables = load_known_tables(rom);
+    let disasm = Disassembly::new(rom, &tables, &[]);
+
+    let table_ptr = tables
+        .iter()
+        .find(|table| table.name == "MusicPointer")
+        .expect("MusicPointer table must be known");
+    let inits: BTreeSet<u16> = disasm
+        .xrefs(table_ptr)
+        .flat_map(|pc| disasm.routines_containing(pc))
+        .collect();
+    ensure!(
+        inits.len() == 1,
+        "music init routine not determined: candidates {:04X?}",
+        inits
+    );
+    let init = *inits.iter().next().unwrap();
+
+    let nmi_callees = nmi_callees(&disasm);
+
+    // 曲 1 の sq1 の最初の発音と一致するものを探す。id_base は 0 と 1 を試す。
+    let musics = load_musics(rom);
+    let periods = locate_period_table(rom).ok();
+    let expect = first_sq1_onset(&musics[0], periods.as_ref());
+
+    let mut found = Vec::new();
+    for &play in &nmi_callees {
+        if play == init {
+            continue;
+        }
+        for id_base in 0..=1 {
+            let driver = MusicDriver {
+                init,
+                play,
+                id_base,
+            };
+            let onset = match first_sq1_period(rom, driver, 1, 60) {
+                Ok(Some(onset)) => onset,
+                _ => continue,
+            };
+            if let Some(expect) = expect {
+                if expect != onset {
+                    continue;
+                }
+            }
+            found.push(driver);
+        }
+    }
+
+    match found.as_slice() {
+        [driver] => Ok(*driver),
+        [] => bail!(
+            "music play routine not found (NMI callees: {:04X?})",
+            nmi_callees
+        ),
+        _ => bail!("music driver is ambiguous: {:04X?}", found),
+    }
+}
+
+/// 曲 1 の sq1 の最初の発音の周期。周期テーブルがなければ分からないので None。
+fn first_sq1_onset(music: &Music, periods: Option<&PeriodTable>) -> Option<u16> {
+    let (octave, note) = track_events(&music.track_sq1)
+        .into_iter()
+        .find_map(|ev| ev.tone)?;
+    periods?.tone_period(octave, note, false)
+}
+
+/// NMI ハンドラから JSR で呼ばれるサブルーチンを返す。
+pub(crate) fn nmi_callees(disasm: &Disassembly) -> Vec<u16> {
+    let mut callees = Vec::new();
+    for pc in disasm.routine_body(disasm.vector(0)) {
+        if let Some((opcode, operand)) = disasm.instruction(pc) {
+            if opcode.mnemonic == "JSR" {
+                let dst = u16::from_le_bytes([operand[0], operand[1]]);
+                if !callees.contains(&dst) {
+                    callees.push(dst);
+                }
+            }
+        }
+    }
+    callees
+}
+
+/// callee を呼ぶ JSR のうち、直前の命令が `LDA #imm` であるものの (JSR のアドレス, 即値) を返す。
+pub(crate) fn immediate_args(disasm: &Disassembly, callee: u16) -> Vec<(u16, u8)> {
+    disasm
+        .calls()
+        .filter(|&(_, dst)| dst == callee)
+        .filter_map(|(pc, _)| match disasm.instruction(pc.wrapping_sub(2)) {
+            Some((opcode, operand))
+                if opcode.mnemonic == "LDA" && opcode.mode == AddrMode::Immediate =>
+            {
+                Some((pc, operand[0]))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// driver で曲 music_id を再生し、max_frames 以内で最初に sq1 をキーオンしたときの周期を返す。
+fn first_sq1_period(
+    rom: &Rom,
+    driver: MusicDriver,
+    music_id: u8,
+    max_frames: u32,
+) -> eyre::Result<Option<u16>> {
+    let mut bus = StubBus::new(&rom.prg);
+    let mut cpu = Cpu::new();
+
+    cpu.a = music_id - 1 + driver.id_base;
+    cpu.call(&mut bus, driver.init, MAX_STEPS)?;
+
+    let mut period = 0u16;
+    for _ in 0..max_frames {
+        bus.apu_writes.clear();
+        cpu.call(&mut bus, driver.play, MAX_STEPS)?;
+        let mut keyed = false;
+        for &(addr, value) in &bus.apu_writes {
+            match addr {
+                0x4002 => period = (period & 0x700) | u16::from(value),
+                0x4003 => {
+                    period = (period & 0xFF) | (u16::from(value & 7) << 8);
+                    keyed = true;
+                }
+                _ => {}
+            }
+        }
+        if keyed {
+            return Ok(Some(period));
+        }
+    }
+
+    Ok(None)
+}
+
+/// 周期テーブルを探す。候補のうち最も長いものを返す。
+pub fn locate_period_table(rom: &Rom) -> eyre::Result<PeriodTable> {
+    let prg = &rom.prg[..prg_offset(0xFFFA)];
+    let word = |lo: usize, hi: usize| u16::from(prg[lo]) | (u16::from(prg[hi]) << 8);
+
+    let mut best: Option<(usize, usize, usize, usize)> = None; // (lo, hi, stride, len)
+    let mut consider = |lo: usize, hi: usize, stride: usize, len: usize| {
+        if len < PERIOD_TABLE_LEN_MIN {
+            return;
+        }
+        match best {
+            Some((_, _, _, best_len)) if best_len >= len => {}
+            _ => best = Some((lo, hi, stride, len)),
+        }
+    };
+
+    // 交互形式
+    for start in 0..prg.len() - 1 {
+        if start >= 2 && is_semitone_step(word(start - 2, start - 1), word(start, start + 1)) {
+            continue;
+        }
+        let len = semitone_run_len((prg.len() - start) / 2, start, start + 1, 2, word);
+        consider(start, start + 1, 2, len);
+    }
+
+    // 分割形式: 上位バイトは 7 以下で単調非増加、かつ先頭は 0 でない。
+    for hi in 0..prg.len() {
+        if prg[hi] == 0 || prg[hi] > 7 || (hi > 0 && prg[hi - 1] <= 7 && prg[hi - 1] >= prg[hi]) {
+            continue;
+        }
+        let hi_len = (hi..prg.len())
+            .take_while(|&i| prg[i] <= 7 && (i == hi || prg[i] <= prg[i - 1]))
+            .count();
+        if hi_len < PERIOD_TABLE_LEN_MIN {
+            continue;
+        }
+        for lo in 0..prg.len() - hi_len {
+            if lo == hi || lo + 1 == hi {
+                continue;
+            }
+            let len = semitone_run_len(hi_len.min(prg.len() - lo), lo, hi, 1, word);
+            consider(lo, hi, 1, len);
+        }
+    }
+
+    let (lo, hi, stride, len) = best.ok_or_else(|| eyre!("period table not found"))?;
+    let periods: Vec<u16> = (0..len)
+        .map(|i| word(lo + stride * i, hi + stride * i))
+        .collect();
+
+    // エントリ 0 の音高を平均律から求める (音符値 v は矩形波の O(v/12+1) に対応する)。
+    let semitone = tone_semitone(periods[0]);
+    Ok(PeriodTable {
+        addr_lo: 0x8000 + lo as u16,
+        addr_hi: 0x8000 + hi as u16,
+        stride: stride as u16,
+        value_first: semitone - 12,
+        periods,
+    })
+}
+
+/// (lo, hi) から stride おきに読んだ 16 bit 値が半音ずつ下がっていく長さ (max_len まで)。
+fn semitone_run_len(
+    max_len: usize,
+    lo: usize,
+    hi: usize,
+    stride: usize,
+    word: impl Fn(usize, usize) -> u16,
+) -> usize {
+    let mut len = 1;
+    while len < max_len
+        && is_semitone_step(
+            word(lo + stride * (len - 1), hi + stride * (len - 1)),
+            word(lo + stride * len, hi + stride * len),
+        )
+    {
+        len += 1;
+    }
+    len
+}
+
+/// 周期 prev の次の半音の周期が cur であるか。
+/// 周期が短いと丸め誤差の比率が大きくなるので、1 までの差は常に許容する。
+fn is_semitone_step(prev: u16, cur: u16) -> bool {
+    if cur < 8 || prev > 0x7FF || cur >= prev {
+        return false;
+    }
+    let expect = f64::from(prev + 1) / 2f64.powf(1.0 / 12.0) - 1.0;
+    (f64::from(cur) - expect).abs() <= (SEMITONE_RATIO_TOLERANCE * expect).max(1.0)
+}
+
+/// 矩形波の周期 period に最も近い音の、O0C からの半音数。
+fn tone_semitone(period: u16) -> i32 {
+    let freq = 1_789_773.0 / (16.0 * f64::from(period + 1));
+    (12.0 * (freq / tone_frequency(0, 0)).log2()).round() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// O2C から始まる平均律の周期 (矩形波、NTSC) を n 個返す。
+    fn equal_temperament_periods(n: u8) -> Vec<u16> {
+        (0..n)
+            .map(|i| {
+                let period = 1_789_773.0 / (16.0 * tone_frequency(2 + i / 12, i % 12)) - 1.0;
+                period.round().clamp(0.0, 0x7FF as f64) as u16
+            })
+            .collect()
+    }
+
+    fn empty_rom() -> Rom {
+        Rom {
+            prg: [0; 0x8000],
+            chr: [0; 0x8000],
+        }
+    }
+
+    #[test]
+    fn locate_interleaved_period_table() {
+        let periods = equal_temperament_periods(60);
+        let mut rom = empty_rom();
+        for (i, period) in itertools::enumerate(&periods) {
+            rom.prg[0x1234 + 2 * i..][..2].copy_from_slice(&period.to_le_bytes());
+        }
+
+        let table = locate_period_table(&rom).unwrap();
+        assert_eq!(table.addr_lo, 0x9234);
+        assert_eq!(table.addr_hi, 0x9235);
+        assert_eq!(table.stride, 2);
+        assert_eq!(table.periods, periods);
+        // エントリ 0 は矩形波の O2C で、音符値 12 に対応する
+        assert_eq!(table.value_first, 12);
+        assert_eq!(table.tone_period(2, 0, false), Some(periods[0]));
+        assert_eq!(table.tone_period(3, 9, false), Some(periods[21]));
+        assert_eq!(table.tone_period(2, 9, true), Some(periods[21]));
+        assert_eq!(table.tone_period(1, 11, false), None);
+    }
+
+    #[test]
+    fn locate_split_period_table() {
+        let periods = equal_temperament_periods(48);
+        let mut rom = empty_rom();
+        for (i, period) in itertools::enumerate(&periods) {
+            let [lo, hi] = period.to_le_bytes();
+            rom.prg[0x3000 + i] = lo;
+            rom.prg[0x3100 + i] = hi;
+        }
+
+        let table = locate_period_table(&rom).unwrap();
+        assert_eq!(table.addr_lo, 0xB000);
+        assert_eq!(table.addr_hi, 0xB100);
+        assert_eq!(table.stride, 1);
+        assert_eq!(table.periods, periods);
+    }
+
+    #[test]
+    fn no_period_table() {
+        assert!(locate_period_table(&empty_rom()).is_err());
+    }
+}
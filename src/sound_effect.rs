@@ -0,0 +1,662 @@
+// 効果音の抽出。
+//
+// 効果音ドライバは逆アセンブル結果から探す。
+// `LDA #imm` の直後に JSR で呼ばれるサブルーチンのうち、曲初期化ルーチン以外で、
+// 呼んだ後に毎フレーム処理ルーチン (NMI ハンドラから呼ばれるもの) を回すと APU に書き込むものを
+// 効果音を鳴らすルーチンとする。その即値が効果音 ID で、呼び出し元のアドレスも記録する。
+// 効果音データのテーブルは、そのルーチンが PRG をインデックス付きで読んでいるアドレスとする。
+//
+// 各効果音はドライバを実行し、play が読んだ効果音データの各バイトを、
+// その値が書き込まれた APU レジスタなどから SoundEffectCommand にデコードする。
+// ドライバのレジスタ書き込みは WAV の出力にだけ使う。
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use eyre::bail;
+use itertools::Itertools as _;
+use serde::Serialize;
+
+use crate::apu::*;
+use crate::cpu::*;
+use crate::disasm::*;
+use crate::known_table::*;
+use crate::music::*;
+use crate::opcode::*;
+use crate::rom::*;
+use crate::sound_driver::*;
+
+const MAX_STEPS: usize = 1_000_000;
+
+/// 効果音が鳴り止んだとみなす無音フレーム数。
+const SILENCE_FRAMES: u32 = 8;
+
+/// 効果音が鳴ったかを調べるときに play を呼ぶフレーム数。
+const PROBE_FRAMES: u32 = 16;
+
+/// 効果音ドライバ。
+/// A レジスタに効果音 ID を入れて trigger を呼び、以後 1 フレームごとに play を呼ぶ。
+/// table は trigger が参照している効果音データのテーブル (見つからなければ None)。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SoundEffectDriver {
+    pub trigger: u16,
+    pub play: u16,
+    pub table: Option<u16>,
+}
+
+/// ゲーム中で使われている効果音 ID と、それを鳴らしている JSR 命令のアドレス。
+pub type SoundEffectCallers = BTreeMap<u8, Vec<u16>>;
+
+/// 効果音ドライバと、ゲーム中で使われている効果音 ID を探す。
+pub fn locate_sound_effect_driver(
+    rom: &Rom,
+) -> eyre::Result<(SoundEffectDriver, SoundEffectCallers)> {
+    let disasm = Disassembly::new(rom, &load_known_tables(rom), &[]);
+    let music_init = locate_music_driver(rom)?.init;
+    let plays = nmi_callees(&disasm);
+
+    let callees: BTreeSet<u16> = disasm.calls().map(|(_, dst)| dst).collect();
+
+    let mut found = Vec::new();
+    for trigger in callees {
+        if trigger == music_init || plays.contains(&trigger) {
+            continue;
+        }
+        let args = immediate_args(&disasm, trigger);
+        if args.is_empty() {
+            continue;
+        }
+
+        let mut callers = SoundEffectCallers::new();
+        for &(pc, id) in &args {
+            callers.entry(id).or_default().push(pc);
+        }
+
+        for &play in &plays {
+            let driver = SoundEffectDriver {
+                trigger,
+                play,
+                table: sound_effect_table(&disasm, trigger),
+            };
+            let audible = callers
+                .keys()
+                .any(|&id| probe_sound_effect(rom, driver, id).unwrap_or(false));
+            if audible {
+                found.push((driver, callers.clone()));
+                break;
+            }
+        }
+    }
+
+    match found.len() {
+        1 => Ok(found.pop().unwrap()),
+        0 => bail!("sound effect driver not found"),
+        _ => bail!(
+            "sound effect driver is ambiguous: {:04X?}",
+            found.iter().map(|(driver, _)| driver).collect::<Vec<_>>()
+        ),
+    }
+}
+
+/// trigger の本体で最初に PRG をインデックス付きで読んでいるアドレス。
+fn sound_effect_table(disasm: &Disassembly, trigger: u16) -> Option<u16> {
+    disasm.routine_body(trigger).into_iter().find_map(|pc| {
+        let (opcode, operand) = disasm.instruction(pc)?;
+        if opcode.mnemonic != "LDA"
+            || !matches!(opcode.mode, AddrMode::AbsoluteX | AddrMode::AbsoluteY)
+        {
+            return None;
+        }
+        let addr = u16::from_le_bytes([operand[0], operand[1]]);
+        (addr >= 0x8000).then_some(addr)
+    })
+}
+
+/// 効果音 id を鳴らして PROBE_FRAMES 以内に APU への書き込みがあるか。
+fn probe_sound_effect(rom: &Rom, driver: SoundEffectDriver, id: u8) -> eyre::Result<bool> {
+    let mut bus = StubBus::new(&rom.prg);
+    let mut cpu = Cpu::new();
+
+    cpu.a = id;
+    cpu.call(&mut bus, driver.trigger, MAX_STEPS)?;
+    for _ in 0..PROBE_FRAMES {
+        cpu.call(&mut bus, driver.play, MAX_STEPS)?;
+        if bus.apu_writes.iter().any(|&(addr, _)| addr != 0x4015) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// 効果音データ 1 バイトの役割。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum SoundEffectCommand {
+    /// デューティ、エンベロープ、音量 ($4000, $4004, $400C)。ノイズでは duty は None。
+    SetVolume {
+        channel: ApuChannel,
+        duty: Option<SquareDuty>,
+        constant: bool,
+        volume: u8,
+    },
+    /// 三角波の線形カウンタ ($4008)。
+    SetLinearCounter { value: u8 },
+    /// スイープ ($4001, $4005)。
+    SetSweep { channel: ApuChannel, value: u8 },
+    /// タイマー周期の下位 8 bit ($4002, $4006, $400A)。
+    SetPeriodLow { channel: ApuChannel, value: u8 },
+    /// タイマー周期の上位 3 bit と長さカウンタ ($4003, $4007, $400B)。発音し直す。
+    SetPeriodHigh {
+        channel: ApuChannel,
+        period_high: u8,
+        length_index: u8,
+    },
+    /// ノイズのモードと周期インデックス ($400E)。
+    SetNoisePeriod { short: bool, index: u8 },
+    /// ノイズの長さカウンタ ($400F)。発音し直す。
+    KeyOnNoise { length_index: u8 },
+    /// チャンネルの有効/無効 ($4015)。
+    SetEnabled { mask: u8 },
+    /// 次のデータを読むまでのフレーム数。
+    Wait { frames: u32 },
+    /// 効果音の終わり。
+    End,
+    /// 役割が分からないバイト。
+    Unknown,
+}
+
+impl SoundEffectCommand {
+    /// データの値がそのまま APU レジスタ addr に書き込まれた場合のコマンド。
+    fn from_register_write(addr: u16, value: u8) -> Option<Self> {
+        let cmd = match addr {
+            0x4015 => Self::SetEnabled { mask: value },
+            0x4008 => Self::SetLinearCounter { value },
+            0x400E => Self::SetNoisePeriod {
+                short: (value & 0x80) != 0,
+                index: value & 0x0F,
+            },
+            0x400F => Self::KeyOnNoise {
+                length_index: value >> 3,
+            },
+            0x4000..=0x400C => {
+                let channel = ApuChannel::ALL[usize::from((addr - 0x4000) / 4)];
+                match addr & 3 {
+                    0 => Self::SetVolume {
+                        channel,
+                        duty: (channel != ApuChannel::Noise).then(|| SquareDuty::new(value >> 6)),
+                        constant: (value & 0x10) != 0,
+                        volume: value & 0x0F,
+                    },
+                    1 => Self::SetSweep { channel, value },
+                    2 => Self::SetPeriodLow { channel, value },
+                    _ => Self::SetPeriodHigh {
+                        channel,
+                        period_high: value & 7,
+                        length_index: value >> 3,
+                    },
+                }
+            }
+            _ => return None,
+        };
+        Some(cmd)
+    }
+
+    /// レジスタに書き込むコマンドなら、その APU レジスタのアドレス。
+    pub fn register(&self) -> Option<u16> {
+        match *self {
+            Self::SetVolume { channel, .. } => Some(channel.reg_base()),
+            Self::SetLinearCounter { .. } => Some(0x4008),
+            Self::SetSweep { channel, .. } => Some(channel.reg_base() + 1),
+            Self::SetPeriodLow { channel, .. } => Some(channel.reg_base() + 2),
+            Self::SetPeriodHigh { channel, .. } => Some(channel.reg_base() + 3),
+            Self::SetNoisePeriod { .. } => Some(0x400E),
+            Self::KeyOnNoise { .. } => Some(0x400F),
+            Self::SetEnabled { .. } => Some(0x4015),
+            Self::Wait { .. } | Self::End | Self::Unknown => None,
+        }
+    }
+
+    /// 音を出すチャンネル。
+    fn channel(&self) -> Option<ApuChannel> {
+        match *self {
+            Self::SetVolume { channel, .. }
+            | Self::SetSweep { channel, .. }
+            | Self::SetPeriodLow { channel, .. }
+            | Self::SetPeriodHigh { channel, .. } => Some(channel),
+            Self::SetLinearCounter { .. } => Some(ApuChannel::Triangle),
+            Self::SetNoisePeriod { .. } | Self::KeyOnNoise { .. } => Some(ApuChannel::Noise),
+            _ => None,
+        }
+    }
+}
+
+/// 効果音データの 1 バイト。frame はドライバがこのバイトを最初に読んだフレーム。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
+pub struct SoundEffectStep {
+    pub addr: u16,
+    pub value: u8,
+    pub frame: u32,
+    pub command: SoundEffectCommand,
+}
+
+/// 効果音。steps はドライバが読んだ効果音データをデコードしたもの。
+/// writes はドライバを実行して得たレジスタ書き込みで、WAV の出力にだけ使う。
+/// callers はこの効果音を鳴らしている JSR 命令のアドレス。
+#[derive(Clone, Debug, Serialize)]
+pub struct SoundEffect {
+    pub id: u8,
+    pub name: Option<String>,
+    pub callers: Vec<u16>,
+    pub frame_count: u32,
+    pub channels: Vec<ApuChannel>,
+    pub steps: Vec<SoundEffectStep>,
+    #[serde(skip)]
+    pub writes: Vec<ApuWrite>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BusEvent {
+    /// PRG のうちコードでない部分からの読み込み。
+    Read { addr: u16, value: u8 },
+    /// APU への書き込み。
+    Write { addr: u16, value: u8 },
+}
+
+/// PRG のデータ読み込みと APU への書き込みを順に記録するバス。
+struct TraceBus<'a> {
+    inner: StubBus,
+    disasm: &'a Disassembly,
+    events: Vec<BusEvent>,
+}
+
+impl Bus for TraceBus<'_> {
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.inner.read(addr);
+        if addr >= 0x8000 && !self.disasm.is_code(addr) {
+            self.events.push(BusEvent::Read { addr, value });
+        }
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.inner.write(addr, value);
+        if matches!(addr, 0x4000..=0x4013 | 0x4015) {
+            self.events.push(BusEvent::Write { addr, value });
+        }
+    }
+}
+
+/// 効果音 id をドライバで再生し、鳴り止むまで (最大 max_frames) を記録する。
+///
+/// trigger はテーブルからデータのアドレスを引くだけで、データ本体は play が読むものとする。
+/// play が読んだデータをデコードし、レジスタ書き込みも記録する。
+pub fn record_sound_effect(
+    rom: &Rom,
+    disasm: &Disassembly,
+    driver: SoundEffectDriver,
+    id: u8,
+    max_frames: u32,
+) -> eyre::Result<SoundEffect> {
+    let mut bus = TraceBus {
+        inner: StubBus::new(&rom.prg),
+        disasm,
+        events: Vec::new(),
+    };
+    let mut cpu = Cpu::new();
+
+    let mut writes = Vec::new();
+    let mut regs = ApuRegisters::default();
+
+    cpu.a = id;
+    cpu.call(&mut bus, driver.trigger, MAX_STEPS)?;
+    bus.events.clear();
+    for (addr, value) in bus.inner.apu_writes.drain(..) {
+        regs.write(addr, value);
+        writes.push(ApuWrite {
+            frame: 0,
+            addr,
+            value,
+        });
+    }
+
+    let mut events = Vec::new();
+    let mut silent = 0;
+    let mut frame_count = 0;
+    for frame in 0..max_frames {
+        cpu.call(&mut bus, driver.play, MAX_STEPS)?;
+        events.extend(bus.events.drain(..).map(|ev| (frame, ev)));
+        for (addr, value) in bus.inner.apu_writes.drain(..) {
+            regs.write(addr, value);
+            writes.push(ApuWrite { frame, addr, value });
+        }
+        frame_count = frame + 1;
+
+        let audible = ApuChannel::ALL.iter().any(|&ch| {
+            let state = regs.channel_state(ch);
+            state.enabled && !(state.constant_volume && state.volume == 0)
+        });
+        silent = if audible { 0 } else { silent + 1 };
+        if silent >= SILENCE_FRAMES {
+            frame_count -= SILENCE_FRAMES;
+            break;
+        }
+    }
+    writes.retain(|w| w.frame < frame_count.max(1));
+
+    let steps = decode_steps(&events);
+    let mut channels = Vec::new();
+    for ch in steps.iter().filter_map(|step| step.command.channel()) {
+        if !channels.contains(&ch) {
+            channels.push(ch);
+        }
+    }
+
+    Ok(SoundEffect {
+        id,
+        name: None,
+        callers: Vec::new(),
+        frame_count,
+        channels,
+        steps,
+        writes,
+    })
+}
+
+/// play が読んだデータの各バイトに役割を付ける。同じアドレスを何度読んでも 1 バイトとして扱う。
+///
+/// - 読んでから次のデータを読むまでに、その値がそのまま APU に書き込まれたらレジスタ設定。
+/// - そうでないバイトのうち、最後に読んだものは終端。
+/// - フレームの最後に読んだバイトで、次のデータを読むのが後のフレームなら待ち。
+/// - それ以外は不明。
+fn decode_steps(events: &[(u32, BusEvent)]) -> Vec<SoundEffectStep> {
+    let mut steps: Vec<SoundEffectStep> = Vec::new();
+    for (i, &(frame, ev)) in events.iter().enumerate() {
+        let (addr, value) = match ev {
+            BusEvent::Read { addr, value } => (addr, value),
+            BusEvent::Write { .. } => continue,
+        };
+        if steps.iter().any(|step| step.addr == addr) {
+            continue;
+        }
+
+        let command = events[i + 1..]
+            .iter()
+            .take_while(|&&(f, ev)| f == frame && !matches!(ev, BusEvent::Read { .. }))
+            .find_map(|&(_, ev)| match ev {
+                BusEvent::Write { addr, value: v } if v == value => {
+                    SoundEffectCommand::from_register_write(addr, v)
+                }
+                _ => None,
+            })
+            .unwrap_or(SoundEffectCommand::Unknown);
+        steps.push(SoundEffectStep {
+            addr,
+            value,
+            frame,
+            command,
+        });
+    }
+
+    for i in 0..steps.len() {
+        if steps[i].command != SoundEffectCommand::Unknown {
+            continue;
+        }
+        steps[i].command = match steps.get(i + 1) {
+            None => SoundEffectCommand::End,
+            Some(next) if next.frame > steps[i].frame => SoundEffectCommand::Wait {
+                frames: next.frame - steps[i].frame,
+            },
+            Some(_) => SoundEffectCommand::Unknown,
+        };
+    }
+
+    steps
+}
+
+impl SoundEffect {
+    /// デコードしたデータのうち、レジスタ設定をレジスタ書き込みとして返す。
+    pub fn step_writes(&self) -> Vec<ApuWrite> {
+        self.steps
+            .iter()
+            .filter_map(|step| {
+                let addr = step.command.register()?;
+                Some(ApuWrite {
+                    frame: step.frame,
+                    addr,
+                    value: step.value,
+                })
+            })
+            .collect()
+    }
+
+    pub fn write_json<W: Write>(&self, wtr: W) -> eyre::Result<()> {
+        serde_json::to_writer_pretty(wtr, self)?;
+        Ok(())
+    }
+
+    pub fn write_wav<W: Write>(&self, wtr: W) -> eyre::Result<()> {
+        write_apu_wav(wtr, &self.writes, self.frame_count, 44100)
+    }
+
+    /// FlMML 形式で出力する。Music::write_mml() と同じく 1F = 192 分音符 (T75) とする。
+    ///
+    /// デコードしたデータのレジスタ設定を順に適用し、1 フレームごとの音量/音程をそのまま並べる。
+    /// データに $4015 がなければ全チャンネルが有効とみなす。エンベロープは固定音量 15 として扱う。
+    /// ノイズは FlMML の @7 を使い、周期インデックスをそのままノート番号 (O0C 起点) にする。
+    pub fn write_mml<W: Write>(&self, mut wtr: W) -> eyre::Result<()> {
+        writeln!(wtr, "T75")?;
+
+        let writes = self.step_writes();
+        for &ch in &self.channels {
+            let mut regs = ApuRegisters::default();
+            regs.write(0x4015, 0x0F);
+            let mut it = writes.iter().peekable();
+
+            let mut frames = Vec::new();
+            for frame in 0..self.frame_count {
+                while let Some(w) = it.peek().filter(|w| w.frame == frame) {
+                    regs.write(w.addr, w.value);
+                    it.next();
+                }
+                frames.push(regs.channel_state(ch));
+            }
+
+            match ch {
+                ApuChannel::Square1 | ApuChannel::Square2 => write!(wtr, "@5 ")?,
+                ApuChannel::Triangle => write!(wtr, "@6-1 ")?,
+                ApuChannel::Noise => write!(wtr, "@7 ")?,
+            }
+
+            for (len, state) in frames.into_iter().dedup_with_count() {
+                let tick = 2 * len;
+                let volume = if state.constant_volume {
+                    state.volume
+                } else {
+                    15
+                };
+                if !state.enabled || volume == 0 {
+                    write!(wtr, "R%{} ", tick)?;
+                    continue;
+                }
+                let (octave, note) = match ch {
+                    ApuChannel::Noise => {
+                        let n = 15 - state.period as u8;
+                        (n / 12, n % 12)
+                    }
+                    _ => period_to_tone(state.period, ch == ApuChannel::Triangle),
+                };
+                if matches!(ch, ApuChannel::Square1 | ApuChannel::Square2) {
+                    write!(wtr, "@W{} ", [1, 2, 4, 6][usize::from(state.duty)])?;
+                }
+                write!(
+                    wtr,
+                    "V{} O{}{}%{} ",
+                    volume,
+                    octave,
+                    note_to_mml(note),
+                    tick
+                )?;
+            }
+
+            writeln!(wtr, ";")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom_with_code(fill: u8, code: &[(u16, &[u8])]) -> Rom {
+        let mut prg = [fill; 0x8000];
+        for &(addr, bytes) in code {
+            prg[prg_offset(addr)..][..bytes.len()].copy_from_slice(bytes);
+        }
+        Rom {
+            prg,
+            chr: [0; 0x8000],
+        }
+    }
+
+    #[test]
+    fn trigger_writes_count_as_audible() {
+        // RTS で埋め、trigger だけ置く。
+        // trigger: LDA #$01; STA $4015; LDA #$3F; STA $4000; RTS
+        let rom = rom_with_code(
+            0x60,
+            &[(
+                0x9000,
+                &[
+                    0xA9, 0x01, 0x8D, 0x15, 0x40, 0xA9, 0x3F, 0x8D, 0x00, 0x40, 0x60,
+                ],
+            )],
+        );
+        let disasm = Disassembly::new(&rom, &[], &[0x9000, 0x9100]);
+        let driver = SoundEffectDriver {
+            trigger: 0x9000,
+            play: 0x9100,
+            table: None,
+        };
+
+        // play は何も書き込まないが、trigger で鳴らし始めた音は鳴り続ける。
+        let sfx = record_sound_effect(&rom, &disasm, driver, 0, 20).unwrap();
+        assert_eq!(sfx.frame_count, 20);
+        assert!(sfx.steps.is_empty());
+        assert_eq!(sfx.writes.len(), 2);
+    }
+
+    #[test]
+    fn decode_noise_sound_effect_data() {
+        // データ: (音量, 周期, 長さ, $F0|待ち) の繰り返しで、$FF で終わる。
+        let rom = rom_with_code(
+            0xEA,
+            &[
+                // RESET: JSR $9000; JSR $9100; JMP *
+                (
+                    0x8000,
+                    &[0x20, 0x00, 0x90, 0x20, 0x00, 0x91, 0x4C, 0x06, 0x80],
+                ),
+                // NMI: RTI
+                (0x8100, &[0x40]),
+                // trigger:
+                // ASL; TAX; LDA $A000,X; STA $10; LDA $A001,X; STA $11
+                // LDA #0; STA $12; LDA #$08; STA $4015; RTS
+                (
+                    0x9000,
+                    &[
+                        0x0A, 0xAA, 0xBD, 0x00, 0xA0, 0x85, 0x10, 0xBD, 0x01, 0xA0, 0x85, 0x11,
+                        0xA9, 0x00, 0x85, 0x12, 0xA9, 0x08, 0x8D, 0x15, 0x40, 0x60,
+                    ],
+                ),
+                // play:
+                // LDA $12; BEQ read; DEC $12; RTS
+                // read: LDY #0; LDA ($10),Y; CMP #$FF; BEQ end
+                // STA $400C; INY; LDA ($10),Y; STA $400E; INY; LDA ($10),Y; STA $400F
+                // INY; LDA ($10),Y; AND #$0F; STA $12
+                // INY; TYA; CLC; ADC $10; STA $10; RTS
+                // end: LDA #$30; STA $400C; RTS
+                (
+                    0x9100,
+                    &[
+                        0xA5, 0x12, 0xF0, 0x03, 0xC6, 0x12, 0x60, 0xA0, 0x00, 0xB1, 0x10, 0xC9,
+                        0xFF, 0xF0, 0x1E, 0x8D, 0x0C, 0x40, 0xC8, 0xB1, 0x10, 0x8D, 0x0E, 0x40,
+                        0xC8, 0xB1, 0x10, 0x8D, 0x0F, 0x40, 0xC8, 0xB1, 0x10, 0x29, 0x0F, 0x85,
+                        0x12, 0xC8, 0x98, 0x18, 0x65, 0x10, 0x85, 0x10, 0x60, 0xA9, 0x30, 0x8D,
+                        0x0C, 0x40, 0x60,
+                    ],
+                ),
+                (0xA000, &[0x00, 0xA1]),
+                (
+                    0xA100,
+                    &[0x3F, 0x05, 0x08, 0xF2, 0x38, 0x0A, 0x08, 0xF1, 0xFF],
+                ),
+                (0xFFFA, &[0x00, 0x81, 0x00, 0x80]),
+            ],
+        );
+        let disasm = Disassembly::new(&rom, &[], &[]);
+        let driver = SoundEffectDriver {
+            trigger: 0x9000,
+            play: 0x9100,
+            table: Some(0xA000),
+        };
+
+        let sfx = record_sound_effect(&rom, &disasm, driver, 0, 60).unwrap();
+        let steps: Vec<(u16, u32, SoundEffectCommand)> = sfx
+            .steps
+            .iter()
+            .map(|step| (step.addr, step.frame, step.command))
+            .collect();
+        use SoundEffectCommand::*;
+        assert_eq!(
+            steps,
+            [
+                (
+                    0xA100,
+                    0,
+                    SetVolume {
+                        channel: ApuChannel::Noise,
+                        duty: None,
+                        constant: true,
+                        volume: 15
+                    }
+                ),
+                (
+                    0xA101,
+                    0,
+                    SetNoisePeriod {
+                        short: false,
+                        index: 5
+                    }
+                ),
+                (0xA102, 0, KeyOnNoise { length_index: 1 }),
+                (0xA103, 0, Wait { frames: 3 }),
+                (
+                    0xA104,
+                    3,
+                    SetVolume {
+                        channel: ApuChannel::Noise,
+                        duty: None,
+                        constant: true,
+                        volume: 8
+                    }
+                ),
+                (
+                    0xA105,
+                    3,
+                    SetNoisePeriod {
+                        short: false,
+                        index: 10
+                    }
+                ),
+                (0xA106, 3, KeyOnNoise { length_index: 1 }),
+                (0xA107, 3, Wait { frames: 2 }),
+                (0xA108, 5, End),
+            ]
+        );
+        assert_eq!(sfx.channels, [ApuChannel::Noise]);
+        assert_eq!(sfx.frame_count, 5);
+    }
+}
@@ -0,0 +1,100 @@
+use std::io::Write;
+
+use crate::known_table::*;
+use crate::rom::*;
+
+/// デバッガ用のシンボル。len が 1 を超える場合は配列として扱う。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Symbol {
+    pub addr: u16,
+    pub len: usize,
+    pub name: String,
+    pub comment: String,
+}
+
+/// 既知テーブルからシンボルを生成する。
+///
+/// エントリサイズが既知のテーブルは `EnemyGroupAttr_01` のようにエントリごとのシンボルを生成する。
+/// (先頭エントリのシンボルにテーブル全体の説明を付ける)
+pub fn table_symbols(tables: &[KnownTable]) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+
+    for table in tables {
+        if table.entry_size == 0 {
+            symbols.push(Symbol {
+                addr: table.addr,
+                len: table.len,
+                name: table.name.clone(),
+                comment: format!("{} bytes", table.len),
+            });
+            continue;
+        }
+
+        let entry_count = table.len / table.entry_size;
+        for i in 0..entry_count {
+            let comment = if i == 0 {
+                format!(
+                    "{} ({} entries x {} bytes)",
+                    table.name, entry_count, table.entry_size
+                )
+            } else {
+                String::new()
+            };
+            symbols.push(Symbol {
+                addr: table.addr + (i * table.entry_size) as u16,
+                len: table.entry_size,
+                name: format!("{}_{:02X}", table.name, table.entry_id_base + i),
+                comment,
+            });
+        }
+    }
+
+    // 同一アドレスに複数のシンボルは付けられないので、先勝ちとする。
+    symbols.sort_by_key(|sym| sym.addr);
+    symbols.dedup_by_key(|sym| sym.addr);
+
+    symbols
+}
+
+/// FCEUX の .nl ファイルを出力する。
+/// bank は 16KB 単位の PRG バンク番号 (0: 0x8000-0xBFFF, 1: 0xC000-0xFFFF)。
+pub fn write_fceux_nl<W: Write>(mut wtr: W, symbols: &[Symbol], bank: u8) -> eyre::Result<()> {
+    let addr_min = 0x8000 + 0x4000 * u32::from(bank);
+    let addr_max = addr_min + 0x4000;
+
+    for sym in symbols
+        .iter()
+        .filter(|sym| (addr_min..addr_max).contains(&u32::from(sym.addr)))
+    {
+        if sym.len > 1 {
+            writeln!(
+                wtr,
+                "${:04X}/{:X}#{}#{}",
+                sym.addr, sym.len, sym.name, sym.comment
+            )?;
+        } else {
+            writeln!(wtr, "${:04X}#{}#{}", sym.addr, sym.name, sym.comment)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Mesen の .mlb ファイルを出力する。
+pub fn write_mesen_mlb<W: Write>(mut wtr: W, symbols: &[Symbol]) -> eyre::Result<()> {
+    for sym in symbols {
+        let offset = prg_offset(sym.addr);
+        if sym.len > 1 {
+            write!(wtr, "P:{:04X}-{:04X}", offset, offset + sym.len - 1)?;
+        } else {
+            write!(wtr, "P:{:04X}", offset)?;
+        }
+        if sym.comment.is_empty() {
+            writeln!(wtr, ":{}", sym.name)?;
+        } else {
+            writeln!(wtr, ":{}:{}", sym.name, sym.comment)?;
+        }
+    }
+
+    Ok(())
+}
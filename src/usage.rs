@@ -0,0 +1,125 @@
+// セル、タイル、パレット、メタスプライトの使用状況。
+//
+// カットされたデータや、改造時に使える空き領域を探すのに使う。
+
+use std::ops::Range;
+
+use crate::cdl::ranges_where;
+use crate::enemy_group::*;
+use crate::game::*;
+use crate::meta_sprite_animation::*;
+use crate::music::*;
+use crate::rom::*;
+
+#[derive(Clone, Debug)]
+pub struct UsageReport {
+    /// セル ID ごとの全ステージでの出現数。隠しセルの visual_id も数える。
+    pub cell_counts: Vec<u32>,
+    /// CHR 内タイル番号ごとの、使われているセル/メタスプライトからの参照数 (両周回分)。
+    /// メタスプライトが使われているかは meta_sprite_counts で判断する。
+    pub tile_counts: Vec<u32>,
+    /// 地形パレット ID ごとの、それを使うステージ前半/後半の数。
+    pub ground_palette_counts: Vec<u32>,
+    /// パレットセット内のパレット番号ごとの、地形上のセル出現数。
+    pub cell_palette_counts: [u32; 4],
+    /// スプライトパレット番号ごとの、メタスプライトのタイル数。
+    pub sprite_palette_counts: [u32; 4],
+    /// メタスプライト ID ごとの、それをフレームに含む敵グループとアニメーションの数。
+    /// 敵グループのメタスプライトは enemy_group_sprite_ids と同じく、アニメーションがあればそれを使う。
+    /// 自機や弾などアニメーションとして見つからないものは数えないので、あくまで目安。
+    pub meta_sprite_counts: Vec<u32>,
+    /// コードから見つかったアニメーションの数。探せなかった場合は None。
+    pub meta_sprite_animation_count: Option<usize>,
+    /// 読み込みで無視している無音の BGM (ID 10) の (sq1, sq2, tri) トラックのアドレス。
+    pub silent_music_ptrs: [u16; 3],
+}
+
+impl UsageReport {
+    pub fn unused_cells(&self) -> Vec<u8> {
+        unused_ids(&self.cell_counts)
+    }
+
+    pub fn unused_tile_ranges(&self) -> Vec<Range<usize>> {
+        ranges_where(self.tile_counts.len(), |i| self.tile_counts[i] == 0)
+    }
+
+    pub fn unused_meta_sprites(&self) -> Vec<u8> {
+        unused_ids(&self.meta_sprite_counts)
+    }
+
+    pub fn unused_ground_palettes(&self) -> Vec<u8> {
+        unused_ids(&self.ground_palette_counts)
+    }
+}
+
+fn unused_ids(counts: &[u32]) -> Vec<u8> {
+    (0..counts.len())
+        .filter(|&i| counts[i] == 0)
+        .map(|i| i as u8)
+        .collect()
+}
+
+pub fn analyze_usage(rom: &Rom, game: &Game) -> UsageReport {
+    let mut cell_counts = vec![0; usize::from(CELL_MAX) + 1];
+    let mut ground_palette_counts = vec![0; game.ground_palette_count()];
+    let mut cell_palette_counts = [0; 4];
+
+    for stage in 1..=16 {
+        let ground = game.ground(stage);
+        for (r, c) in itertools::iproduct!(0..=255, 0..20) {
+            let cell = ground.cell(r, c);
+            cell_counts[usize::from(cell)] += 1;
+            cell_palette_counts[usize::from(game.cell_palette_index(cell))] += 1;
+            if let Some(visual_id) = ground.hidden_visual_id(r, c) {
+                cell_counts[usize::from(visual_id)] += 1;
+            }
+        }
+
+        for half in 0..2 {
+            for &id in game.ground_config(stage, half).palette_ids() {
+                ground_palette_counts[usize::from(id)] += 1;
+            }
+        }
+    }
+
+    let mut sprite_palette_counts = [0; 4];
+    for id in 0..=META_SPRITE_MAX {
+        for idx in game.meta_sprite_palette_indices(id) {
+            sprite_palette_counts[usize::from(idx)] += 1;
+        }
+    }
+
+    let animations = locate_meta_sprite_animations(rom).ok();
+    let mut meta_sprite_counts = vec![0; usize::from(META_SPRITE_MAX) + 1];
+    for ids in enemy_group_sprite_ids(&load_enemy_groups(rom), animations.as_ref()) {
+        for id in ids {
+            meta_sprite_counts[usize::from(id)] += 1;
+        }
+    }
+    for anim in animations.iter().flat_map(|found| &found.animations) {
+        for id in anim.sprite_ids() {
+            meta_sprite_counts[usize::from(id)] += 1;
+        }
+    }
+
+    let used = |counts: &[u32]| -> Vec<u8> {
+        (0..counts.len())
+            .filter(|&i| counts[i] > 0)
+            .map(|i| i as u8)
+            .collect()
+    };
+    let tile_counts = game.tile_reference_counts(&used(&cell_counts), &used(&meta_sprite_counts));
+
+    let silent_music_ptrs = load_silent_music_ptrs(rom);
+
+    UsageReport {
+        cell_counts,
+        tile_counts,
+        ground_palette_counts,
+        cell_palette_counts,
+        sprite_palette_counts,
+        meta_sprite_counts,
+        meta_sprite_animation_count: animations.as_ref().map(|found| found.animations.len()),
+        silent_music_ptrs,
+    }
+}
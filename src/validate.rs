@@ -0,0 +1,319 @@
+// ゲーム自身のルーチンを CPU エミュレータで実行し、デコーダの結果と照合する。
+//
+// ルーチンのアドレスは逆アセンブル結果から探す。
+// 地形展開ルーチンは、行データの制御バイト (0xDB, 0xDC) と比較しているサブルーチンとし、
+// 行データは `LDA (zp),Y` で読み、`STA abs,X` などで RAM に書き込むものとする。
+// サウンドドライバは sound_driver.rs で探す。
+
+use eyre::{bail, ensure};
+
+use crate::cpu::*;
+use crate::disasm::*;
+use crate::game::*;
+use crate::known_table::*;
+use crate::music::*;
+use crate::opcode::*;
+use crate::rom::*;
+use crate::sound_driver::*;
+
+const MAX_STEPS: usize = 1_000_000;
+
+/// 地形 1 行分の展開ルーチン。
+/// src_ptr_zp に行データのアドレスを置いて entry を呼ぶと、dst に 20 バイト書き込まれるものとする。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct GroundRowRoutine {
+    pub entry: u16,
+    pub src_ptr_zp: u8,
+    pub dst: u16,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    pub checked: usize,
+    pub mismatches: Vec<String>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// 地形 1 行分の展開ルーチンを探す。
+pub fn locate_ground_row_routine(rom: &Rom) -> eyre::Result<GroundRowRoutine> {
+    let disasm = Disassembly::new(rom, &load_known_tables(rom), &[]);
+    locate_ground_row_routine_in(&disasm)
+}
+
+pub(crate) fn locate_ground_row_routine_in(disasm: &Disassembly) -> eyre::Result<GroundRowRoutine> {
+    let mut found = Vec::new();
+    for pc in disasm.code_addrs() {
+        let (opcode, operand) = disasm.instruction(pc).unwrap();
+        let is_ctrl_cmp = opcode.mnemonic == "CMP"
+            && opcode.mode == AddrMode::Immediate
+            && matches!(operand[0], 0xDB | 0xDC);
+        if !is_ctrl_cmp {
+            continue;
+        }
+        for entry in disasm.routines_containing(pc) {
+            if let Some(routine) = ground_row_routine_at(disasm, entry) {
+                if !found.contains(&routine) {
+                    found.push(routine);
+                }
+            }
+        }
+    }
+
+    match found.as_slice() {
+        [routine] => Ok(*routine),
+        [] => bail!("ground row routine not found"),
+        _ => bail!("ground row routine is ambiguous: {:04X?}", found),
+    }
+}
+
+/// entry から始まるルーチンの、行データポインタと出力先を調べる。
+fn ground_row_routine_at(disasm: &Disassembly, entry: u16) -> Option<GroundRowRoutine> {
+    let mut src_ptr_zp = None;
+    let mut dst = None;
+    for pc in disasm.routine_body(entry) {
+        let (opcode, operand) = disasm.instruction(pc).unwrap();
+        match (opcode.mnemonic, opcode.mode) {
+            ("LDA", AddrMode::IndirectY) => {
+                src_ptr_zp.get_or_insert(operand[0]);
+            }
+            ("STA", AddrMode::AbsoluteX) | ("STA", AddrMode::AbsoluteY) => {
+                let addr = u16::from_le_bytes([operand[0], operand[1]]);
+                if addr < 0x800 {
+                    dst.get_or_insert(addr);
+                }
+            }
+            ("STA", AddrMode::ZeroPageX) => {
+                dst.get_or_insert(u16::from(operand[0]));
+            }
+            _ => {}
+        }
+    }
+
+    Some(GroundRowRoutine {
+        entry,
+        src_ptr_zp: src_ptr_zp?,
+        dst: dst?,
+    })
+}
+
+/// 展開ルーチン実行後の RAM から 1 行分を取り出す。
+fn ground_row_in_ram(ram: &[u8], dst: u16) -> eyre::Result<&[u8]> {
+    let dst = usize::from(dst);
+    ensure!(
+        dst + 20 <= ram.len(),
+        "ground row destination {:#06X} is out of RAM",
+        dst
+    );
+    Ok(&ram[dst..dst + 20])
+}
+
+/// 全ステージの全行について、ゲームの展開ルーチンと load_ground_row() の結果を比較する。
+pub fn validate_ground_rows(
+    rom: &Rom,
+    routine: GroundRowRoutine,
+) -> eyre::Result<ValidationReport> {
+    let mut report = ValidationReport::default();
+
+    for addr in load_ground_row_addrs(rom) {
+        let mut bus = StubBus::new(&rom.prg);
+        let zp = usize::from(routine.src_ptr_zp);
+        bus.ram[zp] = addr as u8;
+        bus.ram[(zp + 1) & 0xFF] = (addr >> 8) as u8;
+
+        let mut cpu = Cpu::new();
+        cpu.call(&mut bus, routine.entry, MAX_STEPS)?;
+
+        let actual = ground_row_in_ram(&bus.ram, routine.dst)?;
+        let (expect, _) = load_ground_row(rom, addr);
+
+        report.checked += 1;
+        if actual != expect.as_slice() {
+            report.mismatches.push(format!(
+                "row {:#06X}: game={:02X?} decoder={:02X?}",
+                addr, actual, expect
+            ));
+        }
+    }
+
+    Ok(report)
+}
+
+/// 各曲についてサウンドドライバを実行し、発音タイミングと周期レジスタ値を load_musics() の結果と比較する。
+/// 周期レジスタ値はゲームの周期テーブルから引いた値と完全に一致しなければならない。
+pub fn validate_musics(
+    rom: &Rom,
+    driver: MusicDriver,
+    periods: &PeriodTable,
+) -> eyre::Result<ValidationReport> {
+    let mut report = ValidationReport::default();
+
+    for music in load_musics(rom) {
+        let expects: Vec<_> = [&music.track_sq1, &music.track_sq2, &music.track_tri]
+            .iter()
+            .enumerate()
+            .map(|(ch, track)| {
+                track_events(track)
+                    .into_iter()
+                    .filter_map(|ev| {
+                        ev.tone.map(|(octave, note)| {
+                            (ev.frame, periods.tone_period(octave, note, ch == 2))
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let n_frame = music.frame_count();
+
+        let actuals = run_music_driver(rom, driver, music.id, n_frame + 16)?;
+
+        // ドライバの処理遅延を sq1 の最初の発音で吸収する。
+        let delay = match (expects[0].first(), actuals[0].first()) {
+            (Some(&(fe, _)), Some(&(fa, _))) if fa >= fe => fa - fe,
+            _ => 0,
+        };
+
+        for (ch, (expect, actual)) in itertools::zip(&expects, &actuals).enumerate() {
+            for &(frame, period) in expect {
+                report.checked += 1;
+                match actual.iter().find(|&&(f, _)| f == frame + delay) {
+                    Some(&(_, period_actual)) => {
+                        if Some(period_actual) != period {
+                            report.mismatches.push(format!(
+                                "music {} ch{} frame {}: period game={} decoder={:?}",
+                                music.id, ch, frame, period_actual, period
+                            ));
+                        }
+                    }
+                    None => report.mismatches.push(format!(
+                        "music {} ch{} frame {}: no key-on in game",
+                        music.id, ch, frame
+                    )),
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// 各チャンネル (sq1, sq2, tri) の (キーオンしたフレーム, 周期レジスタ値) を返す。
+fn run_music_driver(
+    rom: &Rom,
+    driver: MusicDriver,
+    music_id: u8,
+    n_frame: u32,
+) -> eyre::Result<Vec<Vec<(u32, u16)>>> {
+    const REG_LO: [u16; 3] = [0x4002, 0x4006, 0x400A];
+    const REG_HI: [u16; 3] = [0x4003, 0x4007, 0x400B];
+
+    let mut bus = StubBus::new(&rom.prg);
+    let mut cpu = Cpu::new();
+
+    cpu.a = music_id - 1 + driver.id_base;
+    cpu.call(&mut bus, driver.init, MAX_STEPS)?;
+
+    let mut periods = [0u16; 3];
+    let mut onsets = vec![vec![]; 3];
+
+    for frame in 0..n_frame {
+        bus.apu_writes.clear();
+        cpu.call(&mut bus, driver.play, MAX_STEPS)?;
+
+        // 上位レジスタへの書き込みをキーオンとみなす。
+        // 周期はそのフレームの書き込みが全て終わった時点の値を使う。
+        let mut keyed = [false; 3];
+        for &(addr, value) in &bus.apu_writes {
+            for ch in 0..3 {
+                if addr == REG_LO[ch] {
+                    periods[ch] = (periods[ch] & 0x700) | u16::from(value);
+                } else if addr == REG_HI[ch] {
+                    periods[ch] = (periods[ch] & 0xFF) | (u16::from(value & 7) << 8);
+                    keyed[ch] = true;
+                }
+            }
+        }
+        for ch in 0..3 {
+            if keyed[ch] {
+                onsets[ch].push((frame, periods[ch]));
+            }
+        }
+    }
+
+    Ok(onsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 最小限の PRG を作る。code は (アドレス, バイト列) の組で、NMI/RESET ベクタも設定する。
+    fn rom_with_code(code: &[(u16, &[u8])], nmi: u16, reset: u16) -> Rom {
+        let mut prg = [0xEA; 0x8000]; // NOP で埋める
+        for &(addr, bytes) in code {
+            prg[prg_offset(addr)..][..bytes.len()].copy_from_slice(bytes);
+        }
+        prg[prg_offset(0xFFFA)..][..4].copy_from_slice(&[
+            nmi as u8,
+            (nmi >> 8) as u8,
+            reset as u8,
+            (reset >> 8) as u8,
+        ]);
+        Rom {
+            prg,
+            chr: [0; 0x8000],
+        }
+    }
+
+    #[test]
+    fn ground_row_out_of_ram_is_error() {
+        let ram = vec![0; 0x800];
+        assert!(ground_row_in_ram(&ram, 0x7EC).is_ok());
+        assert!(ground_row_in_ram(&ram, 0x7ED).is_err());
+        assert!(ground_row_in_ram(&ram, 0xFFFF).is_err());
+    }
+
+    #[test]
+    fn locate_ground_row_routine_from_code() {
+        // RESET: JSR $9000; JMP *
+        // $9000: LDY #0; loop: LDA ($10),Y; CMP #$DC; STA $0300,Y; INY; CPY #20; BNE loop; RTS
+        let routine: &[u8] = &[
+            0xA0, 0x00, 0xB1, 0x10, 0xC9, 0xDC, 0x99, 0x00, 0x03, 0xC8, 0xC0, 0x14, 0xD0, 0xF4,
+            0x60,
+        ];
+        let rom = rom_with_code(
+            &[
+                (0x8000, &[0x20, 0x00, 0x90, 0x4C, 0x03, 0x80]),
+                (0x9000, routine),
+                (0x8100, &[0x40]),
+            ],
+            0x8100,
+            0x8000,
+        );
+
+        let disasm = Disassembly::new(&rom, &[], &[]);
+        let found = locate_ground_row_routine_in(&disasm).unwrap();
+        assert_eq!(
+            found,
+            GroundRowRoutine {
+                entry: 0x9000,
+                src_ptr_zp: 0x10,
+                dst: 0x0300,
+            }
+        );
+
+        // 見つけたルーチンをそのまま実行できること。
+        let mut bus = StubBus::new(&rom.prg);
+        bus.ram[0x10] = 0x00;
+        bus.ram[0x11] = 0xA0;
+        Cpu::new().call(&mut bus, found.entry, MAX_STEPS).unwrap();
+        assert_eq!(
+            ground_row_in_ram(&bus.ram, found.dst).unwrap(),
+            &rom.prg[0x2000..0x2014]
+        );
+    }
+}
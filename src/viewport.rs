@@ -0,0 +1,253 @@
+// 横スクロールの表示範囲。
+//
+// 地形は 20 セル (320px) 幅だが、画面は 256px しかないので、自機の X 座標に応じて横スクロールする。
+// スクロール量は、ゲームのスクロール処理を自機の X 座標 0..=255 それぞれについて実行して求める。
+// スクロール処理は次の手順で探す。
+//
+// 1. PPUSCROLL への最初の書き込みの直前で、X スクロール値を読む RAM 変数を調べる。
+// 2. その変数に書き込むサブルーチンのうち、読んでいる RAM 変数の 1 つを 0..=255 と変えると
+//    結果が単調に増えるものをスクロール処理、その変数を自機の X 座標とする。
+//
+// スクロールの座標は画面 (ネームテーブル) 上のもの。
+// Ground はローテート済みの画面上の並びなので、列はそのまま画面の列に対応する。
+
+use std::ops::Range;
+
+use eyre::eyre;
+
+use crate::cpu::*;
+use crate::disasm::*;
+use crate::game::*;
+use crate::known_table::*;
+use crate::opcode::*;
+use crate::rom::*;
+
+pub const GROUND_WIDTH: u32 = 16 * 20;
+pub const SCREEN_WIDTH: u32 = 256;
+
+const ADDR_PPUSCROLL: u16 = 0x2005;
+
+/// スクロール処理 1 回分の実行命令数の上限。
+const MAX_STEPS: usize = 100_000;
+
+/// PPUSCROLL への 2 回の書き込み (X, Y) が離れうるバイト数の上限。
+const PPUSCROLL_PAIR_DISTANCE_MAX: u16 = 16;
+
+/// スクロール処理の位置。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ScrollRoutine {
+    pub entry: u16,
+    /// 自機の X 座標を持つ RAM 変数のアドレス。
+    pub player_x: u16,
+    /// X スクロール値を持つ RAM 変数のアドレス。
+    pub scroll_x: u16,
+}
+
+/// 自機の X 座標からスクロール量への対応。
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ScrollModel {
+    pub routine: ScrollRoutine,
+    /// 自機の X 座標 0..=255 に対するスクロール量。
+    scroll_xs: Vec<u8>,
+}
+
+impl ScrollModel {
+    /// ROM からスクロール処理を探し、自機の X 座標ごとのスクロール量を求める。
+    pub fn from_rom(rom: &Rom) -> eyre::Result<Self> {
+        let tables = load_known_tables(rom);
+        let disasm = Disassembly::new(rom, &tables, &[]);
+
+        Self::from_disassembly(rom, &disasm)
+    }
+
+    fn from_disassembly(rom: &Rom, disasm: &Disassembly) -> eyre::Result<Self> {
+        let scroll_x =
+            locate_scroll_x_var(disasm).ok_or_else(|| eyre!("X scroll variable not found"))?;
+
+        let routines: Vec<u16> = disasm
+            .code_addrs()
+            .filter(|&pc| {
+                let (opcode, operand) = disasm.instruction(pc).unwrap();
+                opcode.mnemonic == "STA" && ram_addr(opcode, operand) == Some(scroll_x)
+            })
+            .flat_map(|pc| disasm.routines_containing(pc))
+            .collect();
+
+        for entry in routines {
+            let inputs: Vec<u16> = disasm
+                .routine_body(entry)
+                .into_iter()
+                .filter_map(|pc| {
+                    let (opcode, operand) = disasm.instruction(pc)?;
+                    if opcode.mnemonic.starts_with("ST") {
+                        return None;
+                    }
+                    ram_addr(opcode, operand).filter(|&addr| addr != scroll_x)
+                })
+                .collect();
+
+            for player_x in inputs {
+                let routine = ScrollRoutine {
+                    entry,
+                    player_x,
+                    scroll_x,
+                };
+                let scroll_xs = match run_scroll_routine(rom, routine) {
+                    Ok(scroll_xs) => scroll_xs,
+                    Err(_) => continue,
+                };
+                let monotonic = scroll_xs.windows(2).all(|w| w[0] <= w[1]);
+                if monotonic && scroll_xs.first() != scroll_xs.last() {
+                    return Ok(Self { routine, scroll_xs });
+                }
+            }
+        }
+
+        Err(eyre!("scroll routine writing ${:04X} not found", scroll_x))
+    }
+
+    /// 自機の X 座標に対するスクロール量 (画面左端の X 座標)。
+    pub fn scroll_x(&self, player_x: u8) -> u32 {
+        u32::from(self.scroll_xs[usize::from(player_x)])
+    }
+
+    /// 自機の X 座標に対して画面に映る X 座標範囲。
+    pub fn visible_x_range(&self, player_x: u8) -> Range<u32> {
+        let scroll_x = self.scroll_x(player_x);
+        scroll_x..(scroll_x + SCREEN_WIDTH).min(GROUND_WIDTH)
+    }
+
+    /// 自機の X 座標に対して、一部でも画面に映るセルの列範囲。
+    pub fn visible_column_range(&self, player_x: u8) -> Range<u8> {
+        x_range_to_columns(self.visible_x_range(player_x))
+    }
+
+    /// いずれかのスクロール位置で画面に映る X 座標範囲。
+    /// 自機の X 座標の可動範囲は考慮せず、0..=255 の全てを対象にする。
+    pub fn reachable_x_range(&self) -> Range<u32> {
+        self.visible_x_range(0).start..self.visible_x_range(255).end
+    }
+
+    /// どのスクロール位置でも画面に映らないセルの列。
+    pub fn unreachable_columns(&self) -> Vec<u8> {
+        let reachable = x_range_to_columns(self.reachable_x_range());
+        (0..20).filter(|c| !reachable.contains(c)).collect()
+    }
+
+    /// どのスクロール位置でも常に画面に映る X 座標範囲。
+    pub fn always_visible_x_range(&self) -> Range<u32> {
+        self.visible_x_range(255).start..self.visible_x_range(0).end
+    }
+}
+
+/// PPUSCROLL への X の書き込みの直前に読んでいる RAM 変数を返す。
+fn locate_scroll_x_var(disasm: &Disassembly) -> Option<u16> {
+    let writes: Vec<u16> = disasm
+        .code_addrs()
+        .filter(|&pc| {
+            let (opcode, operand) = disasm.instruction(pc).unwrap();
+            opcode.mnemonic == "STA"
+                && opcode.mode == AddrMode::Absolute
+                && u16::from_le_bytes([operand[0], operand[1]]) == ADDR_PPUSCROLL
+        })
+        .collect();
+
+    // X, Y の組のうち先の書き込みを調べる。
+    writes
+        .windows(2)
+        .filter(|w| w[1] - w[0] <= PPUSCROLL_PAIR_DISTANCE_MAX)
+        .find_map(|w| {
+            let (opcode, operand) = disasm.instruction(disasm.previous_instruction(w[0])?)?;
+            if opcode.mnemonic != "LDA" {
+                return None;
+            }
+            ram_addr(opcode, operand)
+        })
+}
+
+/// 直接アドレスで RAM を指すオペランドならそのアドレスを返す。
+fn ram_addr(opcode: Opcode, operand: &[u8]) -> Option<u16> {
+    let addr = match opcode.mode {
+        AddrMode::ZeroPage => u16::from(operand[0]),
+        AddrMode::Absolute => u16::from_le_bytes([operand[0], operand[1]]),
+        _ => return None,
+    };
+    (addr < 0x800).then_some(addr)
+}
+
+/// 自機の X 座標 0..=255 それぞれについてスクロール処理を実行し、スクロール量を返す。
+fn run_scroll_routine(rom: &Rom, routine: ScrollRoutine) -> eyre::Result<Vec<u8>> {
+    (0..=255)
+        .map(|player_x| {
+            let mut bus = StubBus::new(&rom.prg);
+            bus.ram[usize::from(routine.player_x)] = player_x;
+            Cpu::new().call(&mut bus, routine.entry, MAX_STEPS)?;
+            Ok(bus.ram[usize::from(routine.scroll_x)])
+        })
+        .collect()
+}
+
+fn x_range_to_columns(range: Range<u32>) -> Range<u8> {
+    (range.start / 16) as u8..range.end.div_ceil(16).min(20) as u8
+}
+
+impl Ground {
+    /// 行 r のうち、自機の X 座標が player_x のとき画面に映るセル (画面の左から順)。
+    pub fn visible_row_cells(&self, r: u8, model: &ScrollModel, player_x: u8) -> Vec<u8> {
+        model
+            .visible_column_range(player_x)
+            .map(|c| self.cell(r, c))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locate_scroll_routine_from_code() {
+        let mut prg = [0xEA; 0x8000]; // NOP で埋める
+        let code: &[(u16, &[u8])] = &[
+            // RESET: JSR $9000; JMP *
+            (0x8000, &[0x20, 0x00, 0x90, 0x4C, 0x03, 0x80]),
+            // NMI: LDA $30; STA $2005; LDA #0; STA $2005; RTI
+            (
+                0x8100,
+                &[
+                    0xA5, 0x30, 0x8D, 0x05, 0x20, 0xA9, 0x00, 0x8D, 0x05, 0x20, 0x40,
+                ],
+            ),
+            // LDA $10; STA $11; LDA $40; LSR; LSR; STA $30; RTS
+            (
+                0x9000,
+                &[
+                    0xA5, 0x10, 0x85, 0x11, 0xA5, 0x40, 0x4A, 0x4A, 0x85, 0x30, 0x60,
+                ],
+            ),
+            (0xFFFA, &[0x00, 0x81, 0x00, 0x80]),
+        ];
+        for &(addr, bytes) in code {
+            prg[prg_offset(addr)..][..bytes.len()].copy_from_slice(bytes);
+        }
+        let rom = Rom {
+            prg,
+            chr: [0; 0x8000],
+        };
+        let disasm = Disassembly::new(&rom, &[], &[]);
+
+        let model = ScrollModel::from_disassembly(&rom, &disasm).unwrap();
+        assert_eq!(
+            model.routine,
+            ScrollRoutine {
+                entry: 0x9000,
+                player_x: 0x40,
+                scroll_x: 0x30,
+            }
+        );
+        assert_eq!(model.scroll_x(0), 0);
+        assert_eq!(model.scroll_x(255), 63);
+        assert_eq!(model.reachable_x_range(), 0..63 + SCREEN_WIDTH);
+        assert_eq!(model.always_visible_x_range(), 63..SCREEN_WIDTH);
+    }
+}